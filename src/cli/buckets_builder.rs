@@ -1,3 +1,4 @@
+use crate::client::Durability;
 use serde_derive::Deserialize;
 use std::convert::TryFrom;
 use std::fmt;
@@ -45,8 +46,10 @@ impl TryFrom<&str> for DurabilityLevel {
         match alias {
             "none" => Ok(DurabilityLevel::None),
             "majority" => Ok(DurabilityLevel::Majority),
-            "majorityAndPersistActive" => Ok(DurabilityLevel::MajorityAndPersistOnMaster),
-            "persistToMajority" => Ok(DurabilityLevel::PersistToMajority),
+            "majorityAndPersistActive" | "majority-and-persist-active" => {
+                Ok(DurabilityLevel::MajorityAndPersistOnMaster)
+            }
+            "persistToMajority" | "persist-to-majority" => Ok(DurabilityLevel::PersistToMajority),
             _ => Err(BuilderError {
                 message: "invalid durability level".to_string(),
             }),
@@ -54,6 +57,17 @@ impl TryFrom<&str> for DurabilityLevel {
     }
 }
 
+impl From<DurabilityLevel> for Durability {
+    fn from(level: DurabilityLevel) -> Self {
+        match level {
+            DurabilityLevel::None => Durability::None,
+            DurabilityLevel::Majority => Durability::Majority,
+            DurabilityLevel::MajorityAndPersistOnMaster => Durability::MajorityAndPersistActive,
+            DurabilityLevel::PersistToMajority => Durability::PersistToMajority,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum BucketType {
     Couchbase,
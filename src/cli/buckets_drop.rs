@@ -103,6 +103,8 @@ fn buckets_drop(
         } else {
             drop_server_bucket(active_cluster, name.clone(), signals.clone(), span)
         }?;
+
+        active_cluster.invalidate_key_value_client(&name);
     }
 
     Ok(PipelineData::empty())
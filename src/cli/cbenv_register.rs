@@ -1,5 +1,6 @@
 use crate::cli::error::generic_error;
 use crate::cli::util::{get_username_and_password, read_config_file, update_config_file};
+use crate::client::RetryPolicy;
 use crate::config::{ClusterConfig, DEFAULT_KV_BATCH_SIZE};
 use crate::state::State;
 use crate::{
@@ -188,6 +189,9 @@ fn clusters_register(
         project,
         DEFAULT_KV_BATCH_SIZE,
         RemoteClusterType::from(hostnames),
+        None,
+        None,
+        RetryPolicy::default(),
     );
 
     let mut guard = state.lock().unwrap();
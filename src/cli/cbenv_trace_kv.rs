@@ -0,0 +1,143 @@
+use crate::cli::error::generic_error;
+use crate::cli::util::NuValueMap;
+use crate::client::{
+    kv_trace_settings, start_kv_trace, stop_kv_trace, KvTraceFormat, KvTraceSettings,
+};
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, SyntaxShape,
+    Value,
+};
+use std::path::PathBuf;
+
+#[derive(Clone)]
+pub struct CbEnvTraceKv;
+
+impl Command for CbEnvTraceKv {
+    fn name(&self) -> &str {
+        "cb-env trace-kv"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("cb-env trace-kv")
+            .named(
+                "file",
+                SyntaxShape::Filepath,
+                "the file to write every data service frame to",
+                None,
+            )
+            .switch(
+                "pcap",
+                "write the trace as a pcap which can be opened with Wireshark",
+                None,
+            )
+            .switch(
+                "no-redact",
+                "include document values in the trace, authentication values are always redacted",
+                None,
+            )
+            .switch("off", "stop the running trace", None)
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Traces the frames sent to and received from the data service to a file"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let span = call.head;
+
+        let file: Option<String> = call.get_flag(engine_state, stack, "file")?;
+        let off = call.has_flag(engine_state, stack, "off")?;
+
+        let settings = match (file, off) {
+            (Some(_), true) => {
+                return Err(generic_error(
+                    "Invalid flags",
+                    "The file flag cannot be used with the off flag".to_string(),
+                    span,
+                ));
+            }
+            (Some(file), false) => {
+                let format = if call.has_flag(engine_state, stack, "pcap")? {
+                    KvTraceFormat::Pcap
+                } else {
+                    KvTraceFormat::Text
+                };
+                let settings = KvTraceSettings {
+                    path: PathBuf::from(file),
+                    format,
+                    redact: !call.has_flag(engine_state, stack, "no-redact")?,
+                };
+                start_kv_trace(settings.clone()).map_err(|e| {
+                    generic_error(
+                        format!("Failed to create trace file {}", settings.path.display()),
+                        e.to_string(),
+                        span,
+                    )
+                })?;
+                Some(settings)
+            }
+            (None, true) => {
+                stop_kv_trace();
+                None
+            }
+            (None, false) => kv_trace_settings(),
+        };
+
+        Ok(settings_to_value(settings, span).into_pipeline_data())
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Trace data service frames to a file, with document values redacted",
+                example: "cb-env trace-kv --file kv.trace",
+                result: None,
+            },
+            Example {
+                description: "Trace data service frames to a pcap for Wireshark",
+                example: "cb-env trace-kv --file kv.pcap --pcap",
+                result: None,
+            },
+            Example {
+                description: "Stop tracing",
+                example: "cb-env trace-kv --off",
+                result: None,
+            },
+        ]
+    }
+}
+
+fn settings_to_value(settings: Option<KvTraceSettings>, span: Span) -> Value {
+    let mut collected = NuValueMap::default();
+    collected.add_bool("enabled", settings.is_some(), span);
+    match settings {
+        Some(s) => {
+            collected.add_string("file", s.path.display().to_string(), span);
+            collected.add_string(
+                "format",
+                match s.format {
+                    KvTraceFormat::Text => "text",
+                    KvTraceFormat::Pcap => "pcap",
+                },
+                span,
+            );
+            collected.add_bool("redacted", s.redact, span);
+        }
+        None => {
+            collected.add("file", Value::nothing(span));
+            collected.add("format", Value::nothing(span));
+            collected.add("redacted", Value::nothing(span));
+        }
+    }
+    collected.into_value(span)
+}
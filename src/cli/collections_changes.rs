@@ -0,0 +1,89 @@
+//! The `collections changes` command streams every change made to a collection through DCP.
+
+use crate::cli::doc_watch::run_watch;
+use crate::state::State;
+use std::sync::{Arc, Mutex};
+
+use nu_engine::command_prelude::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape};
+
+#[derive(Clone)]
+pub struct CollectionsChanges {
+    state: Arc<Mutex<State>>,
+}
+
+impl CollectionsChanges {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for CollectionsChanges {
+    fn name(&self) -> &str {
+        "collections changes"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("collections changes")
+            .switch(
+                "from-beginning",
+                "show every change still held by the server rather than only new ones",
+                None,
+            )
+            .switch(
+                "with-content",
+                "include the content of mutated documents",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Streams the changes made to a collection as they happen"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_watch(self.state.clone(), engine_state, stack, call, input)
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Shows changes to the active collection as they happen",
+                example: "collections changes",
+                result: None,
+            },
+            Example {
+                description: "Shows every change to the airline collection still held by the server, then new changes",
+                example: "collections changes --collection airline --from-beginning",
+                result: None,
+            },
+        ]
+    }
+}
@@ -0,0 +1,157 @@
+//! The `collections manifest` command fetches the collections manifest through the data service.
+
+use crate::cli::util::{cluster_identifiers_from, get_active_cluster, NuValueMap};
+use crate::state::State;
+use log::debug;
+use std::ops::Add;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+use tokio::time::Instant;
+
+use crate::cli::collections::get_bucket_or_active;
+use crate::cli::error::client_error_to_shell_error;
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct CollectionsManifest {
+    state: Arc<Mutex<State>>,
+}
+
+impl CollectionsManifest {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for CollectionsManifest {
+    fn name(&self) -> &str {
+        "collections manifest"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("collections manifest")
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named(
+                "scope",
+                SyntaxShape::String,
+                "only show the collections in this scope",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters to query against",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Fetches the collections manifest through the data service"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        collections_manifest(self.state.clone(), engine_state, stack, call, input)
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Shows the ids of every collection in the active bucket",
+                example: "collections manifest",
+                result: None,
+            },
+            Example {
+                description: "Shows the ids of the collections in the inventory scope",
+                example: "collections manifest --bucket travel-sample --scope inventory",
+                result: None,
+            },
+        ]
+    }
+}
+
+fn collections_manifest(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    _input: PipelineData,
+) -> Result<PipelineData, ShellError> {
+    let span = call.head;
+    let signals = engine_state.signals().clone();
+
+    let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
+    let scope: Option<String> = call.get_flag(engine_state, stack, "scope")?;
+    let guard = state.lock().unwrap();
+
+    let rt = Runtime::new().unwrap();
+    let mut results: Vec<Value> = vec![];
+    for identifier in cluster_identifiers {
+        let active_cluster = get_active_cluster(identifier.clone(), &guard, span)?;
+        let bucket = get_bucket_or_active(active_cluster, engine_state, stack, call)?;
+
+        debug!("Running collections manifest for bucket {:?}", &bucket);
+
+        let deadline = Instant::now().add(active_cluster.timeouts().data_timeout());
+        let manifest = rt
+            .block_on(async {
+                let client = active_cluster
+                    .key_value_client(bucket.clone(), deadline, signals.clone())
+                    .await?;
+                client
+                    .get_collections_manifest(deadline, signals.clone())
+                    .await
+            })
+            .map_err(|e| client_error_to_shell_error(e, span))?;
+
+        for manifest_scope in &manifest.scopes {
+            if let Some(scope) = &scope {
+                if &manifest_scope.name != scope {
+                    continue;
+                }
+            }
+
+            for collection in &manifest_scope.collections {
+                let mut collected = NuValueMap::default();
+                collected.add_string("manifest_uid", format!("{:x}", manifest.uid), span);
+                collected.add_string("scope", manifest_scope.name.clone(), span);
+                collected.add_i64("scope_id", manifest_scope.uid as i64, span);
+                collected.add_string("collection", collection.name.clone(), span);
+                collected.add_i64("collection_id", collection.uid as i64, span);
+                // Collections without a max TTL of their own inherit the bucket's.
+                collected.add(
+                    "max_ttl",
+                    match collection.max_ttl {
+                        Some(ttl) => Value::int(ttl, span),
+                        None => Value::nothing(span),
+                    },
+                );
+                collected.add_bool("history", collection.history, span);
+                collected.add_string("cluster", identifier.clone(), span);
+                results.push(collected.into_value(span));
+            }
+        }
+    }
+
+    Ok(Value::List {
+        vals: results,
+        internal_span: span,
+    }
+    .into_pipeline_data())
+}
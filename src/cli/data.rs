@@ -1,30 +1,35 @@
-use async_trait::async_trait;
-use nu_cli::OutputStream;
-use nu_engine::CommandArgs;
-use nu_errors::ShellError;
-use nu_protocol::{ReturnSuccess, Signature, UntaggedValue};
-use nu_source::Tag;
+use nu_engine::command_prelude::Call;
+use nu_engine::get_full_help;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, IntoPipelineData, PipelineData, ShellError, Signature, Value};
 
+#[derive(Clone)]
 pub struct Data;
 
-#[async_trait]
-impl nu_engine::WholeStreamCommand for Data {
+impl Command for Data {
     fn name(&self) -> &str {
         "data"
     }
 
     fn signature(&self) -> Signature {
-        Signature::build("data")
+        Signature::build("data").category(Category::Custom("couchbase".to_string()))
     }
 
-    fn usage(&self) -> &str {
+    fn description(&self) -> &str {
         "Performs operations against the data service"
     }
 
-    async fn run(&self, args: CommandArgs) -> Result<OutputStream, ShellError> {
-        Ok(OutputStream::one(ReturnSuccess::value(
-            UntaggedValue::string(nu_engine::get_help(&Data, &args.scope))
-                .into_value(Tag::unknown()),
-        )))
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        Ok(Value::String {
+            val: get_full_help(&Data, engine_state, stack),
+            internal_span: call.head,
+        }
+        .into_pipeline_data())
     }
 }
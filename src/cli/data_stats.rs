@@ -1,17 +1,26 @@
-use crate::cli::convert_cb_error;
-use crate::cli::util::cluster_identifiers_from;
+//! The `data stats` command fetches stats from the data service on every node.
+
+use super::util::convert_json_value_to_nu_value;
+use crate::cli::collections::get_bucket_or_active;
+use crate::cli::doc_common::get_active_cluster_client_cid;
+use crate::cli::error::{client_error_to_shell_error, generic_error};
+use crate::cli::util::{cluster_identifiers_from, get_active_cluster, NuValueMap};
 use crate::state::State;
-use async_trait::async_trait;
-use couchbase::{KvStatsRequest, Request};
-use futures::channel::oneshot;
-use futures::stream::StreamExt;
-use nu_cli::OutputStream;
-use nu_engine::CommandArgs;
-use nu_errors::ShellError;
-use nu_protocol::{Signature, SyntaxShape, TaggedDictBuilder};
-use nu_source::Tag;
+use log::debug;
+use std::ops::Add;
 use std::sync::{Arc, Mutex};
-
+use tokio::runtime::Runtime;
+use tokio::time::Instant;
+
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, Span, SyntaxShape,
+    Value,
+};
+
+#[derive(Clone)]
 pub struct DataStats {
     state: Arc<Mutex<State>>,
 }
@@ -22,90 +31,197 @@ impl DataStats {
     }
 }
 
-#[async_trait]
-impl nu_engine::WholeStreamCommand for DataStats {
+impl Command for DataStats {
     fn name(&self) -> &str {
         "data stats"
     }
 
     fn signature(&self) -> Signature {
         Signature::build("data stats")
+            .optional(
+                "group",
+                SyntaxShape::String,
+                "the group of stats to fetch, such as memory, timings, dcp, collections or key, defaults to the default group",
+            )
             .named(
-                "clusters",
+                "id",
                 SyntaxShape::String,
-                "the clusters which should be contacted",
+                "the document id to fetch stats for with the key group",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
                 None,
             )
             .named(
-                "key",
+                "clusters",
                 SyntaxShape::String,
-                "the custom stats key that should be passed down",
+                "the clusters which should be contacted",
                 None,
             )
+            .category(Category::Custom("couchbase".to_string()))
     }
 
-    fn usage(&self) -> &str {
-        "Loads Key/Value statistics from the cluster"
+    fn description(&self) -> &str {
+        "Fetches stats from the data service on every node"
     }
 
-    async fn run(&self, args: CommandArgs) -> Result<OutputStream, ShellError> {
-        run_stats(self.state.clone(), args).await
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_stats(self.state.clone(), engine_state, stack, call, input)
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Fetches the default stats of the active bucket from every node",
+                example: "data stats",
+                result: None,
+            },
+            Example {
+                description: "Shows how much memory the bucket is using on each node",
+                example: "data stats memory | where stat == mem_used",
+                result: None,
+            },
+            Example {
+                description: "Fetches the stats of a single document",
+                example: "data stats key --id airline_10",
+                result: None,
+            },
+        ]
     }
 }
 
-async fn run_stats(
+fn run_stats(
     state: Arc<Mutex<State>>,
-    args: CommandArgs,
-) -> Result<OutputStream, ShellError> {
-    let args = args.evaluate_once().await?;
-
-    let cluster_identifiers = cluster_identifiers_from(&state, &args, true)?;
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    _input: PipelineData,
+) -> Result<PipelineData, ShellError> {
+    let span = call.head;
+    let signals = engine_state.signals().clone();
+
+    let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
+
+    // The server fetches the default group when no group is given.
+    let group = match call.opt::<String>(engine_state, stack, 0)? {
+        Some(g) if g == "default" => String::new(),
+        Some(g) => g,
+        None => String::new(),
+    };
+    let id: Option<String> = call.get_flag(engine_state, stack, "id")?;
+    match (group.as_str(), &id) {
+        ("key", None) => {
+            return Err(generic_error(
+                "Missing document id",
+                "The key group needs the id of the document to fetch stats for, use --id"
+                    .to_string(),
+                span,
+            ));
+        }
+        ("key", Some(_)) => {}
+        (_, Some(_)) => {
+            return Err(generic_error(
+                "Invalid flags",
+                "The id flag can only be used with the key group".to_string(),
+                span,
+            ));
+        }
+        _ => {}
+    }
 
-    let key = args.get("key").map(|id| id.as_string().ok()).flatten();
+    let bucket_flag = call.get_flag(engine_state, stack, "bucket")?;
+    let scope_flag = call.get_flag(engine_state, stack, "scope")?;
+    let collection_flag = call.get_flag(engine_state, stack, "collection")?;
 
-    let mut stats = vec![];
+    let guard = state.lock().unwrap();
 
+    let rt = Runtime::new().unwrap();
+    let mut results = vec![];
     for identifier in cluster_identifiers {
-        let core = match state.lock().unwrap().clusters().get(&identifier) {
-            Some(c) => c.cluster().core(),
-            None => {
-                return Err(ShellError::unexpected("Cluster not found"));
-            }
+        let node_stats = if let Some(id) = &id {
+            let (active_cluster, client, cid) = get_active_cluster_client_cid(
+                &rt,
+                identifier.clone(),
+                &guard,
+                bucket_flag.clone(),
+                scope_flag.clone(),
+                collection_flag.clone(),
+                signals.clone(),
+                span,
+            )?;
+
+            debug!("Fetching key stats for {}", id);
+
+            let deadline = Instant::now().add(active_cluster.timeouts().data_timeout());
+            vec![rt.block_on(client.key_stats(id.clone(), cid, deadline, signals.clone()))]
+        } else {
+            let active_cluster = get_active_cluster(identifier.clone(), &guard, span)?;
+            let bucket = get_bucket_or_active(active_cluster, engine_state, stack, call)?;
+
+            debug!("Fetching {:?} stats for bucket {}", &group, &bucket);
+
+            let deadline = Instant::now().add(active_cluster.timeouts().data_timeout());
+            rt.block_on(async {
+                let client = active_cluster
+                    .key_value_client(bucket.clone(), deadline, signals.clone())
+                    .await?;
+                Ok(client.stats(group.clone(), deadline, signals.clone()).await)
+            })
+            .map_err(|e| client_error_to_shell_error(e, span))?
         };
 
-        let (sender, receiver) = oneshot::channel();
-        let request = KvStatsRequest::new(sender, key.clone());
-        core.send(Request::KvStatsRequest(request));
-
-        let input = match receiver.await {
-            Ok(i) => i,
-            Err(e) => {
-                return Err(ShellError::unexpected(format!(
-                    "Error streaming result {}",
-                    e
-                )))
+        for (node, stats) in node_stats {
+            let stats = stats.map_err(|e| client_error_to_shell_error(e, span))?;
+            for (stat, value) in stats {
+                let mut collected = NuValueMap::default();
+                collected.add_string("node", node.clone(), span);
+                collected.add_string("stat", stat, span);
+                collected.add("value", convert_stat_value(value, span));
+                collected.add_string("cluster", identifier.clone(), span);
+                results.push(collected.into_value(span));
             }
-        };
-        let mut result = convert_cb_error(input)?;
-        let mut s = result
-            .stats()
-            .map(|stat| {
-                let mut collected = TaggedDictBuilder::new(Tag::default());
-                collected.insert_value("cluster", identifier.clone());
-
-                if let Some(node) = stat.server().split(':').nth(0) {
-                    collected.insert_value("node", String::from(node));
-                }
-
-                collected.insert_value("key", String::from(stat.key()));
-                collected.insert_value("value", String::from(stat.value()));
-                collected.into_value()
-            })
-            .collect::<Vec<_>>()
-            .await;
+        }
+    }
+
+    Ok(Value::List {
+        vals: results,
+        internal_span: span,
+    }
+    .into_pipeline_data())
+}
 
-        stats.append(&mut s);
+// convert_stat_value converts a stat, which the server always sends as a string, into a number
+// where it is one. Some stats, such as timings histograms, are JSON.
+fn convert_stat_value(value: String, span: Span) -> Value {
+    if let Ok(v) = value.parse::<i64>() {
+        return Value::int(v, span);
+    }
+    if let Ok(v) = value.parse::<f64>() {
+        return Value::float(v, span);
+    }
+    if value.starts_with('{') || value.starts_with('[') {
+        if let Ok(json) = serde_json::from_str(&value) {
+            if let Ok(v) = convert_json_value_to_nu_value(&json, span) {
+                return v;
+            }
+        }
     }
 
-    Ok(stats.into())
+    Value::string(value, span)
 }
@@ -0,0 +1,143 @@
+//! The `doc append` command performs a KV append operation.
+
+use crate::cli::doc_common::run_kv_concat_ops;
+use crate::client::Durability;
+use crate::client::KeyValueRequest;
+use crate::state::State;
+use nu_engine::command_prelude::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct DocAppend {
+    state: Arc<Mutex<State>>,
+}
+
+impl DocAppend {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for DocAppend {
+    fn name(&self) -> &str {
+        "doc append"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("doc append")
+            .optional("id", SyntaxShape::String, "the document id")
+            .optional(
+                "content",
+                SyntaxShape::Any,
+                "the raw value to append, a string, int or binary",
+            )
+            .named(
+                "id-column",
+                SyntaxShape::String,
+                "the name of the id column if used with an input stream",
+                None,
+            )
+            .named(
+                "content-column",
+                SyntaxShape::String,
+                "the name of the content column if used with an input stream",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .named(
+                "batch-size",
+                SyntaxShape::Number,
+                "the maximum number of items to batch send at a time",
+                None,
+            )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
+            .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Appends a raw value to the end of an existing document through the data service"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_kv_concat_ops(
+            self.state.clone(),
+            engine_state,
+            stack,
+            call,
+            input,
+            build_req,
+        )
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Appends a line to the raw document with the ID log",
+                example: r#"doc append log "started\n""#,
+                result: None,
+            },
+            Example {
+                description: "Appends to multiple documents from the previous command",
+                example: r#"[[id content]; [log_1 "a"] [log_2 "b"]] | doc append"#,
+                result: None,
+            },
+        ]
+    }
+}
+
+fn build_req(
+    key: String,
+    value: Vec<u8>,
+    _flags: u32,
+    _expiry: u32,
+    _cas: u64,
+    durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Append {
+        key,
+        value,
+        durability,
+    }
+}
@@ -1,35 +1,53 @@
+use crate::cli::buckets_builder::DurabilityLevel;
+use crate::cli::doc_get::{ids_from_input, GetResult};
+use crate::cli::doc_id_template::IdTemplate;
 use crate::cli::util::{
     cluster_identifiers_from, convert_nu_value_to_json_value, get_active_cluster,
     namespace_from_args, NuValueMap,
 };
-use crate::cli::{client_error_to_shell_error, serialize_error};
-use crate::client::{ClientError, KeyValueRequest, KvClient, KvResponse};
+use crate::cli::{client_error_to_shell_error, generic_error, serialize_error};
+use crate::client::{
+    ClientError, DocumentFormat, Durability, KeyValueRequest, KvClient, KvResponse, MutationToken,
+};
 use crate::remote_cluster::RemoteCluster;
 use crate::state::State;
+use chrono::Utc;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use log::info;
 use nu_engine::command_prelude::Call;
 use nu_engine::CallExt;
 use nu_protocol::engine::{EngineState, Stack};
-use nu_protocol::{PipelineData, ShellError, Signals, Span, Value};
-use std::collections::HashSet;
+use nu_protocol::{ListStream, PipelineData, Record, ShellError, Signals, Span, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
 use std::future::Future;
 use std::ops::Add;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::time::Instant;
 
+// The longest expiry, in seconds, which the server treats as relative to now.
+const RELATIVE_EXPIRY_LIMIT: i64 = 30 * 24 * 60 * 60;
+
 pub(crate) fn run_kv_store_ops(
     state: Arc<Mutex<State>>,
     engine_state: &EngineState,
     stack: &mut Stack,
     call: &Call,
     input: PipelineData,
-    req_builder: fn(String, Vec<u8>, u32) -> KeyValueRequest,
-) -> Result<Vec<Value>, ShellError> {
+    req_builder: fn(String, Vec<u8>, u32, u32, u64, Durability) -> KeyValueRequest,
+) -> Result<PipelineData, ShellError> {
     let span = call.head;
 
+    // The cas flag applies to the document given as arguments, or to any input without a cas.
+    let cas_flag: Option<i64> = call.get_flag(engine_state, stack, "cas")?;
+    let cas_flag = cas_flag.unwrap_or(0) as u64;
+    let format = format_from_args(engine_state, stack, call)?;
+
+    let id_template = id_template_from_args(engine_state, stack, call)?;
     let id_column = call
         .get_flag(engine_state, stack, "id-column")?
         .unwrap_or_else(|| String::from("id"));
@@ -38,84 +56,232 @@ pub(crate) fn run_kv_store_ops(
         .get_flag(engine_state, stack, "content-column")?
         .unwrap_or_else(|| String::from("content"));
 
-    let input_args = if let Some(id) = call.opt::<String>(engine_state, stack, 0)? {
+    let mut first = None;
+    let mut input = Some(input);
+    if let Some(id) = call.opt::<String>(engine_state, stack, 0)? {
         if let Some(v) = call.opt::<Value>(engine_state, stack, 1)? {
-            let content = convert_nu_value_to_json_value(&v, span)?;
-            vec![(id, content)]
-        } else {
-            vec![]
+            first = Some((Ok(id), v, cas_flag));
+        } else if let Some(v) = raw_content_from_input(&mut input, span)? {
+            // Raw input, such as from `open --raw`, is the content of the document.
+            first = Some((Ok(id), v, cas_flag));
         }
-    } else {
-        vec![]
-    };
-
-    let filtered = input.into_iter().filter_map(move |i| {
-        let id_column = id_column.clone();
-        let content_column = content_column.clone();
+    }
 
-        if let Value::Record { val, .. } = i {
-            let mut id = None;
-            let mut content = None;
-            for (k, v) in val.iter() {
-                if k.clone() == id_column {
-                    id = id_from_value(v, span);
-                }
-                if k.clone() == content_column {
-                    content = convert_nu_value_to_json_value(v, span).ok();
+    // The input is read lazily so that documents are only held in memory while being sent.
+    let rest = input
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter_map(move |(position, i)| {
+            if let Value::Record { val, .. } = i {
+                let mut id = None;
+                let mut content = None;
+                let mut cas = cas_flag;
+                for (k, v) in val.iter() {
+                    if k.clone() == id_column {
+                        id = id_from_value(v, span);
+                    }
+                    if k.clone() == content_column {
+                        content = Some(v.clone());
+                    }
+                    if k == "cas" {
+                        cas = cas_from_value(v).unwrap_or(cas_flag);
+                    }
                 }
+
+                content.map(|c| {
+                    let id = match &id_template {
+                        Some(template) => id_from_template(
+                            template,
+                            &c,
+                            &val,
+                            &content_column,
+                            position as u64 + 1,
+                            span,
+                        ),
+                        None => id.ok_or_else(|| MISSING_DOC_ID.to_string()),
+                    };
+                    (id, c, cas)
+                })
+            } else {
+                None
             }
+        });
+
+    let items = first
+        .into_iter()
+        .chain(rest)
+        .map(move |(id, content, cas)| {
+            let (value, flags) = encode_content(&content, format, span)?;
+            Ok((id, value, flags, cas))
+        });
+
+    run_kv_mutations(state, engine_state, stack, call, span, items, req_builder)
+}
+
+// raw_content_from_input takes the input if it is a single string or binary value, or a stream of
+// bytes, rather than a stream of records.
+fn raw_content_from_input(
+    input: &mut Option<PipelineData>,
+    span: Span,
+) -> Result<Option<Value>, ShellError> {
+    match input.take() {
+        Some(data @ PipelineData::ByteStream(..)) => Ok(Some(data.into_value(span)?)),
+        Some(PipelineData::Value(v @ (Value::String { .. } | Value::Binary { .. }), ..)) => {
+            Ok(Some(v))
+        }
+        other => {
+            *input = other;
+            Ok(None)
+        }
+    }
+}
 
-            if let Some(c) = content {
-                return Some((id.unwrap_or("".into()), c));
+// encode_content encodes the content of a document in the format given, along with the flags
+// which tell the SDKs how to read it back. Binary content is stored as binary unless another
+// format is given, everything else is stored as JSON.
+fn encode_content(
+    content: &Value,
+    format: Option<DocumentFormat>,
+    span: Span,
+) -> Result<(Vec<u8>, u32), ShellError> {
+    let format = match (format, content) {
+        (Some(f), _) => f,
+        (None, Value::Binary { .. }) => DocumentFormat::Binary,
+        (None, _) => DocumentFormat::Json,
+    };
+
+    let value = match (format, content) {
+        (DocumentFormat::Json, _) => {
+            let json = convert_nu_value_to_json_value(content, span)?;
+            serde_json::to_vec(&json).map_err(|e| serialize_error(e.to_string(), span))?
+        }
+        (DocumentFormat::String, Value::Binary { val, .. }) => {
+            if std::str::from_utf8(val).is_err() {
+                return Err(generic_error(
+                    "Invalid string content",
+                    "The content is not valid UTF-8, use --format binary to store it".to_string(),
+                    span,
+                ));
             }
+            val.clone()
         }
-        None
-    });
+        (DocumentFormat::Binary, Value::Binary { val, .. }) => val.clone(),
+        (_, Value::String { val, .. }) => val.as_bytes().to_vec(),
+        (_, v) => {
+            return Err(generic_error(
+                format!("Unsupported content of type {}", v.get_type()),
+                "Only string and binary content can be stored as string or binary, use --format json"
+                    .to_string(),
+                span,
+            ));
+        }
+    };
 
-    let mut all_items = vec![];
-    for item in filtered.chain(input_args) {
-        let value =
-            serde_json::to_vec(&item.1).map_err(|e| serialize_error(e.to_string(), span))?;
+    Ok((value, format.flags()))
+}
+
+// run_kv_concat_ops appends or prepends raw values to existing documents. Values are written as
+// they are rather than as JSON, so strings are not quoted.
+pub(crate) fn run_kv_concat_ops(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    input: PipelineData,
+    req_builder: fn(String, Vec<u8>, u32, u32, u64, Durability) -> KeyValueRequest,
+) -> Result<PipelineData, ShellError> {
+    let span = call.head;
 
-        all_items.push((item.0, value));
+    let id_column = call
+        .get_flag(engine_state, stack, "id-column")?
+        .unwrap_or_else(|| String::from("id"));
+
+    let content_column = call
+        .get_flag(engine_state, stack, "content-column")?
+        .unwrap_or_else(|| String::from("content"));
+
+    let mut first = None;
+    if let Some(id) = call.opt::<String>(engine_state, stack, 0)? {
+        if let Some(v) = call.opt::<Value>(engine_state, stack, 1)? {
+            first = Some((Ok(id), raw_value_from_value(&v, span)?, 0, 0));
+        }
     }
 
-    run_kv_mutations(
-        state,
-        engine_state,
-        stack,
-        call,
-        span,
-        all_items,
-        req_builder,
-    )
+    let rest = input.into_iter().filter_map(move |v| {
+        if let Value::Record { val, .. } = v {
+            let id = val.get(&id_column).and_then(|v| id_from_value(v, span));
+            val.get(&content_column).map(|content| {
+                Ok((
+                    id.ok_or_else(|| MISSING_DOC_ID.to_string()),
+                    raw_value_from_value(content, span)?,
+                    0,
+                    0,
+                ))
+            })
+        } else {
+            None
+        }
+    });
+
+    let items = first.map(Ok).into_iter().chain(rest);
+
+    run_kv_mutations(state, engine_state, stack, call, span, items, req_builder)
 }
 
-pub fn id_from_value(v: &Value, span: Span) -> Option<String> {
+fn raw_value_from_value(v: &Value, span: Span) -> Result<Vec<u8>, ShellError> {
     match v {
-        Value::String { val, .. } => Some(val.clone()),
-        Value::Int { val, .. } => Some(val.to_string()),
-        _ => {
-            info!(
-                "Skipping doc with id '{}' as id is not an int or string",
-                convert_nu_value_to_json_value(v, span).unwrap_or("error parsing id".into())
-            );
-            None
-        }
+        Value::String { val, .. } => Ok(val.as_bytes().to_vec()),
+        Value::Binary { val, .. } => Ok(val.clone()),
+        Value::Int { val, .. } => Ok(val.to_string().into_bytes()),
+        _ => Err(generic_error(
+            format!("Unsupported value of type {}", v.get_type()),
+            "Only strings, ints and binary values can be appended or prepended".to_string(),
+            span,
+        )),
     }
 }
 
-pub fn run_kv_mutations(
+// run_kv_counter_ops increments or decrements counter documents, returning the new value of each
+// counter.
+pub(crate) fn run_kv_counter_ops(
     state: Arc<Mutex<State>>,
     engine_state: &EngineState,
     stack: &mut Stack,
     call: &Call,
-    span: Span,
-    all_items: Vec<(String, Vec<u8>)>,
-    req_builder: fn(String, Vec<u8>, u32) -> KeyValueRequest,
+    input: PipelineData,
+    increment: bool,
 ) -> Result<Vec<Value>, ShellError> {
+    let span = call.head;
     let signals = engine_state.signals().clone();
 
+    let id_column: String = call
+        .get_flag(engine_state, stack, "id-column")?
+        .unwrap_or_else(|| "id".to_string());
+    let ids = ids_from_input(input, id_column.clone(), call.positional_nth(stack, 0))?;
+
+    let delta = match call.get_flag::<i64>(engine_state, stack, "delta")? {
+        Some(d) if d < 1 => {
+            return Err(generic_error(
+                format!("Invalid delta {}", d),
+                "The delta must be at least 1".to_string(),
+                span,
+            ));
+        }
+        Some(d) => d as u64,
+        None => 1,
+    };
+    let initial = match call.get_flag::<i64>(engine_state, stack, "initial")? {
+        Some(i) if i < 0 => {
+            return Err(generic_error(
+                format!("Invalid initial value {}", i),
+                "The initial value cannot be negative".to_string(),
+                span,
+            ));
+        }
+        Some(i) => Some(i as u64),
+        None => None,
+    };
     let expiry: i64 = call.get_flag(engine_state, stack, "expiry")?.unwrap_or(0);
     let batch_size: Option<i64> = call.get_flag(engine_state, stack, "batch-size")?;
 
@@ -124,14 +290,16 @@ pub fn run_kv_mutations(
     let collection_flag = call.get_flag(engine_state, stack, "collection")?;
 
     let halt_on_error = call.has_flag(engine_state, stack, "halt-on-error")?;
+    let with_timings = call.has_flag(engine_state, stack, "with-timings")?;
+    let durability = durability_from_args(engine_state, stack, call)?;
 
     let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
 
     let guard = state.lock().unwrap();
 
-    let mut all_values = vec![];
+    let mut all_ids: Vec<Vec<String>> = vec![];
     if let Some(size) = batch_size {
-        all_values = build_batched_kv_items(size as u32, all_items.clone());
+        all_ids = build_batched_kv_items(size as u32, ids.clone());
     }
 
     let mut results = vec![];
@@ -153,107 +321,709 @@ pub fn run_kv_mutations(
                     return Err(e);
                 }
 
-                let mut failures = HashSet::new();
-                failures.insert(e.to_string());
-                let collected = MutationResult::new(identifier.clone())
-                    .fail_reasons(failures)
-                    .into_value(call.head);
+                let collected = GetResult::new(identifier.clone())
+                    .id_column(&id_column)
+                    .error(e.to_string())
+                    .into_value(span);
                 results.push(collected);
                 continue;
             }
         };
 
-        if all_values.is_empty() {
-            all_values = build_batched_kv_items(active_cluster.kv_batch_size(), all_items.clone());
+        if all_ids.is_empty() {
+            all_ids = build_batched_kv_items(active_cluster.kv_batch_size(), ids.clone());
         }
 
-        let mut workers = FuturesUnordered::new();
-        let mut success = 0;
-        let mut failed = 0;
-        let mut fail_reasons: HashSet<String> = HashSet::new();
-        for items in all_values.clone() {
-            for item in items.clone() {
+        for ids in all_ids.clone() {
+            let mut workers = FuturesUnordered::new();
+            for key in ids {
                 let deadline = Instant::now().add(active_cluster.timeouts().data_timeout());
-
                 let signals = signals.clone();
-
                 let client = client.clone();
 
-                if !item.0.is_empty() {
-                    workers.push(async move {
-                        client
-                            .request(
-                                req_builder(item.0, item.1, expiry as u32),
-                                cid,
-                                deadline,
-                                signals,
-                            )
-                            .await
-                    });
+                let request = if increment {
+                    KeyValueRequest::Increment {
+                        key,
+                        delta,
+                        initial,
+                        expiry: expiry as u32,
+                        durability,
+                    }
                 } else {
-                    failed += 1;
-                    let mut missing_reason = HashSet::new();
-                    missing_reason.insert("Missing doc id".into());
-                    fail_reasons.extend(missing_reason);
+                    KeyValueRequest::Decrement {
+                        key,
+                        delta,
+                        initial,
+                        expiry: expiry as u32,
+                        durability,
+                    }
+                };
+                workers.push(async move { client.request(request, cid, deadline, signals).await });
+            }
+
+            rt.block_on(async {
+                while let Some(response) = workers.next().await {
+                    let collected = match response {
+                        Ok(mut res) => {
+                            let value = res
+                                .content()
+                                .and_then(|c| c.as_u64())
+                                .map(|c| Value::int(c as i64, span))
+                                .unwrap_or_default();
+                            let collected = GetResult::new(&identifier)
+                                .id_column(&id_column)
+                                .key(res.key())
+                                .content(value)
+                                .cas(res.cas() as i64);
+                            if with_timings {
+                                collected.timings(&res)
+                            } else {
+                                collected
+                            }
+                        }
+                        Err(e) => {
+                            if halt_on_error {
+                                return Err(client_error_to_shell_error(e, span));
+                            }
+
+                            GetResult::new(&identifier)
+                                .id_column(&id_column)
+                                .key(e.key().unwrap_or_default())
+                                .error(e.to_string())
+                        }
+                    };
+                    results.push(collected.into_value(span));
                 }
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(results)
+}
+
+// id_template_from_args reads the --id-template flag, which replaces --id-column.
+pub(crate) fn id_template_from_args(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+) -> Result<Option<IdTemplate>, ShellError> {
+    let template: Option<String> = call.get_flag(engine_state, stack, "id-template")?;
+    let Some(template) = template else {
+        return Ok(None);
+    };
+
+    if call
+        .get_flag::<String>(engine_state, stack, "id-column")?
+        .is_some()
+    {
+        return Err(generic_error(
+            "Both an id template and an id column were given",
+            "Use either --id-template or --id-column, the id column can be used in the template as {column}".to_string(),
+            call.head,
+        ));
+    }
+
+    IdTemplate::parse(&template, call.head).map(Some)
+}
+
+// id_from_template builds the id of a document from its content, or failing that the other
+// columns of the input row.
+fn id_from_template(
+    template: &IdTemplate,
+    content: &Value,
+    row: &Record,
+    content_column: &str,
+    position: u64,
+    span: Span,
+) -> Result<String, String> {
+    let content = match convert_nu_value_to_json_value(content, span) {
+        Ok(serde_json::Value::Object(content)) => content,
+        _ => serde_json::Map::new(),
+    };
+    let mut columns = serde_json::Map::new();
+    for (k, v) in row.iter().filter(|(k, _)| *k != content_column) {
+        if let Ok(v) = convert_nu_value_to_json_value(v, span) {
+            columns.insert(k.clone(), v);
+        }
+    }
+
+    template.render(&[&content, &columns], position)
+}
+
+pub fn id_from_value(v: &Value, span: Span) -> Option<String> {
+    match v {
+        Value::String { val, .. } => Some(val.clone()),
+        Value::Int { val, .. } => Some(val.to_string()),
+        _ => {
+            info!(
+                "Skipping doc with id '{}' as id is not an int or string",
+                convert_nu_value_to_json_value(v, span).unwrap_or("error parsing id".into())
+            );
+            None
+        }
+    }
+}
+
+// cas_from_value reads a CAS, as output by commands such as `doc get`, from a value.
+pub(crate) fn cas_from_value(v: &Value) -> Option<u64> {
+    match v {
+        Value::Int { val, .. } if *val != 0 => Some(*val as u64),
+        _ => None,
+    }
+}
+
+// duration_flag_as_secs reads a duration flag as a whole number of seconds.
+pub(crate) fn duration_flag_as_secs(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    name: &str,
+) -> Result<Option<i64>, ShellError> {
+    match call.get_flag::<Value>(engine_state, stack, name)? {
+        Some(Value::Duration { val, .. }) if val < 1_000_000_000 => Err(generic_error(
+            format!("Invalid {} duration", name),
+            "The duration must be at least 1sec".to_string(),
+            call.head,
+        )),
+        Some(Value::Duration { val, .. }) => Ok(Some(val / 1_000_000_000)),
+        Some(v) => Err(generic_error(
+            format!("Invalid {} duration", name),
+            format!("Expected a duration but got {}", v.get_type()),
+            call.head,
+        )),
+        None => Ok(None),
+    }
+}
+
+// expiry_from_secs converts a number of seconds from now into an expiry. The server treats any
+// expiry longer than 30 days as a unix timestamp, so longer expiries are converted to one.
+pub(crate) fn expiry_from_secs(secs: i64) -> u32 {
+    if secs > RELATIVE_EXPIRY_LIMIT {
+        (Utc::now().timestamp() + secs) as u32
+    } else {
+        secs as u32
+    }
+}
+
+// ids_and_cas_from_input reads the ids of documents, along with the CAS that each document must have
+// if one was given. Records without a usable id are returned with why, so that they are reported as
+// failed rather than dropped.
+pub(crate) fn ids_and_cas_from_input(
+    input: PipelineData,
+    id_column: String,
+    id: Option<&nu_protocol::ast::Expression>,
+    cas: u64,
+) -> impl Iterator<Item = (Result<String, String>, u64)> + Send + 'static {
+    let id = id.and_then(|id| id.as_string()).map(|id| (Ok(id), cas));
+    input
+        .into_iter()
+        .filter_map(move |v| match v {
+            Value::String { val, .. } => Some((Ok(val), cas)),
+            Value::Int { val, .. } => Some((Ok(val.to_string()), cas)),
+            Value::Record { val, .. } => {
+                let id = match val.get(&id_column) {
+                    Some(id) => id_from_value(id, id.span()).ok_or_else(|| {
+                        format!(
+                            "{}, the {} column is not a string or int",
+                            MISSING_DOC_ID, id_column
+                        )
+                    }),
+                    None => Err(format!(
+                        "{}, the record has no {} column",
+                        MISSING_DOC_ID, id_column
+                    )),
+                };
+                Some((id, val.get("cas").and_then(cas_from_value).unwrap_or(cas)))
             }
-            // process_kv_workers will handle creating an error for us if halt_on_error is set so we
-            // can just bubble it.
-            let worked = process_kv_workers(workers, &rt, halt_on_error, span)?;
+            _ => None,
+        })
+        .chain(id)
+}
+
+pub(crate) fn format_from_args(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+) -> Result<Option<DocumentFormat>, ShellError> {
+    let format: Option<String> = call.get_flag(engine_state, stack, "format")?;
+    format
+        .map(|f| {
+            DocumentFormat::try_from(f.as_str()).map_err(|_e| {
+                generic_error(
+                    format!("Failed to parse format {}", f),
+                    "Allowed values for format are json, string, binary".to_string(),
+                    call.head,
+                )
+            })
+        })
+        .transpose()
+}
+
+pub(crate) fn durability_from_args(
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+) -> Result<Durability, ShellError> {
+    let durability: Option<String> = call.get_flag(engine_state, stack, "durability")?;
+    match durability {
+        Some(d) => DurabilityLevel::try_from(d.as_str()).map(Durability::from).map_err(|_e| {
+            generic_error(
+                format!("Failed to parse durability level {}", d),
+                "Allowed values for durability level are none, majority, majority-and-persist-active, persist-to-majority".to_string(),
+                call.head,
+            )
+        }),
+        None => Ok(Durability::None),
+    }
+}
+
+// MutationItem is a document to mutate: its id, or why it has none, and its value, flags and cas.
+pub(crate) type MutationItem = (Result<String, String>, Vec<u8>, u32, u64);
+
+// The failure of documents without an id.
+pub(crate) const MISSING_DOC_ID: &str = "Missing doc id";
+
+// InvalidItem is an item of the input which could not be turned into a document, along with the
+// input it was read from, if any, so that the hook can keep it.
+#[derive(Debug)]
+pub(crate) struct InvalidItem {
+    pub(crate) error: ShellError,
+    pub(crate) raw: Vec<u8>,
+}
+
+impl From<ShellError> for InvalidItem {
+    fn from(error: ShellError) -> Self {
+        Self { error, raw: vec![] }
+    }
+}
+
+// run_kv_mutations sends a mutation built by req_builder for every item to every cluster. Items
+// are read from the iterator as earlier mutations complete so that only a bounded number are held
+// in memory, which allows inputs much larger than memory to be streamed through. The summary of
+// each cluster is emitted once every item has been sent, or when interrupted. With --with-results
+// a result for each item is emitted as it completes instead.
+pub fn run_kv_mutations(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    span: Span,
+    items: impl Iterator<Item = Result<MutationItem, ShellError>> + Send + 'static,
+    req_builder: impl Fn(String, Vec<u8>, u32, u32, u64, Durability) -> KeyValueRequest + Send + 'static,
+) -> Result<PipelineData, ShellError> {
+    run_kv_mutations_with_hook(
+        state,
+        engine_state,
+        stack,
+        call,
+        span,
+        items,
+        req_builder,
+        None,
+    )
+}
+
+// MutationHook is told once every cluster has completed an item, so that progress through the
+// input can be kept.
+pub(crate) trait MutationHook: Send {
+    // completed is called with the position of the item in the input and its value, along with
+    // the error and the kind of error if it failed on any cluster. Items complete out of order.
+    fn completed(
+        &mut self,
+        seq: u64,
+        value: &[u8],
+        error: Option<(&str, &str)>,
+    ) -> Result<(), ShellError>;
 
-            success += worked.success;
-            failed += worked.failed;
-            fail_reasons.extend(worked.fail_reasons);
-            workers = FuturesUnordered::new()
+    // finished is called once no more items will complete, including when interrupted.
+    fn finished(&mut self) -> Result<(), ShellError>;
+}
+
+// run_kv_mutations_with_hook is run_kv_mutations, telling the hook about every item completed.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run_kv_mutations_with_hook<E: Into<InvalidItem>>(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    span: Span,
+    items: impl Iterator<Item = Result<MutationItem, E>> + Send + 'static,
+    req_builder: impl Fn(String, Vec<u8>, u32, u32, u64, Durability) -> KeyValueRequest + Send + 'static,
+    hook: Option<Box<dyn MutationHook>>,
+) -> Result<PipelineData, ShellError> {
+    let signals = engine_state.signals().clone();
+
+    let expiry: i64 = call.get_flag(engine_state, stack, "expiry")?.unwrap_or(0);
+    let batch_size = match call.get_flag::<i64>(engine_state, stack, "batch-size")? {
+        Some(b) if b < 1 => {
+            return Err(generic_error(
+                format!("Invalid batch size {}", b),
+                "The batch size must be at least 1".to_string(),
+                span,
+            ));
         }
+        Some(b) => Some(b as usize),
+        None => None,
+    };
+
+    let bucket_flag = call.get_flag(engine_state, stack, "bucket")?;
+    let scope_flag = call.get_flag(engine_state, stack, "scope")?;
+    let collection_flag = call.get_flag(engine_state, stack, "collection")?;
+
+    let halt_on_error = call.has_flag(engine_state, stack, "halt-on-error")?;
+    let with_timings = call.has_flag(engine_state, stack, "with-timings")?;
+    let with_results = call.has_flag(engine_state, stack, "with-results")?;
+    let durability = durability_from_args(engine_state, stack, call)?;
+
+    let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
+
+    let guard = state.lock().unwrap();
+
+    let rt = Runtime::new().unwrap();
+    let mut targets = vec![];
+    let mut output = VecDeque::new();
+    let mut max_in_flight = batch_size;
+    for identifier in cluster_identifiers {
+        let (active_cluster, client, cid) = match get_active_cluster_client_cid(
+            &rt,
+            identifier.clone(),
+            &guard,
+            bucket_flag.clone(),
+            scope_flag.clone(),
+            collection_flag.clone(),
+            signals.clone(),
+            span,
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                if halt_on_error {
+                    return Err(e);
+                }
 
-        let collected = MutationResult::new(identifier.clone())
-            .success(success)
-            .failed(failed)
-            .fail_reasons(fail_reasons);
+                let collected = if with_results {
+                    MutationItemResult::new("", identifier.clone())
+                        .error(e.to_string(), "cluster_unavailable")
+                        .into_value(call.head)
+                } else {
+                    let mut failures = HashSet::new();
+                    failures.insert(e.to_string());
+                    MutationResult::new(identifier.clone())
+                        .fail_reasons(failures)
+                        .into_value(call.head)
+                };
+                output.push_back(collected);
+                continue;
+            }
+        };
 
-        results.push(collected.into_value(span));
+        if batch_size.is_none() {
+            let cluster_batch_size = active_cluster.kv_batch_size().max(1) as usize;
+            max_in_flight =
+                Some(max_in_flight.map_or(cluster_batch_size, |m| m.min(cluster_batch_size)));
+        }
+        targets.push(MutationTarget {
+            result: MutationResult::new(identifier.clone()),
+            identifier,
+            client,
+            cid,
+            data_timeout: active_cluster.timeouts().data_timeout(),
+            latencies: Latencies::default(),
+        });
     }
+    drop(guard);
 
-    Ok(results)
+    let stream = MutationStream {
+        rt,
+        items: Box::new(items.map(|item| item.map_err(Into::into))),
+        req_builder: Box::new(req_builder),
+        in_flight: FuturesUnordered::new(),
+        max_in_flight: max_in_flight.unwrap_or(1) * targets.len(),
+        input_done: targets.is_empty(),
+        targets,
+        output,
+        expiry: expiry as u32,
+        durability,
+        halt_on_error,
+        with_timings,
+        with_results,
+        hook,
+        next_seq: 0,
+        pending: HashMap::new(),
+        interrupted: false,
+        finished: false,
+        signals,
+        span,
+    };
+
+    // The stream watches for interrupts itself so that the summaries are still emitted.
+    Ok(PipelineData::from(ListStream::new(
+        stream,
+        span,
+        Signals::empty(),
+    )))
 }
 
-pub(crate) struct WorkerResponse {
-    pub(crate) success: i32,
-    pub(crate) failed: i32,
-    pub(crate) fail_reasons: HashSet<String>,
+// MutationTarget is a cluster which every item is sent to, along with the results so far.
+struct MutationTarget {
+    identifier: String,
+    result: MutationResult,
+    client: Arc<KvClient>,
+    cid: u32,
+    data_timeout: Duration,
+    latencies: Latencies,
 }
 
-pub(crate) fn process_kv_workers(
-    mut workers: FuturesUnordered<impl Future<Output = Result<KvResponse, ClientError>>>,
-    rt: &Runtime,
+type MutationRequestBuilder =
+    dyn Fn(String, Vec<u8>, u32, u32, u64, Durability) -> KeyValueRequest + Send;
+
+type MutationFuture =
+    Pin<Box<dyn Future<Output = (usize, u64, String, Result<KvResponse, ClientError>)> + Send>>;
+
+// PendingItem is an item which is still in flight on some clusters, kept for the hook.
+struct PendingItem {
+    remaining: usize,
+    value: Vec<u8>,
+    error: Option<(String, String)>,
+}
+
+// MutationStream sends the mutations for its items, keeping at most max_in_flight requests in
+// flight, and yields the summary of each cluster once done.
+struct MutationStream {
+    rt: Runtime,
+    items: Box<dyn Iterator<Item = Result<MutationItem, InvalidItem>> + Send>,
+    req_builder: Box<MutationRequestBuilder>,
+    targets: Vec<MutationTarget>,
+    in_flight: FuturesUnordered<MutationFuture>,
+    max_in_flight: usize,
+    output: VecDeque<Value>,
+    expiry: u32,
+    durability: Durability,
     halt_on_error: bool,
+    with_timings: bool,
+    with_results: bool,
+    hook: Option<Box<dyn MutationHook>>,
+    next_seq: u64,
+    pending: HashMap<u64, PendingItem>,
+    input_done: bool,
+    interrupted: bool,
+    finished: bool,
+    signals: Signals,
     span: Span,
-) -> Result<WorkerResponse, ShellError> {
-    let (success, failed, fail_reasons) = rt.block_on(async {
-        let mut success = 0;
-        let mut failed = 0;
-        let mut fail_reasons: HashSet<String> = HashSet::new();
-        while let Some(result) = workers.next().await {
-            match result {
-                Ok(_) => success += 1,
-                Err(e) => {
-                    if halt_on_error {
-                        return Err(client_error_to_shell_error(e, span));
+}
+
+impl MutationStream {
+    // fill sends mutations for items from the input until the in flight limit is reached.
+    fn fill(&mut self) -> Result<(), ShellError> {
+        while !self.input_done && self.in_flight.len() < self.max_in_flight {
+            if self.signals.interrupted() {
+                self.interrupted = true;
+                self.input_done = true;
+                break;
+            }
+
+            let seq = self.next_seq;
+            let (id, value, flags, cas) = match self.items.next() {
+                Some(Ok(item)) => item,
+                // A bad row fails on its own so that the rest of a large input is still written.
+                Some(Err(invalid)) if !self.halt_on_error => {
+                    self.next_seq += 1;
+                    let reason = invalid.error.to_string();
+                    for idx in 0..self.targets.len() {
+                        self.record_failure(idx, String::new(), reason.clone(), "invalid_input");
+                    }
+                    if let Some(hook) = self.hook.as_mut() {
+                        hook.completed(seq, &invalid.raw, Some((&reason, "invalid_input")))?;
+                    }
+                    continue;
+                }
+                Some(Err(invalid)) => return Err(invalid.error),
+                None => {
+                    // The input stops early when interrupted.
+                    self.interrupted = self.signals.interrupted();
+                    self.input_done = true;
+                    break;
+                }
+            };
+
+            self.next_seq += 1;
+
+            // Documents without an id are rejected rather than written to an empty key.
+            let id = match id {
+                Ok(id) if id.is_empty() => Err(MISSING_DOC_ID.to_string()),
+                id => id,
+            };
+            let id = match id {
+                Ok(id) => id,
+                Err(reason) => {
+                    for idx in 0..self.targets.len() {
+                        self.record_failure(idx, String::new(), reason.clone(), "missing_id");
+                    }
+                    if let Some(hook) = self.hook.as_mut() {
+                        hook.completed(seq, &value, Some((&reason, "missing_id")))?;
+                    }
+                    continue;
+                }
+            };
+
+            if self.hook.is_some() {
+                self.pending.insert(
+                    seq,
+                    PendingItem {
+                        remaining: self.targets.len(),
+                        value: value.clone(),
+                        error: None,
+                    },
+                );
+            }
+
+            for idx in 0..self.targets.len() {
+                let target = &self.targets[idx];
+                let request = (self.req_builder)(
+                    id.clone(),
+                    value.clone(),
+                    flags,
+                    self.expiry,
+                    cas,
+                    self.durability,
+                );
+                let client = target.client.clone();
+                let cid = target.cid;
+                let deadline = Instant::now().add(target.data_timeout);
+                let signals = self.signals.clone();
+                let id = id.clone();
+                self.in_flight.push(Box::pin(async move {
+                    let result = client.request(request, cid, deadline, signals).await;
+                    (idx, seq, id, result)
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        idx: usize,
+        seq: u64,
+        id: String,
+        result: Result<KvResponse, ClientError>,
+    ) -> Result<(), ShellError> {
+        if let Some(pending) = self.pending.get_mut(&seq) {
+            pending.remaining -= 1;
+            if let (Err(e), None) = (&result, &pending.error) {
+                pending.error = Some((e.to_string(), e.kind_name().to_string()));
+            }
+            if pending.remaining == 0 {
+                let pending = self.pending.remove(&seq).unwrap();
+                if let Some(hook) = self.hook.as_mut() {
+                    let error = pending
+                        .error
+                        .as_ref()
+                        .map(|(reason, kind)| (reason.as_str(), kind.as_str()));
+                    hook.completed(seq, &pending.value, error)?;
+                }
+            }
+        }
+
+        match result {
+            Ok(res) => {
+                let target = &mut self.targets[idx];
+                target.result.record_success(res.retries());
+                if self.with_results {
+                    let mut result = MutationItemResult::new(id, target.identifier.clone())
+                        .cas(res.cas())
+                        .mutation_token(res.mutation_token());
+                    if self.with_timings {
+                        result = result.timings(&res);
                     }
-                    failed += 1;
-                    fail_reasons.insert(e.to_string());
+                    self.output.push_back(result.into_value(self.span));
+                } else if self.with_timings {
+                    target.latencies.record(&res);
+                }
+            }
+            Err(e) => {
+                if self.halt_on_error {
+                    return Err(client_error_to_shell_error(e, self.span));
                 }
+                self.record_failure(idx, id, e.to_string(), e.kind_name());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_failure(&mut self, idx: usize, id: String, reason: String, kind: &str) {
+        let target = &mut self.targets[idx];
+        if self.with_results {
+            let result =
+                MutationItemResult::new(id, target.identifier.clone()).error(reason.clone(), kind);
+            self.output.push_back(result.into_value(self.span));
+        }
+        target.result.record_failure(reason);
+    }
+
+    fn finish(&mut self) -> Result<(), ShellError> {
+        self.finished = true;
+        if let Some(mut hook) = self.hook.take() {
+            hook.finished()?;
+        }
+
+        // Each item already has a result, an interrupt is seen by the missing items.
+        if self.with_results {
+            return Ok(());
+        }
+
+        for target in self.targets.drain(..) {
+            let mut result = target.result;
+            if self.with_timings {
+                result = result.latencies(target.latencies);
+            }
+            if self.interrupted {
+                result = result.interrupted(true);
             }
+            self.output.push_back(result.into_value(self.span));
+        }
+
+        Ok(())
+    }
+
+    // fail stops the stream with the error, the hook still gets to save its progress.
+    fn fail(&mut self, e: ShellError) -> Value {
+        self.finished = true;
+        if let Some(mut hook) = self.hook.take() {
+            let _ = hook.finished();
         }
-        Ok((success, failed, fail_reasons))
-    })?;
+        Value::error(e, self.span)
+    }
+}
 
-    Ok(WorkerResponse {
-        success,
-        failed,
-        fail_reasons,
-    })
+impl Iterator for MutationStream {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(v) = self.output.pop_front() {
+                return Some(v);
+            }
+            if self.finished {
+                return None;
+            }
+
+            if let Err(e) = self.fill() {
+                return Some(self.fail(e));
+            }
+
+            let result = match self.rt.block_on(self.in_flight.next()) {
+                Some((idx, seq, id, result)) => self.record(idx, seq, id, result),
+                None if self.input_done => self.finish(),
+                None => Ok(()),
+            };
+            if let Err(e) = result {
+                return Some(self.fail(e));
+            }
+        }
+    }
 }
 
 pub(crate) fn build_batched_kv_items<T>(
@@ -296,11 +1066,7 @@ pub(crate) fn get_active_cluster_client_cid<'a>(
 
     let deadline = Instant::now().add(active_cluster.timeouts().data_timeout());
     let client = rt
-        .block_on(active_cluster.cluster().key_value_client(
-            bucket.clone(),
-            deadline,
-            signals.clone(),
-        ))
+        .block_on(active_cluster.key_value_client(bucket.clone(), deadline, signals.clone()))
         .map_err(|e| client_error_to_shell_error(e, span))?;
 
     let cid = rt
@@ -310,9 +1076,59 @@ pub(crate) fn get_active_cluster_client_cid<'a>(
             Instant::now().add(active_cluster.timeouts().data_timeout()),
             signals.clone(),
         ))
-        .map_err(|e| client_error_to_shell_error(e, span))?;
+        .map_err(|e| {
+            // The client may be stale, e.g. if the bucket has been recreated.
+            active_cluster.invalidate_key_value_client(&bucket);
+            client_error_to_shell_error(e, span)
+        })?;
+
+    Ok((active_cluster, client, cid))
+}
+
+// Latencies collects the server durations and client round trips of successful requests, so that
+// a summary of them can be added to the results of bulk operations.
+#[derive(Debug, Default)]
+pub(crate) struct Latencies {
+    server: Vec<Duration>,
+    round_trip: Vec<Duration>,
+}
+
+impl Latencies {
+    pub fn record(&mut self, res: &KvResponse) {
+        // The server only reports durations once tracing has been negotiated.
+        if let Some(d) = res.server_duration() {
+            self.server.push(d);
+        }
+        self.round_trip.push(res.round_trip());
+    }
 
-    Ok((active_cluster, Arc::new(client), cid))
+    fn add_summary(
+        collected: &mut NuValueMap,
+        prefix: &str,
+        mut durations: Vec<Duration>,
+        span: Span,
+    ) {
+        durations.sort();
+        for (name, value) in [
+            ("p50", percentile(&durations, 50.0)),
+            ("p99", percentile(&durations, 99.0)),
+            ("max", durations.last().copied()),
+        ] {
+            let value = value
+                .map(|d| Value::duration(d.as_nanos() as i64, span))
+                .unwrap_or_else(|| Value::nothing(span));
+            collected.add(format!("{}_{}", prefix, name), value);
+        }
+    }
+}
+
+// percentile uses the nearest rank method to find the percentile of a sorted list of durations.
+fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
 }
 
 #[derive(Debug)]
@@ -320,6 +1136,9 @@ pub struct MutationResult {
     success: i32,
     failed: i32,
     fail_reasons: HashSet<String>,
+    retries: u32,
+    latencies: Option<Latencies>,
+    interrupted: bool,
     cluster: String,
 }
 
@@ -329,24 +1148,39 @@ impl MutationResult {
             success: 0,
             failed: 0,
             fail_reasons: Default::default(),
+            retries: 0,
+            latencies: None,
+            interrupted: false,
             cluster,
         }
     }
-    pub fn success(mut self, success: i32) -> Self {
-        self.success = success;
+    pub fn fail_reasons(mut self, fail_reasons: HashSet<String>) -> Self {
+        self.fail_reasons = fail_reasons;
         self
     }
 
-    pub fn failed(mut self, failed: i32) -> Self {
-        self.failed = failed;
+    pub(crate) fn latencies(mut self, latencies: Latencies) -> Self {
+        self.latencies = Some(latencies);
         self
     }
 
-    pub fn fail_reasons(mut self, fail_reasons: HashSet<String>) -> Self {
-        self.fail_reasons = fail_reasons;
+    // interrupted marks the results as partial, because the operation was cancelled before every
+    // document had been processed.
+    pub fn interrupted(mut self, interrupted: bool) -> Self {
+        self.interrupted = interrupted;
         self
     }
 
+    pub(crate) fn record_success(&mut self, retries: u32) {
+        self.success += 1;
+        self.retries += retries;
+    }
+
+    pub(crate) fn record_failure(&mut self, reason: String) {
+        self.failed += 1;
+        self.fail_reasons.insert(reason);
+    }
+
     pub fn into_value(self, span: Span) -> Value {
         let mut collected = NuValueMap::default();
         collected.add_i64("processed", (self.success + self.failed) as i64, span);
@@ -359,7 +1193,171 @@ impl MutationResult {
             .collect::<Vec<String>>()
             .join(", ");
         collected.add_string("failures", reasons, span);
+        collected.add_i64("retries", self.retries as i64, span);
+        if let Some(latencies) = self.latencies {
+            Latencies::add_summary(&mut collected, "server", latencies.server, span);
+            Latencies::add_summary(&mut collected, "round_trip", latencies.round_trip, span);
+        }
+        if self.interrupted {
+            collected.add_bool("interrupted", true, span);
+        }
+        collected.add_string("cluster", self.cluster, span);
+        collected.into_value(span)
+    }
+}
+
+// MutationItemResult is the outcome of mutating a single document on a cluster, which is emitted
+// in place of the summary with --with-results.
+pub(crate) struct MutationItemResult {
+    id: String,
+    cas: u64,
+    mutation_token: Option<MutationToken>,
+    error: Option<(String, String)>,
+    timings: Option<(Option<Duration>, Duration, u16, String)>,
+    cluster: String,
+}
+
+impl MutationItemResult {
+    pub fn new(id: impl Into<String>, cluster: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            cas: 0,
+            mutation_token: None,
+            error: None,
+            timings: None,
+            cluster: cluster.into(),
+        }
+    }
+
+    pub fn cas(mut self, cas: u64) -> Self {
+        self.cas = cas;
+        self
+    }
+
+    pub fn mutation_token(mut self, token: Option<MutationToken>) -> Self {
+        self.mutation_token = token;
+        self
+    }
+
+    // error marks the mutation as failed, kind is the short name of the error to filter on.
+    pub fn error(mut self, reason: String, kind: impl Into<String>) -> Self {
+        self.error = Some((reason, kind.into()));
+        self
+    }
+
+    pub fn timings(mut self, res: &KvResponse) -> Self {
+        self.timings = Some((
+            res.server_duration(),
+            res.round_trip(),
+            res.partition(),
+            res.node(),
+        ));
+        self
+    }
+
+    pub fn into_value(self, span: Span) -> Value {
+        let mut collected = NuValueMap::default();
+        collected.add_string("id", self.id, span);
+        let (status, error, kind) = match self.error {
+            Some((error, kind)) => ("failed", error, kind),
+            None => ("success", String::new(), String::new()),
+        };
+        collected.add_string("status", status, span);
+        collected.add_i64("cas", self.cas as i64, span);
+        collected.add(
+            "mutation_token",
+            self.mutation_token
+                .map(|token| {
+                    let mut t = NuValueMap::default();
+                    t.add_string("bucket", token.bucket, span);
+                    t.add_i64("vbucket", token.partition as i64, span);
+                    t.add_i64("vbucket_uuid", token.partition_uuid as i64, span);
+                    t.add_i64("seqno", token.sequence as i64, span);
+                    t.into_value(span)
+                })
+                .unwrap_or_else(|| Value::nothing(span)),
+        );
+        collected.add_string("error", error, span);
+        collected.add_string("error_kind", kind, span);
+        if let Some((server_duration, round_trip, vbucket, node)) = self.timings {
+            collected.add(
+                "server_duration",
+                server_duration
+                    .map(|d| Value::duration(d.as_nanos() as i64, span))
+                    .unwrap_or_else(|| Value::nothing(span)),
+            );
+            collected.add(
+                "round_trip",
+                Value::duration(round_trip.as_nanos() as i64, span),
+            );
+            collected.add_i64("vbucket", vbucket as i64, span);
+            collected.add_string("node", node, span);
+        }
         collected.add_string("cluster", self.cluster, span);
         collected.into_value(span)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let durations: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(
+            Some(Duration::from_millis(50)),
+            percentile(&durations, 50.0)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(99)),
+            percentile(&durations, 99.0)
+        );
+
+        let single = vec![Duration::from_millis(7)];
+        assert_eq!(Some(Duration::from_millis(7)), percentile(&single, 50.0));
+        assert_eq!(Some(Duration::from_millis(7)), percentile(&single, 99.0));
+        assert_eq!(None, percentile(&[], 50.0));
+    }
+
+    #[test]
+    fn records_without_a_usable_id_are_not_dropped() {
+        let span = Span::unknown();
+        let record = |id: Value| {
+            let mut record = Record::new();
+            record.push("id", id);
+            record.push("cas", Value::int(7, span));
+            Value::record(record, span)
+        };
+        let input = Value::list(
+            vec![
+                Value::int(1, span),
+                record(Value::string("a", span)),
+                record(Value::int(2, span)),
+                record(Value::bool(true, span)),
+                Value::record(Record::new(), span),
+            ],
+            span,
+        );
+
+        let ids: Vec<(Result<String, String>, u64)> =
+            ids_and_cas_from_input(PipelineData::Value(input, None), "id".to_string(), None, 0)
+                .collect();
+        assert_eq!(
+            vec![
+                (Ok("1".to_string()), 0),
+                (Ok("a".to_string()), 7),
+                (Ok("2".to_string()), 7),
+                (
+                    Err("Missing doc id, the id column is not a string or int".to_string()),
+                    7
+                ),
+                (
+                    Err("Missing doc id, the record has no id column".to_string()),
+                    0
+                ),
+            ],
+            ids
+        );
+    }
+}
@@ -3,14 +3,18 @@
 use super::util::convert_json_value_to_nu_value;
 use crate::state::State;
 
-use crate::cli::doc_common::{build_batched_kv_items, get_active_cluster_client_cid};
+use crate::cli::doc_common::{
+    build_batched_kv_items, duration_flag_as_secs, expiry_from_secs, get_active_cluster_client_cid,
+};
 use crate::cli::util::{cluster_identifiers_from, NuValueMap};
-use crate::client::KeyValueRequest;
+use crate::client::{DocumentFormat, KeyValueRequest, KvResponse};
+use chrono::DateTime;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use log::debug;
 use std::ops::Add;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::time::Instant;
 
@@ -73,7 +77,35 @@ impl Command for DocGet {
                 "the maximum number of items to batch send at a time",
                 None,
             )
+            .named(
+                "replica",
+                SyntaxShape::String,
+                "read from replicas as well as the active, either 'any' or 'all'",
+                None,
+            )
+            .switch(
+                "xattrs",
+                "fetch the extended attributes of the document as well as the content",
+                None,
+            )
+            .named(
+                "touch",
+                SyntaxShape::Duration,
+                "update the expiry of the document to this long from now",
+                None,
+            )
+            .named(
+                "lock",
+                SyntaxShape::Duration,
+                "lock the document for this long, up to 30sec, it can be unlocked early with doc unlock",
+                None,
+            )
             .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add the server duration, round trip, node and vbucket to each result",
+                None,
+            )
             .category(Category::Custom("couchbase".to_string()))
     }
 
@@ -103,6 +135,31 @@ impl Command for DocGet {
                 example: "echo [[id]; [airline_10] [airline_11]] | doc get",
                 result: None,
             },
+            Example {
+                description: "Fetches a document from whichever of the active or replica nodes responds first",
+                example: "doc get my_doc_id --replica any",
+                result: None,
+            },
+            Example {
+                description: "Fetches a document from the active and all replica nodes",
+                example: "doc get my_doc_id --replica all",
+                result: None,
+            },
+            Example {
+                description: "Fetches a document along with its extended attributes",
+                example: "doc get my_doc_id --xattrs",
+                result: None,
+            },
+            Example {
+                description: "Fetches a session document and extends its expiry to an hour from now",
+                example: "doc get session_123 --touch 1hr",
+                result: None,
+            },
+            Example {
+                description: "Fetches and locks a document, then updates and unlocks it with the CAS of the lock",
+                example: "doc get my_doc_id --lock 10sec | update content.name new_name | doc replace",
+                result: None,
+            },
         ]
     }
 
@@ -140,6 +197,56 @@ fn run_get(
     let scope_flag = call.get_flag(engine_state, stack, "scope")?;
     let collection_flag = call.get_flag(engine_state, stack, "collection")?;
     let halt_on_error = call.has_flag(engine_state, stack, "halt-on-error")?;
+    let replica_mode = match call
+        .get_flag::<String>(engine_state, stack, "replica")?
+        .as_deref()
+    {
+        Some("any") => Some(ReplicaMode::Any),
+        Some("all") => Some(ReplicaMode::All),
+        Some(m) => {
+            return Err(generic_error(
+                format!("Invalid replica mode {}", m),
+                "Allowed values for replica are any, all".to_string(),
+                span,
+            ));
+        }
+        None => None,
+    };
+    let xattrs = call.has_flag(engine_state, stack, "xattrs")?;
+    let with_timings = call.has_flag(engine_state, stack, "with-timings")?;
+    if xattrs && replica_mode.is_some() {
+        return Err(generic_error(
+            "Invalid flags",
+            "The xattrs flag cannot be used with the replica flag".to_string(),
+            span,
+        ));
+    }
+
+    let touch = duration_flag_as_secs(engine_state, stack, call, "touch")?;
+    let lock = duration_flag_as_secs(engine_state, stack, call, "lock")?;
+    if (touch.is_some() || lock.is_some())
+        && (touch.is_some() == lock.is_some() || xattrs || replica_mode.is_some())
+    {
+        return Err(generic_error(
+            "Invalid flags",
+            "The touch and lock flags cannot be used together or with the replica and xattrs flags"
+                .to_string(),
+            span,
+        ));
+    }
+    if let Some(lock) = lock {
+        if !(1..=MAX_LOCK_TIME).contains(&lock) {
+            return Err(generic_error(
+                format!("Invalid lock time {}sec", lock),
+                format!(
+                    "Documents can be locked for between 1 and {} seconds",
+                    MAX_LOCK_TIME
+                ),
+                span,
+            ));
+        }
+    }
+    let touch = touch.map(expiry_from_secs);
 
     let mut results = vec![];
     for identifier in cluster_identifiers {
@@ -185,49 +292,104 @@ fn run_get(
                 let client = client.clone();
 
                 workers.push(async move {
-                    client
-                        .request(KeyValueRequest::Get { key: id }, cid, deadline, signals)
-                        .await
+                    match replica_mode {
+                        Some(ReplicaMode::Any) => {
+                            vec![client.get_any_replica(id, cid, deadline, signals).await]
+                        }
+                        Some(ReplicaMode::All) => {
+                            client.get_all_replicas(id, cid, deadline, signals).await
+                        }
+                        None if xattrs => {
+                            vec![client.get_with_xattrs(id, cid, deadline, signals).await]
+                        }
+                        None => {
+                            let request = match (touch, lock) {
+                                (Some(expiry), _) => {
+                                    KeyValueRequest::GetAndTouch { key: id, expiry }
+                                }
+                                (_, Some(lock_time)) => KeyValueRequest::GetAndLock {
+                                    key: id,
+                                    lock_time: lock_time as u32,
+                                },
+                                _ => KeyValueRequest::Get { key: id },
+                            };
+                            vec![client.request(request, cid, deadline, signals).await]
+                        }
+                    }
                 });
             }
             rt.block_on(async {
-                while let Some(response) = workers.next().await {
-                    match response {
-                        Ok(mut res) => {
-                            let mut collected = GetResult::new(&identifier)
-                                .id_column(&id_column)
-                                .key(res.key())
-                                .cas(res.cas() as i64);
-
-                            let content = res.content().unwrap_or_default();
-                            match convert_json_value_to_nu_value(&content, call.head) {
-                                Ok(c) => {
-                                    collected = collected.content(c);
+                while let Some(responses) = workers.next().await {
+                    for response in responses {
+                        match response {
+                            Ok(mut res) => {
+                                let mut collected = GetResult::new(&identifier)
+                                    .id_column(&id_column)
+                                    .key(res.key())
+                                    .cas(res.cas() as i64);
+                                if replica_mode.is_some() {
+                                    collected = collected.node(res.node()).replica(res.replica());
+                                }
+                                if with_timings {
+                                    collected = collected.timings(&res);
                                 }
-                                Err(e) => {
-                                    if halt_on_error {
-                                        return Err(e);
+
+                                if let Some(raw) = res.raw_content() {
+                                    collected = collected.content(raw_content_to_nu_value(
+                                        res.format(),
+                                        raw,
+                                        call.head,
+                                    ));
+                                    results.push(collected.into_value(call.head));
+                                    continue;
+                                }
+
+                                let mut content = res.content().unwrap_or_default();
+                                if xattrs {
+                                    match convert_xattrs_to_nu_value(&content["xattrs"], call.head)
+                                    {
+                                        Ok(x) => {
+                                            collected = collected.xattrs(x);
+                                        }
+                                        Err(e) => {
+                                            if halt_on_error {
+                                                return Err(e);
+                                            }
+                                            collected = collected.error(e.to_string());
+                                        }
                                     }
-                                    collected = collected.error(e.to_string());
+                                    content = content["content"].take();
                                 }
+
+                                match convert_json_value_to_nu_value(&content, call.head) {
+                                    Ok(c) => {
+                                        collected = collected.content(c);
+                                    }
+                                    Err(e) => {
+                                        if halt_on_error {
+                                            return Err(e);
+                                        }
+                                        collected = collected.error(e.to_string());
+                                    }
+                                }
+                                results.push(collected.into_value(call.head));
                             }
-                            results.push(collected.into_value(call.head));
-                        }
-                        Err(e) => {
-                            if halt_on_error {
-                                return Err(generic_error(
-                                    "Failed to fetch document",
-                                    Some(e.to_string()),
-                                    call.head,
-                                ));
-                            }
+                            Err(e) => {
+                                if halt_on_error {
+                                    return Err(generic_error(
+                                        "Failed to fetch document",
+                                        Some(e.to_string()),
+                                        call.head,
+                                    ));
+                                }
 
-                            let collected = GetResult::new(&identifier)
-                                .id_column(&id_column)
-                                .key(e.key().unwrap_or_default())
-                                .error(e.to_string())
-                                .into_value(call.head);
-                            results.push(collected);
+                                let collected = GetResult::new(&identifier)
+                                    .id_column(&id_column)
+                                    .key(e.key().unwrap_or_default())
+                                    .error(e.to_string())
+                                    .into_value(call.head);
+                                results.push(collected);
+                            }
                         }
                     }
                 }
@@ -276,6 +438,73 @@ pub(crate) fn ids_from_input(
     Ok(ids)
 }
 
+// raw_content_to_nu_value converts the content of a document stored as a string or as binary.
+fn raw_content_to_nu_value(format: DocumentFormat, raw: Vec<u8>, span: Span) -> Value {
+    match format {
+        DocumentFormat::String => Value::string(String::from_utf8_lossy(&raw), span),
+        _ => Value::binary(raw, span),
+    }
+}
+
+// convert_xattrs_to_nu_value converts the extended attributes of a document into a record,
+// decoding the virtual $document attribute.
+fn convert_xattrs_to_nu_value(xattrs: &serde_json::Value, span: Span) -> Result<Value, ShellError> {
+    let mut collected = NuValueMap::default();
+    if let Some(xattrs) = xattrs.as_object() {
+        for (name, value) in xattrs {
+            let converted = if name == "$document" {
+                convert_virtual_document_to_nu_value(value, span)?
+            } else {
+                convert_json_value_to_nu_value(value, span)?
+            };
+            collected.add(name, converted);
+        }
+    }
+    Ok(collected.into_value(span))
+}
+
+// convert_virtual_document_to_nu_value converts the virtual $document extended attribute into a
+// record. The server encodes the CAS and seqno as hex strings so they are decoded into integers,
+// and the last modified time is taken from the hybrid logical clock in the CAS.
+pub(crate) fn convert_virtual_document_to_nu_value(
+    document: &serde_json::Value,
+    span: Span,
+) -> Result<Value, ShellError> {
+    let mut record = match convert_json_value_to_nu_value(document, span)? {
+        Value::Record { val, .. } => val.into_owned(),
+        other => return Ok(other),
+    };
+
+    if let Some(cas) = hex_field(document, "CAS") {
+        record.insert("CAS", Value::int(cas as i64, span));
+        // The lower 16 bits of the clock are a logical counter rather than nanoseconds.
+        let nanos = (cas & !0xffff) as i64;
+        record.insert(
+            "last_modified",
+            Value::date(DateTime::from_timestamp_nanos(nanos).fixed_offset(), span),
+        );
+    }
+    if let Some(seqno) = hex_field(document, "seqno") {
+        record.insert("seqno", Value::int(seqno as i64, span));
+    }
+
+    Ok(Value::record(record, span))
+}
+
+fn hex_field(document: &serde_json::Value, name: &str) -> Option<u64> {
+    let value = document.get(name)?.as_str()?;
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+// The longest that the server allows a document to be locked for, in seconds.
+const MAX_LOCK_TIME: i64 = 30;
+
+#[derive(Debug, Copy, Clone)]
+enum ReplicaMode {
+    Any,
+    All,
+}
+
 #[derive(Debug)]
 pub(crate) struct GetResult {
     error: Option<String>,
@@ -284,6 +513,10 @@ pub(crate) struct GetResult {
     cluster: String,
     cas: Option<i64>,
     id_column: Option<String>,
+    node: Option<String>,
+    replica: Option<bool>,
+    xattrs: Option<Value>,
+    timings: Option<(Option<Duration>, Duration, u16)>,
 }
 
 impl GetResult {
@@ -295,6 +528,10 @@ impl GetResult {
             cluster: cluster.into(),
             cas: None,
             id_column: None,
+            node: None,
+            replica: None,
+            xattrs: None,
+            timings: None,
         }
     }
 
@@ -318,6 +555,28 @@ impl GetResult {
         self
     }
 
+    pub fn node(mut self, node: String) -> GetResult {
+        self.node = Some(node);
+        self
+    }
+
+    pub fn replica(mut self, replica: bool) -> GetResult {
+        self.replica = Some(replica);
+        self
+    }
+
+    pub fn xattrs(mut self, xattrs: Value) -> GetResult {
+        self.xattrs = Some(xattrs);
+        self
+    }
+
+    // timings adds the server duration, round trip, node and vbucket of the response to the result.
+    pub fn timings(mut self, res: &KvResponse) -> GetResult {
+        self.node = Some(res.node());
+        self.timings = Some((res.server_duration(), res.round_trip(), res.partition()));
+        self
+    }
+
     pub fn error(mut self, err: String) -> GetResult {
         self.error = Some(err);
         self
@@ -332,7 +591,29 @@ impl GetResult {
         );
         collected.add("content", self.content.unwrap_or_default());
         collected.add_i64("cas", self.cas.unwrap_or_default(), span);
+        if let Some(xattrs) = self.xattrs {
+            collected.add("xattrs", xattrs);
+        }
         collected.add_string("error", self.error.unwrap_or_default(), span);
+        if let Some(node) = self.node {
+            collected.add_string("node", node, span);
+        }
+        if let Some(replica) = self.replica {
+            collected.add_bool("replica", replica, span);
+        }
+        if let Some((server_duration, round_trip, vbucket)) = self.timings {
+            collected.add(
+                "server_duration",
+                server_duration
+                    .map(|d| Value::duration(d.as_nanos() as i64, span))
+                    .unwrap_or_else(|| Value::nothing(span)),
+            );
+            collected.add(
+                "round_trip",
+                Value::duration(round_trip.as_nanos() as i64, span),
+            );
+            collected.add_i64("vbucket", vbucket as i64, span);
+        }
         collected.add_string("cluster", self.cluster, span);
         collected.into_value(span)
     }
@@ -0,0 +1,265 @@
+//! Templates which build document ids from the fields of each row, such as `{type}::{id}`, along
+//! with generators for rows which have no natural key.
+
+use crate::cli::error::generic_error;
+use nu_protocol::{ShellError, Span};
+use serde_json::{Map, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+// How many bytes of the SHA-256 digest are used for {hash:...}, 128 bits keeps collisions unlikely
+// for any number of rows which could be imported.
+const HASH_BYTES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(String),
+    Uuid,
+    Monotonic,
+    Hash(Vec<String>),
+}
+
+// IdTemplate is a parsed --id-template. Fields in braces are replaced by the value of that field of
+// the row, which can be a dotted path into nested objects, and {uuid}, {monotonic} and
+// {hash:field,...} generate ids. Braces are written as {{ and }}.
+#[derive(Debug, Clone)]
+pub(crate) struct IdTemplate {
+    parts: Vec<Part>,
+}
+
+impl IdTemplate {
+    pub fn parse(template: &str, span: Span) -> Result<Self, ShellError> {
+        let invalid = |reason: String| {
+            generic_error(format!("Invalid id template {}", template), reason, span)
+        };
+
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => {
+                    return Err(invalid(
+                        "A closing brace must be written as }} outside of a field".to_string(),
+                    ));
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                return Err(invalid(format!(
+                                    "The field {{{} is missing its closing brace",
+                                    name
+                                )));
+                            }
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Self::parse_field(name.trim()).map_err(invalid)?);
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        if parts.iter().all(|p| matches!(p, Part::Literal(_))) {
+            return Err(invalid(
+                "The template must contain at least one field or generator, otherwise every document has the same id"
+                    .to_string(),
+            ));
+        }
+
+        Ok(Self { parts })
+    }
+
+    fn parse_field(name: &str) -> Result<Part, String> {
+        if let Some(fields) = name.strip_prefix("hash:") {
+            let fields: Vec<String> = fields
+                .split(',')
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty())
+                .collect();
+            if fields.is_empty() {
+                return Err("{hash:...} must name at least one field".to_string());
+            }
+            return Ok(Part::Hash(fields));
+        }
+
+        match name {
+            "" => Err("Fields must have a name, braces are written as {{ and }}".to_string()),
+            "uuid" => Ok(Part::Uuid),
+            "monotonic" => Ok(Part::Monotonic),
+            _ => Ok(Part::Field(name.to_string())),
+        }
+    }
+
+    // render builds the id of a row. Fields are looked for in each of the sources in turn, and
+    // position is the position of the row in the input, starting at 1, which is used for
+    // {monotonic} so that resuming an import generates the same ids.
+    pub fn render(
+        &self,
+        sources: &[&Map<String, JsonValue>],
+        position: u64,
+    ) -> Result<String, String> {
+        let mut id = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(l) => id.push_str(l),
+                Part::Field(name) => match lookup(sources, name) {
+                    Some(JsonValue::String(s)) => id.push_str(s),
+                    Some(v @ (JsonValue::Number(_) | JsonValue::Bool(_))) => {
+                        id.push_str(&v.to_string())
+                    }
+                    Some(JsonValue::Null) | None => {
+                        return Err(format!("Missing doc id, the row has no {} field", name));
+                    }
+                    Some(_) => {
+                        return Err(format!(
+                            "Missing doc id, the {} field is not a string, number or bool",
+                            name
+                        ));
+                    }
+                },
+                Part::Uuid => id.push_str(&uuid::Uuid::new_v4().to_string()),
+                Part::Monotonic => id.push_str(&position.to_string()),
+                Part::Hash(fields) => {
+                    let mut hasher = Sha256::new();
+                    for name in fields {
+                        let value = lookup(sources, name).ok_or_else(|| {
+                            format!("Missing doc id, the row has no {} field to hash", name)
+                        })?;
+                        // Values are hashed as JSON so that the string "1" and the number 1
+                        // differ, and separated so that moving characters between fields does too.
+                        hasher.update(value.to_string().as_bytes());
+                        hasher.update([0]);
+                    }
+                    for b in &hasher.finalize()[..HASH_BYTES] {
+                        let _ = write!(id, "{:02x}", b);
+                    }
+                }
+            }
+        }
+
+        Ok(id)
+    }
+}
+
+// lookup finds a field by its name, or failing that as a dotted path into nested objects.
+fn lookup<'a>(sources: &[&'a Map<String, JsonValue>], name: &str) -> Option<&'a JsonValue> {
+    sources.iter().find_map(|row| {
+        row.get(name).or_else(|| {
+            let mut path = name.split('.');
+            let first = row.get(path.next()?)?;
+            path.try_fold(first, |v, key| v.get(key))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn row(value: JsonValue) -> Map<String, JsonValue> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn renders_fields() {
+        let template = IdTemplate::parse("{type}::{airline}::{id}", Span::unknown()).unwrap();
+        let row = row(json!({"type": "route", "airline": "AF", "id": 10000}));
+        assert_eq!(
+            Ok("route::AF::10000".to_string()),
+            template.render(&[&row], 1)
+        );
+    }
+
+    #[test]
+    fn renders_nested_fields_and_escaped_braces() {
+        let template = IdTemplate::parse("{{{geo.country}}}-{monotonic}", Span::unknown()).unwrap();
+        let row = row(json!({"geo": {"country": "France"}}));
+        assert_eq!(Ok("{France}-7".to_string()), template.render(&[&row], 7));
+    }
+
+    #[test]
+    fn fields_are_found_in_later_sources() {
+        let template = IdTemplate::parse("{name}:{id}", Span::unknown()).unwrap();
+        let content = row(json!({"name": "Bob"}));
+        let input = row(json!({"id": "a", "name": "Alice"}));
+        assert_eq!(
+            Ok("Bob:a".to_string()),
+            template.render(&[&content, &input], 1)
+        );
+    }
+
+    #[test]
+    fn hash_is_stable_and_typed() {
+        let template = IdTemplate::parse("user::{hash:email, age}", Span::unknown()).unwrap();
+        let first = template
+            .render(&[&row(json!({"email": "a@b.c", "age": 1}))], 1)
+            .unwrap();
+        let again = template
+            .render(&[&row(json!({"age": 1, "email": "a@b.c", "x": 2}))], 2)
+            .unwrap();
+        let string_age = template
+            .render(&[&row(json!({"email": "a@b.c", "age": "1"}))], 1)
+            .unwrap();
+
+        assert_eq!(first, again);
+        assert_ne!(first, string_age);
+        assert_eq!("user::".len() + HASH_BYTES * 2, first.len());
+    }
+
+    #[test]
+    fn uuids_differ() {
+        let template = IdTemplate::parse("{uuid}", Span::unknown()).unwrap();
+        let row = row(json!({}));
+        assert_ne!(
+            template.render(&[&row], 1).unwrap(),
+            template.render(&[&row], 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn rows_without_a_key_are_rejected() {
+        let template = IdTemplate::parse("{type}::{id}", Span::unknown()).unwrap();
+        assert!(template
+            .render(&[&row(json!({"type": "route"}))], 1)
+            .is_err());
+        assert!(template
+            .render(&[&row(json!({"type": "route", "id": null}))], 1)
+            .is_err());
+        assert!(template
+            .render(&[&row(json!({"type": {}, "id": 1}))], 1)
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for template in ["airline", "{type", "type}", "{}", "{hash:}", "{{type}}"] {
+            assert!(
+                IdTemplate::parse(template, Span::unknown()).is_err(),
+                "{}",
+                template
+            );
+        }
+    }
+}
@@ -1,15 +1,24 @@
-use crate::cli::doc_common::{id_from_value, run_kv_mutations};
-use crate::cli::error::serialize_error;
+use crate::cli::doc_common::{
+    id_template_from_args, run_kv_mutations_with_hook, InvalidItem, MutationHook, MutationItem,
+    MISSING_DOC_ID,
+};
+use crate::cli::doc_import_readers::{is_compressed, read_rows, read_schema, ImportFormat, Rows};
+use crate::cli::error::{deserialize_error, generic_error, serialize_error};
 use crate::cli::util::convert_nu_value_to_json_value;
-use crate::client::KeyValueRequest;
+use crate::client::Durability;
+use crate::client::{DocumentFormat, KeyValueRequest};
 use crate::state::State;
 use nu_command::Open;
 use nu_engine::command_prelude::Call;
 use nu_engine::CallExt;
 use nu_protocol::engine::{Command, EngineState, Stack};
-use nu_protocol::{
-    Category, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
-};
+use nu_protocol::{Category, PipelineData, ShellError, Signature, Span, SyntaxShape, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
@@ -41,6 +50,12 @@ impl Command for DocImport {
                 "the name of the id column if used with an input stream",
                 None,
             )
+            .named(
+                "id-template",
+                SyntaxShape::String,
+                "build the id of each document from a template such as {type}::{id}, with {uuid}, {monotonic} and {hash:field,...} to generate ids",
+                None,
+            )
             .named(
                 "bucket",
                 SyntaxShape::String,
@@ -72,6 +87,51 @@ impl Command for DocImport {
                 "the maximum number of items to batch send at a time",
                 None,
             )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
+            .named(
+                "format",
+                SyntaxShape::String,
+                "the format of the file, one of json, ndjson, csv or tsv, for when it cannot be told from the extension",
+                None,
+            )
+            .named(
+                "schema",
+                SyntaxShape::Filepath,
+                "a JSON file of csv column names to their types, one of string, int, float, bool or json, other columns have their types inferred",
+                None,
+            )
+            .named(
+                "checkpoint",
+                SyntaxShape::Filepath,
+                "the file to record how many rows have been written to, so that the import can be resumed",
+                None,
+            )
+            .switch(
+                "resume",
+                "skip the rows already written according to the checkpoint file",
+                None,
+            )
+            .named(
+                "dead-letter",
+                SyntaxShape::Filepath,
+                "the file to write rows which failed to as NDJSON, with the error in an _error column which is ignored when imported again and lines which could not be read in a _raw column",
+                None,
+            )
             .category(Category::Custom("couchbase".to_string()))
     }
 
@@ -90,8 +150,21 @@ impl Command for DocImport {
     }
 }
 
-fn build_req(key: String, value: Vec<u8>, expiry: u32) -> KeyValueRequest {
-    KeyValueRequest::Set { key, value, expiry }
+fn build_req(
+    key: String,
+    value: Vec<u8>,
+    flags: u32,
+    expiry: u32,
+    _cas: u64,
+    durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Set {
+        key,
+        value,
+        flags,
+        expiry,
+        durability,
+    }
 }
 
 fn run_import(
@@ -102,50 +175,492 @@ fn run_import(
     input: PipelineData,
 ) -> Result<PipelineData, ShellError> {
     let span = call.head;
-    let open = Open;
-    let data = open.run(engine_state, stack, call, input)?;
 
+    let filename: String = call.req(engine_state, stack, 0)?;
+    let checkpoint: Option<String> = call.get_flag(engine_state, stack, "checkpoint")?;
+    let resume = call.has_flag(engine_state, stack, "resume")?;
+    let dead_letter: Option<String> = call.get_flag(engine_state, stack, "dead-letter")?;
+
+    let start = match (&checkpoint, resume) {
+        (None, true) => {
+            return Err(generic_error(
+                "Missing checkpoint",
+                "The resume flag requires a checkpoint file to resume from".to_string(),
+                span,
+            ));
+        }
+        (Some(path), true) => read_checkpoint(path, &filename, span)?,
+        _ => 0,
+    };
+
+    let format = match call.get_flag::<String>(engine_state, stack, "format")? {
+        Some(name) => Some(ImportFormat::from_name(&name).ok_or_else(|| {
+            generic_error(
+                format!("Unknown format {}", name),
+                "The format must be one of json, ndjson, csv or tsv".to_string(),
+                span,
+            )
+        })?),
+        None => None,
+    };
+    let schema_path: Option<String> = call.get_flag(engine_state, stack, "schema")?;
+
+    let cwd = engine_state.cwd(Some(stack))?;
+    let path = nu_path::expand_path_with(&filename, cwd, true);
+    let format = format.or_else(|| ImportFormat::from_path(&path));
+
+    let schema = match (schema_path, format) {
+        (Some(schema), Some(ImportFormat::Csv | ImportFormat::Tsv)) => {
+            read_schema(Path::new(&schema), span)?
+        }
+        (Some(_), _) => {
+            return Err(generic_error(
+                "Schema given for a file which is not CSV",
+                "The schema flag can only be used with csv or tsv files".to_string(),
+                span,
+            ));
+        }
+        (None, _) => HashMap::new(),
+    };
+
+    let rows: Rows = match format {
+        Some(format) => read_rows(&path, format, schema, span)?,
+        None => {
+            if is_compressed(&path).unwrap_or(false) {
+                return Err(generic_error(
+                    format!("Cannot tell the format of {}", filename),
+                    "Use the format flag to give the format of a compressed file".to_string(),
+                    span,
+                ));
+            }
+
+            // Other formats are read in full by open, rows which are not records are dropped.
+            let open = Open;
+            let data = open.run(engine_state, stack, call, input)?;
+            Box::new(data.into_iter().filter_map(move |i| match i {
+                Value::Record { .. } => match convert_nu_value_to_json_value(&i, span) {
+                    Ok(serde_json::Value::Object(row)) => Some(Ok(row)),
+                    _ => None,
+                },
+                _ => None,
+            }))
+        }
+    };
+
+    let id_template = id_template_from_args(engine_state, stack, call)?;
     let id_column = call
         .get_flag(engine_state, stack, "id-column")?
         .unwrap_or_else(|| String::from("id"));
 
-    let filtered = data
-        .into_iter()
-        .filter_map(move |i| {
-            let id_column = id_column.clone();
+    let items = rows
+        .enumerate()
+        // Rows are counted after those which cannot be imported are dropped, which is the same
+        // for every run over the same file.
+        .skip(start as usize)
+        .map(
+            move |(position, row)| -> Result<MutationItem, InvalidItem> {
+                let mut row = row?;
+                strip_dead_letter_error(&mut row);
+                let id = match &id_template {
+                    Some(template) => template.render(&[&row], position as u64 + 1),
+                    None => row
+                        .get(&id_column)
+                        .and_then(id_from_json)
+                        .ok_or_else(|| MISSING_DOC_ID.to_string()),
+                };
 
-            if let Value::Record { val, .. } = i {
-                let mut id = None;
-                let mut content = serde_json::Map::new();
-                for (k, v) in val.iter() {
-                    if k.clone() == id_column {
-                        id = id_from_value(v, span);
-                    }
-
-                    content.insert(k.clone(), convert_nu_value_to_json_value(v, span).ok()?);
-                }
-                return Some((id.unwrap_or("".into()), content));
+                let value =
+                    serde_json::to_vec(&row).map_err(|e| serialize_error(e.to_string(), span))?;
+                Ok((id, value, DocumentFormat::Json.flags(), 0))
+            },
+        );
+
+    let hook: Option<Box<dyn MutationHook>> = if checkpoint.is_some() || dead_letter.is_some() {
+        Some(Box::new(ImportProgress::new(
+            filename,
+            checkpoint,
+            dead_letter,
+            start,
+            resume,
+            span,
+        )?))
+    } else {
+        None
+    };
+
+    run_kv_mutations_with_hook(
+        state,
+        engine_state,
+        stack,
+        call,
+        span,
+        items,
+        build_req,
+        hook,
+    )
+}
+
+// strip_dead_letter_error removes the error that rows from a dead letter file failed with, a
+// column of the same name which doesn't look like one is left alone.
+fn strip_dead_letter_error(row: &mut serde_json::Map<String, serde_json::Value>) {
+    let is_dead_letter = match row.get(DEAD_LETTER_ERROR_COLUMN) {
+        Some(serde_json::Value::Object(error)) => {
+            error.len() == 3
+                && error.contains_key("message")
+                && error.contains_key("kind")
+                && error.contains_key("row")
+        }
+        _ => false,
+    };
+    if is_dead_letter {
+        row.remove(DEAD_LETTER_ERROR_COLUMN);
+    }
+}
+
+fn id_from_json(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+// The column holding the error of rows written to the dead letter file.
+const DEAD_LETTER_ERROR_COLUMN: &str = "_error";
+
+// The kind of error of requests which were cancelled by an interrupt.
+const CANCELLED_KIND: &str = "cancelled";
+
+// The column holding the text of input which could not be read as a row, in the dead letter file.
+const DEAD_LETTER_RAW_COLUMN: &str = "_raw";
+
+// How many rows are written between saves of the checkpoint.
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointFile {
+    file: String,
+    rows: u64,
+}
+
+fn read_checkpoint(path: &str, filename: &str, span: Span) -> Result<u64, ShellError> {
+    let contents = match fs::read(path) {
+        Ok(c) => c,
+        // Nothing has been written yet.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => {
+            return Err(generic_error(
+                format!("Failed to read checkpoint {}", path),
+                e.to_string(),
+                span,
+            ));
+        }
+    };
+
+    let checkpoint: CheckpointFile =
+        serde_json::from_slice(&contents).map_err(|e| deserialize_error(e.to_string(), span))?;
+    if checkpoint.file != filename {
+        return Err(generic_error(
+            format!("Checkpoint {} is for a different file", path),
+            format!(
+                "The checkpoint was written when importing {}, not {}",
+                checkpoint.file, filename
+            ),
+            span,
+        ));
+    }
+
+    Ok(checkpoint.rows)
+}
+
+// Watermark tracks the number of rows from the start of the input which have all been written,
+// as rows complete out of order.
+#[derive(Debug)]
+struct Watermark {
+    rows: u64,
+    ahead: BTreeSet<u64>,
+}
+
+impl Watermark {
+    fn new(rows: u64) -> Self {
+        Self {
+            rows,
+            ahead: BTreeSet::new(),
+        }
+    }
+
+    fn complete(&mut self, row: u64) {
+        self.ahead.insert(row);
+        while self.ahead.remove(&self.rows) {
+            self.rows += 1;
+        }
+    }
+
+    fn rows(&self) -> u64 {
+        self.rows
+    }
+}
+
+// ImportProgress saves a checkpoint of the rows written and writes rows which failed to the dead
+// letter file. Failed rows only count as written when there is a dead letter file to keep them.
+struct ImportProgress {
+    filename: String,
+    checkpoint: Option<String>,
+    dead_letter: Option<BufWriter<File>>,
+    start: u64,
+    watermark: Watermark,
+    saved: u64,
+    span: Span,
+}
+
+impl ImportProgress {
+    fn new(
+        filename: String,
+        checkpoint: Option<String>,
+        dead_letter: Option<String>,
+        start: u64,
+        resume: bool,
+        span: Span,
+    ) -> Result<Self, ShellError> {
+        let dead_letter = match dead_letter {
+            Some(path) => {
+                // Rows which failed before the import was resumed are kept.
+                let file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(resume)
+                    .truncate(!resume)
+                    .open(&path)
+                    .map_err(|e| {
+                        generic_error(
+                            format!("Failed to create dead letter file {}", path),
+                            e.to_string(),
+                            span,
+                        )
+                    })?;
+                Some(BufWriter::new(file))
             }
-            None
+            None => None,
+        };
+
+        Ok(Self {
+            filename,
+            checkpoint,
+            dead_letter,
+            start,
+            watermark: Watermark::new(start),
+            saved: start,
+            span,
         })
-        .collect::<Vec<(
-            String,
-            serde_json::Map<std::string::String, serde_json::Value>,
-        )>>();
+    }
 
-    let mut all_items = vec![];
-    for item in filtered {
-        let value =
-            serde_json::to_vec(&item.1).map_err(|e| serialize_error(e.to_string(), span))?;
+    fn write_dead_letter(
+        &mut self,
+        row: u64,
+        value: &[u8],
+        reason: &str,
+        kind: &str,
+    ) -> Result<(), ShellError> {
+        let span = self.span;
+        let writer = match self.dead_letter.as_mut() {
+            Some(w) => w,
+            None => return Ok(()),
+        };
 
-        all_items.push((item.0, value));
+        // Input which could not be read as a row, such as a line which is not JSON, is kept as the
+        // text it was read from.
+        let mut content = match serde_json::from_slice(value) {
+            Ok(serde_json::Value::Object(row)) => row,
+            _ if value.is_empty() => serde_json::Map::new(),
+            _ => {
+                let mut content = serde_json::Map::new();
+                content.insert(
+                    DEAD_LETTER_RAW_COLUMN.to_string(),
+                    json!(String::from_utf8_lossy(value)),
+                );
+                content
+            }
+        };
+        content.insert(
+            DEAD_LETTER_ERROR_COLUMN.to_string(),
+            json!({"message": reason, "kind": kind, "row": row}),
+        );
+
+        serde_json::to_writer(&mut *writer, &content)
+            .map_err(|e| serialize_error(e.to_string(), span))?;
+        writer
+            .write_all(b"\n")
+            .map_err(|e| dead_letter_error(e, span))
     }
 
-    let results = run_kv_mutations(state, engine_state, stack, call, span, all_items, build_req)?;
+    fn save(&mut self) -> Result<(), ShellError> {
+        // Failed rows must be in the dead letter file before they are skipped by a resume.
+        if let Some(writer) = self.dead_letter.as_mut() {
+            writer
+                .flush()
+                .map_err(|e| dead_letter_error(e, self.span))?;
+        }
+
+        let path = match &self.checkpoint {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let checkpoint = CheckpointFile {
+            file: self.filename.clone(),
+            rows: self.watermark.rows(),
+        };
+        let contents = serde_json::to_vec(&checkpoint)
+            .map_err(|e| serialize_error(e.to_string(), self.span))?;
+
+        // The checkpoint is replaced in one go so that it is never left half written.
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, contents)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| {
+                generic_error(
+                    format!("Failed to write checkpoint {}", path),
+                    e.to_string(),
+                    self.span,
+                )
+            })?;
+        self.saved = checkpoint.rows;
+
+        Ok(())
+    }
+}
+
+impl MutationHook for ImportProgress {
+    fn completed(
+        &mut self,
+        seq: u64,
+        value: &[u8],
+        error: Option<(&str, &str)>,
+    ) -> Result<(), ShellError> {
+        let row = self.start + seq;
+        if let Some((reason, kind)) = error {
+            // Rows cancelled by an interrupt were never written and rows which failed are only
+            // kept in a dead letter file, otherwise the row has to be written again on resume.
+            if kind == CANCELLED_KIND || self.dead_letter.is_none() {
+                return Ok(());
+            }
+            self.write_dead_letter(row, value, reason, kind)?;
+        }
+
+        self.watermark.complete(row);
+        if self.watermark.rows() - self.saved >= CHECKPOINT_INTERVAL {
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    fn finished(&mut self) -> Result<(), ShellError> {
+        self.save()
+    }
+}
+
+fn dead_letter_error(e: io::Error, span: Span) -> ShellError {
+    generic_error("Failed to write dead letter file", e.to_string(), span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watermark_waits_for_earlier_rows() {
+        let mut watermark = Watermark::new(10);
+        watermark.complete(11);
+        watermark.complete(13);
+        assert_eq!(10, watermark.rows());
+
+        watermark.complete(10);
+        assert_eq!(12, watermark.rows());
+
+        watermark.complete(12);
+        assert_eq!(14, watermark.rows());
+    }
+
+    #[test]
+    fn only_dead_letter_errors_are_stripped() {
+        let mut row = json!({"id": "a", "_error": {"message": "Bad line", "kind": "invalid_input", "row": 1}});
+        strip_dead_letter_error(row.as_object_mut().unwrap());
+        assert_eq!(json!({"id": "a"}), row);
+
+        for error in [
+            json!("disk full"),
+            json!({"message": "disk full", "code": 28}),
+            json!({"message": "Bad line", "kind": "invalid_input", "row": 1, "host": "a"}),
+        ] {
+            let mut row = json!({"id": "a", "_error": error});
+            let expected = row.clone();
+            strip_dead_letter_error(row.as_object_mut().unwrap());
+            assert_eq!(expected, row);
+        }
+    }
+
+    #[test]
+    fn cancelled_rows_are_written_again_on_resume() {
+        let path = std::env::temp_dir().join(format!("cbsh-dead-letter-{}", uuid::Uuid::new_v4()));
+        let mut progress = ImportProgress::new(
+            "rows.ndjson".to_string(),
+            None,
+            Some(path.display().to_string()),
+            0,
+            false,
+            Span::unknown(),
+        )
+        .unwrap();
+        progress.completed(0, b"{}", None).unwrap();
+        progress
+            .completed(1, b"{\"id\": \"b\"}", Some(("Cancelled", "cancelled")))
+            .unwrap();
+        progress.completed(2, b"{}", None).unwrap();
+        progress.finished().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(contents.is_empty());
+        assert_eq!(1, progress.watermark.rows());
+    }
+
+    #[test]
+    fn dead_letter_keeps_rows_which_could_not_be_read() {
+        let path = std::env::temp_dir().join(format!("cbsh-dead-letter-{}", uuid::Uuid::new_v4()));
+        let mut progress = ImportProgress::new(
+            "rows.ndjson".to_string(),
+            None,
+            Some(path.display().to_string()),
+            0,
+            false,
+            Span::unknown(),
+        )
+        .unwrap();
+        progress
+            .completed(
+                0,
+                b"{\"id\": \"a\"}",
+                Some(("Missing doc id", "missing_id")),
+            )
+            .unwrap();
+        progress
+            .completed(1, b"not json", Some(("Bad line", "invalid_input")))
+            .unwrap();
+        progress.finished().unwrap();
 
-    Ok(Value::List {
-        vals: results,
-        internal_span: call.head,
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let rows: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                json!({"id": "a", "_error": {"message": "Missing doc id", "kind": "missing_id", "row": 0}}),
+                json!({"_raw": "not json", "_error": {"message": "Bad line", "kind": "invalid_input", "row": 1}}),
+            ],
+            rows
+        );
     }
-    .into_pipeline_data())
 }
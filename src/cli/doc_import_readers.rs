@@ -0,0 +1,719 @@
+//! Streaming readers for the files imported by `doc import`. Rows are read one at a time so that
+//! files far larger than memory can be imported, and compressed files are read transparently.
+
+use crate::cli::doc_common::InvalidItem;
+use crate::cli::error::{deserialize_error, generic_error};
+use flate2::read::MultiGzDecoder;
+use nu_protocol::{ShellError, Span};
+use serde::Deserialize;
+use serde_json::{Map, Number};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use utilities::json_row_parser::JsonRowParser;
+
+pub(crate) type Row = Map<String, serde_json::Value>;
+
+// Rows which cannot be read fail with the text they were read from, where there is one.
+pub(crate) type Rows = Box<dyn Iterator<Item = Result<Row, InvalidItem>> + Send>;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ImportFormat {
+    // Json is a top level array of rows, or one or more objects.
+    Json,
+    Ndjson,
+    Csv,
+    Tsv,
+}
+
+impl ImportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            "csv" => Some(Self::Csv),
+            "tsv" => Some(Self::Tsv),
+            _ => None,
+        }
+    }
+
+    // from_path picks the format from the extension of the file, looking past any extension for
+    // compression such as data.ndjson.gz.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        let name = [".gz", ".gzip", ".zst", ".zstd"]
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext))
+            .unwrap_or(&name);
+        let ext = Path::new(name).extension()?.to_str()?;
+        Self::from_name(ext)
+    }
+}
+
+// ColumnType is the type that the cells of a CSV column are converted to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ColumnType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Json,
+}
+
+// read_schema reads a JSON object of CSV column names to their types.
+pub(crate) fn read_schema(
+    path: &Path,
+    span: Span,
+) -> Result<HashMap<String, ColumnType>, ShellError> {
+    let contents = fs::read(path).map_err(|e| {
+        generic_error(
+            format!("Failed to read schema {}", path.display()),
+            e.to_string(),
+            span,
+        )
+    })?;
+
+    serde_json::from_slice(&contents).map_err(|e| {
+        deserialize_error(
+            format!(
+                "Invalid schema, expected an object of column names to one of string, int, float, bool or json: {}",
+                e
+            ),
+            span,
+        )
+    })
+}
+
+// is_compressed is whether the file starts with a gzip or zstd header.
+pub(crate) fn is_compressed(path: &Path) -> io::Result<bool> {
+    let mut header = [0; 4];
+    let mut file = File::open(path)?;
+    let mut read = 0;
+    while read < header.len() {
+        match file.read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(header[..read].starts_with(GZIP_MAGIC) || header[..read].starts_with(ZSTD_MAGIC))
+}
+
+// open_decompressed opens the file, decompressing it when it starts with a gzip or zstd header
+// whatever the file is called.
+fn open_decompressed(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, File::open(path)?);
+    let header = reader.fill_buf()?;
+    if header.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(BufReader::with_capacity(
+            READ_BUFFER_SIZE,
+            MultiGzDecoder::new(reader),
+        )))
+    } else if header.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(BufReader::with_capacity(
+            READ_BUFFER_SIZE,
+            zstd::stream::read::Decoder::with_buffer(reader)?,
+        )))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+// read_rows streams the rows of the file in the given format. Rows which cannot be read are
+// returned as errors so that the rest of the file can still be imported.
+pub(crate) fn read_rows(
+    path: &Path,
+    format: ImportFormat,
+    schema: HashMap<String, ColumnType>,
+    span: Span,
+) -> Result<Rows, ShellError> {
+    let read_error = |e: io::Error| {
+        generic_error(
+            format!("Failed to read {}", path.display()),
+            e.to_string(),
+            span,
+        )
+    };
+
+    let mut reader = open_decompressed(path).map_err(read_error)?;
+    match format {
+        ImportFormat::Json => match first_byte(&mut reader).map_err(read_error)? {
+            Some(b'[') => Ok(Box::new(JsonArrayRows::new(reader, span))),
+            _ => Ok(Box::new(JsonObjectRows::new(reader, span))),
+        },
+        ImportFormat::Ndjson => Ok(Box::new(NdjsonRows {
+            reader,
+            buf: vec![],
+            line: 0,
+            done: false,
+            span,
+        })),
+        ImportFormat::Csv | ImportFormat::Tsv => {
+            let delimiter = if format == ImportFormat::Tsv {
+                b'\t'
+            } else {
+                b','
+            };
+            Ok(Box::new(CsvRows::new(reader, delimiter, schema, span)?))
+        }
+    }
+}
+
+// first_byte skips any leading whitespace and returns the first byte, without consuming it.
+fn first_byte(reader: &mut dyn BufRead) -> io::Result<Option<u8>> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let b = buf[i];
+                reader.consume(i);
+                return Ok(Some(b));
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+fn row_from_slice(value: &[u8], position: String, span: Span) -> Result<Row, InvalidItem> {
+    serde_json::from_slice(value).map_err(|e| InvalidItem {
+        error: deserialize_error(
+            format!("Failed to read {} as a JSON object: {}", position, e),
+            span,
+        ),
+        raw: value.to_vec(),
+    })
+}
+
+fn read_failed(e: io::Error, span: Span) -> ShellError {
+    generic_error("Failed to read the file", e.to_string(), span)
+}
+
+// JsonArrayRows reads the rows of a top level JSON array, splitting them out with the same parser
+// used for query results. The array is wrapped in an object so that it looks like a result set.
+struct JsonArrayRows {
+    reader: Box<dyn Read + Send>,
+    parser: JsonRowParser,
+    buf: Vec<u8>,
+    in_rows: bool,
+    row: u64,
+    done: bool,
+    span: Span,
+}
+
+impl JsonArrayRows {
+    fn new(reader: Box<dyn BufRead + Send>, span: Span) -> Self {
+        let reader = io::Cursor::new(&b"{\"rows\":"[..])
+            .chain(reader)
+            .chain(io::Cursor::new(&b"}"[..]));
+        Self {
+            reader: Box::new(reader),
+            parser: JsonRowParser::new(2),
+            buf: vec![0; READ_BUFFER_SIZE],
+            in_rows: false,
+            row: 0,
+            done: false,
+            span,
+        }
+    }
+}
+
+impl Iterator for JsonArrayRows {
+    type Item = Result<Row, InvalidItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.parser.parse_next() {
+                Ok(Some(value)) => {
+                    if !self.in_rows {
+                        self.in_rows = value.ends_with(b"[");
+                        continue;
+                    }
+                    if value == b"]" {
+                        self.done = true;
+                        return None;
+                    }
+                    if value.is_empty() {
+                        continue;
+                    }
+
+                    self.row += 1;
+                    return Some(row_from_slice(
+                        &value,
+                        format!("row {}", self.row),
+                        self.span,
+                    ));
+                }
+                Ok(None) => match self.reader.read(&mut self.buf) {
+                    Ok(0) => self.done = true,
+                    Ok(n) => self.parser.push(&self.buf[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(read_failed(e, self.span).into()));
+                    }
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// JsonObjectRows reads a file of one or more JSON objects, such as a single document.
+struct JsonObjectRows {
+    rows: serde_json::StreamDeserializer<
+        'static,
+        serde_json::de::IoRead<Box<dyn BufRead + Send>>,
+        Row,
+    >,
+    row: u64,
+    done: bool,
+    span: Span,
+}
+
+impl JsonObjectRows {
+    fn new(reader: Box<dyn BufRead + Send>, span: Span) -> Self {
+        Self {
+            rows: serde_json::Deserializer::from_reader(reader).into_iter(),
+            row: 0,
+            done: false,
+            span,
+        }
+    }
+}
+
+impl Iterator for JsonObjectRows {
+    type Item = Result<Row, InvalidItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        self.row += 1;
+        match self.rows.next()? {
+            Ok(row) => Some(Ok(row)),
+            // The position in the file is lost after an error, so nothing more can be read.
+            Err(e) => {
+                self.done = true;
+                Some(Err(deserialize_error(
+                    format!("Failed to read row {} as a JSON object: {}", self.row, e),
+                    self.span,
+                )
+                .into()))
+            }
+        }
+    }
+}
+
+// NdjsonRows reads a file with a JSON object on each line, blank lines are skipped. Lines are read
+// as bytes so that a line which isn't valid UTF-8 fails on its own.
+struct NdjsonRows {
+    reader: Box<dyn BufRead + Send>,
+    buf: Vec<u8>,
+    line: u64,
+    done: bool,
+    span: Span,
+}
+
+impl Iterator for NdjsonRows {
+    type Item = Result<Row, InvalidItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.line += 1;
+            self.buf.clear();
+            match self.reader.read_until(b'\n', &mut self.buf) {
+                Ok(0) => self.done = true,
+                Ok(_) => {
+                    let line = self.buf.trim_ascii();
+                    if !line.is_empty() {
+                        return Some(row_from_slice(
+                            line,
+                            format!("line {}", self.line),
+                            self.span,
+                        ));
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(read_failed(e, self.span).into()));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// CsvRows reads a file of delimited values with a header row. Cells are converted to the type
+// given for their column by the schema, or otherwise to the type they look like. Records are read
+// as bytes so that a row which can't be imported still has the text it was read from.
+struct CsvRows {
+    reader: csv::Reader<Box<dyn BufRead + Send>>,
+    record: csv::ByteRecord,
+    delimiter: u8,
+    headers: Vec<String>,
+    types: Vec<Option<ColumnType>>,
+    row: u64,
+    done: bool,
+    span: Span,
+}
+
+impl CsvRows {
+    fn new(
+        reader: Box<dyn BufRead + Send>,
+        delimiter: u8,
+        schema: HashMap<String, ColumnType>,
+        span: Span,
+    ) -> Result<Self, ShellError> {
+        // Rows with the wrong number of fields are failed on their own rather than by the reader.
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_reader(reader);
+        let headers: Vec<String> = reader
+            .headers()
+            .map_err(|e| deserialize_error(format!("Failed to read the header row: {}", e), span))?
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+
+        for column in schema.keys() {
+            if !headers.contains(column) {
+                return Err(generic_error(
+                    format!("Column {} is not in the file", column),
+                    "Every column in the schema must be in the header row of the file".to_string(),
+                    span,
+                ));
+            }
+        }
+        let types = headers.iter().map(|h| schema.get(h).copied()).collect();
+
+        Ok(Self {
+            reader,
+            record: csv::ByteRecord::new(),
+            delimiter,
+            headers,
+            types,
+            row: 0,
+            done: false,
+            span,
+        })
+    }
+
+    // raw_record writes the current record back out as it would appear in the file.
+    fn raw_record(&self) -> Vec<u8> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(vec![]);
+        if writer.write_byte_record(&self.record).is_err() {
+            return vec![];
+        }
+        let mut raw = writer.into_inner().unwrap_or_default();
+        if raw.last() == Some(&b'\n') {
+            raw.pop();
+        }
+        raw
+    }
+
+    fn invalid_row(&self, message: String) -> InvalidItem {
+        InvalidItem {
+            error: deserialize_error(message, self.span),
+            raw: self.raw_record(),
+        }
+    }
+}
+
+impl Iterator for CsvRows {
+    type Item = Result<Row, InvalidItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        self.row += 1;
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => {
+                self.done = true;
+                return None;
+            }
+            // Only reading the file can fail now, after which the position in it is lost.
+            Err(e) => {
+                self.done = true;
+                return Some(Err(deserialize_error(
+                    format!("Failed to read row {}: {}", self.row, e),
+                    self.span,
+                )
+                .into()));
+            }
+        }
+
+        if self.record.len() != self.headers.len() {
+            return Some(Err(self.invalid_row(format!(
+                "Row {} has {} fields but the header row has {}",
+                self.row,
+                self.record.len(),
+                self.headers.len()
+            ))));
+        }
+
+        let mut row = Row::new();
+        for ((header, ty), cell) in self.headers.iter().zip(&self.types).zip(&self.record) {
+            let cell = match std::str::from_utf8(cell) {
+                Ok(cell) => cell,
+                Err(_) => {
+                    return Some(Err(self.invalid_row(format!(
+                        "Invalid {} in row {}: not valid UTF-8",
+                        header, self.row
+                    ))));
+                }
+            };
+            let value = match ty {
+                Some(ty) => match typed_cell(cell, *ty) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return Some(Err(self.invalid_row(format!(
+                            "Invalid {} in row {}: {}",
+                            header, self.row, e
+                        ))));
+                    }
+                },
+                None => infer_cell(cell),
+            };
+            row.insert(header.clone(), value);
+        }
+
+        Some(Ok(row))
+    }
+}
+
+// infer_cell converts a cell to a number or bool if it looks like one. Numbers with leading zeros,
+// such as zip codes, are kept as strings so that they are not changed.
+fn infer_cell(cell: &str) -> serde_json::Value {
+    if cell.is_empty() {
+        return serde_json::Value::Null;
+    }
+
+    let digits = cell.strip_prefix('-').unwrap_or(cell);
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.");
+    if !leading_zero {
+        if let Ok(i) = cell.parse::<i64>() {
+            return i.into();
+        }
+        if let Some(n) = cell.parse::<f64>().ok().and_then(Number::from_f64) {
+            return n.into();
+        }
+    }
+
+    match cell {
+        "true" => true.into(),
+        "false" => false.into(),
+        _ => cell.into(),
+    }
+}
+
+fn typed_cell(cell: &str, ty: ColumnType) -> Result<serde_json::Value, String> {
+    if cell.is_empty() && ty != ColumnType::String {
+        return Ok(serde_json::Value::Null);
+    }
+
+    match ty {
+        ColumnType::String => Ok(cell.into()),
+        ColumnType::Int => cell
+            .parse::<i64>()
+            .map(Into::into)
+            .map_err(|e| e.to_string()),
+        ColumnType::Float => cell
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Into::into)
+            .ok_or_else(|| format!("{} is not a number", cell)),
+        ColumnType::Bool => match cell.to_lowercase().as_str() {
+            "true" => Ok(true.into()),
+            "false" => Ok(false.into()),
+            _ => Err(format!("{} is not true or false", cell)),
+        },
+        ColumnType::Json => serde_json::from_str(cell).map_err(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn rows_of(contents: &[u8], format: ImportFormat) -> Vec<Result<Row, InvalidItem>> {
+        rows_with_schema(contents, format, HashMap::new())
+    }
+
+    fn rows_with_schema(
+        contents: &[u8],
+        format: ImportFormat,
+        schema: HashMap<String, ColumnType>,
+    ) -> Vec<Result<Row, InvalidItem>> {
+        let path = std::env::temp_dir().join(format!("cbsh-import-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, contents).unwrap();
+        let rows = read_rows(&path, format, schema, Span::unknown())
+            .unwrap()
+            .collect();
+        fs::remove_file(&path).unwrap();
+        rows
+    }
+
+    fn values(rows: Vec<Result<Row, InvalidItem>>) -> Vec<serde_json::Value> {
+        rows.into_iter()
+            .map(|r| serde_json::Value::Object(r.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn format_is_read_past_compression_extension() {
+        assert_eq!(
+            Some(ImportFormat::Ndjson),
+            ImportFormat::from_path(Path::new("data.ndjson.gz"))
+        );
+        assert_eq!(
+            Some(ImportFormat::Csv),
+            ImportFormat::from_path(Path::new("/tmp/DATA.CSV.zst"))
+        );
+        assert_eq!(None, ImportFormat::from_path(Path::new("data.gz")));
+        assert_eq!(None, ImportFormat::from_path(Path::new("data.yaml")));
+    }
+
+    #[test]
+    fn reads_a_json_array() {
+        let rows = rows_of(
+            b" [\n{\"id\": \"a\", \"tags\": [1, 2], \"s\": \"x, y]\"},\n{\"id\": \"b\"}\n]\n",
+            ImportFormat::Json,
+        );
+        assert_eq!(
+            vec![
+                json!({"id": "a", "tags": [1, 2], "s": "x, y]"}),
+                json!({"id": "b"})
+            ],
+            values(rows)
+        );
+        assert!(rows_of(b"[]", ImportFormat::Json).is_empty());
+    }
+
+    #[test]
+    fn reads_json_objects() {
+        let rows = rows_of(b"{\"id\": \"a\"}\n{\"id\": \"b\"}", ImportFormat::Json);
+        assert_eq!(vec![json!({"id": "a"}), json!({"id": "b"})], values(rows));
+    }
+
+    #[test]
+    fn bad_ndjson_lines_fail_alone() {
+        let rows = rows_of(
+            b"{\"id\": \"a\"}\n\nnot json\n{\"id\": \"b\"}\n",
+            ImportFormat::Ndjson,
+        );
+        assert_eq!(3, rows.len());
+        assert_eq!(b"not json".to_vec(), rows[1].as_ref().unwrap_err().raw);
+        assert_eq!(json!({"id": "b"}), json!(rows[2].as_ref().unwrap()));
+    }
+
+    #[test]
+    fn ndjson_lines_which_are_not_utf8_fail_alone() {
+        let rows = rows_of(
+            b"{\"id\": \"a\"}\r\n{\"id\": \"\xff\"}\r\n{\"id\": \"b\"}",
+            ImportFormat::Ndjson,
+        );
+        assert_eq!(3, rows.len());
+        assert_eq!(json!({"id": "a"}), json!(rows[0].as_ref().unwrap()));
+        assert_eq!(
+            b"{\"id\": \"\xff\"}".to_vec(),
+            rows[1].as_ref().unwrap_err().raw
+        );
+        assert_eq!(json!({"id": "b"}), json!(rows[2].as_ref().unwrap()));
+    }
+
+    #[test]
+    fn reads_gzip_and_zstd() {
+        let contents = b"{\"id\": \"a\"}\n{\"id\": \"b\"}\n";
+
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(contents).unwrap();
+        let rows = rows_of(&gzip.finish().unwrap(), ImportFormat::Ndjson);
+        assert_eq!(vec![json!({"id": "a"}), json!({"id": "b"})], values(rows));
+
+        let zstd = zstd::encode_all(&contents[..], 0).unwrap();
+        let rows = rows_of(&zstd, ImportFormat::Ndjson);
+        assert_eq!(vec![json!({"id": "a"}), json!({"id": "b"})], values(rows));
+    }
+
+    #[test]
+    fn csv_types_are_inferred() {
+        let rows = rows_of(
+            b"id,age,price,active,zip,name,empty\na,42,1.5,true,01234,Bob,\n",
+            ImportFormat::Csv,
+        );
+        assert_eq!(
+            vec![
+                json!({"id": "a", "age": 42, "price": 1.5, "active": true, "zip": "01234", "name": "Bob", "empty": null})
+            ],
+            values(rows)
+        );
+    }
+
+    #[test]
+    fn csv_schema_sets_types() {
+        let schema = HashMap::from([
+            ("id".to_string(), ColumnType::String),
+            ("age".to_string(), ColumnType::Float),
+            ("tags".to_string(), ColumnType::Json),
+        ]);
+        let rows = rows_with_schema(
+            b"id\tage\ttags\n1\t42\t[\"a\"]\n2\told\t[]\n",
+            ImportFormat::Tsv,
+            schema,
+        );
+        assert_eq!(2, rows.len());
+        assert_eq!(
+            json!({"id": "1", "age": 42.0, "tags": ["a"]}),
+            json!(rows[0].as_ref().unwrap())
+        );
+        assert_eq!(b"2\told\t[]".to_vec(), rows[1].as_ref().unwrap_err().raw);
+    }
+
+    #[test]
+    fn bad_csv_rows_keep_their_text() {
+        let rows = rows_of(
+            b"id,name\na,\"Smith, Bob\"\nb\nc,\xff\nd,Eve\n",
+            ImportFormat::Csv,
+        );
+        assert_eq!(4, rows.len());
+        assert_eq!(
+            json!({"id": "a", "name": "Smith, Bob"}),
+            json!(rows[0].as_ref().unwrap())
+        );
+        assert_eq!(b"b".to_vec(), rows[1].as_ref().unwrap_err().raw);
+        assert_eq!(b"c,\xff".to_vec(), rows[2].as_ref().unwrap_err().raw);
+        assert_eq!(
+            json!({"id": "d", "name": "Eve"}),
+            json!(rows[3].as_ref().unwrap())
+        );
+    }
+}
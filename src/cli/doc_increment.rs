@@ -0,0 +1,139 @@
+//! The `doc increment` command performs a KV increment operation.
+
+use crate::cli::doc_common::run_kv_counter_ops;
+use crate::state::State;
+use nu_engine::command_prelude::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
+};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct DocIncrement {
+    state: Arc<Mutex<State>>,
+}
+
+impl DocIncrement {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for DocIncrement {
+    fn name(&self) -> &str {
+        "doc increment"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("doc increment")
+            .optional("id", SyntaxShape::String, "the document id")
+            .named(
+                "id-column",
+                SyntaxShape::String,
+                "the name of the id column if used with an input stream",
+                None,
+            )
+            .named(
+                "delta",
+                SyntaxShape::Int,
+                "the amount to increment the counter by, defaults to 1",
+                None,
+            )
+            .named(
+                "initial",
+                SyntaxShape::Int,
+                "the value to create the counter with if it does not exist",
+                None,
+            )
+            .named(
+                "expiry",
+                SyntaxShape::Number,
+                "the expiry for counters created with the initial value in seconds, or absolute",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .named(
+                "batch-size",
+                SyntaxShape::Number,
+                "the maximum number of items to batch send at a time",
+                None,
+            )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
+            .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add the server duration, round trip, node and vbucket to each result",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Increments a counter document through the data service"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        let results =
+            run_kv_counter_ops(self.state.clone(), engine_state, stack, call, input, true)?;
+
+        Ok(Value::List {
+            vals: results,
+            internal_span: call.head,
+        }
+        .into_pipeline_data())
+    }
+
+    fn requires_ast_for_arguments(&self) -> bool {
+        true
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Increments the counter with the ID page_views by one",
+                example: "doc increment page_views",
+                result: None,
+            },
+            Example {
+                description: "Increments the counter by 10, creating it with the value 100 if it does not exist",
+                example: "doc increment page_views --delta 10 --initial 100",
+                result: None,
+            },
+            Example {
+                description: "Increments multiple counters with IDs from the previous command",
+                example: "[views_1 views_2] | doc increment",
+                result: None,
+            },
+        ]
+    }
+}
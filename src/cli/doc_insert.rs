@@ -1,15 +1,14 @@
 //! The `doc insert` command performs a KV insert operation.
 
 use crate::cli::doc_common::run_kv_store_ops;
+use crate::client::Durability;
 use crate::client::KeyValueRequest;
 use crate::state::State;
 use std::sync::{Arc, Mutex};
 
 use nu_engine::command_prelude::Call;
 use nu_protocol::engine::{Command, EngineState, Stack};
-use nu_protocol::{
-    Category, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
-};
+use nu_protocol::{Category, PipelineData, ShellError, Signature, SyntaxShape};
 
 #[derive(Clone)]
 pub struct DocInsert {
@@ -49,6 +48,12 @@ impl Command for DocInsert {
                 "the name of the content column if used with an input stream",
                 None,
             )
+            .named(
+                "format",
+                SyntaxShape::String,
+                "how to store the content, one of json, string or binary, binary content is stored as binary by default and anything else as json",
+                None,
+            )
             .named(
                 "expiry",
                 SyntaxShape::Number,
@@ -74,7 +79,23 @@ impl Command for DocInsert {
                 "the maximum number of items to batch send at a time",
                 None,
             )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
             .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
             .category(Category::Custom("couchbase".to_string()))
     }
 
@@ -93,8 +114,21 @@ impl Command for DocInsert {
     }
 }
 
-fn build_req(key: String, value: Vec<u8>, expiry: u32) -> KeyValueRequest {
-    KeyValueRequest::Insert { key, value, expiry }
+fn build_req(
+    key: String,
+    value: Vec<u8>,
+    flags: u32,
+    expiry: u32,
+    _cas: u64,
+    durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Insert {
+        key,
+        value,
+        flags,
+        expiry,
+        durability,
+    }
 }
 
 fn run_insert(
@@ -104,11 +138,5 @@ fn run_insert(
     call: &Call,
     input: PipelineData,
 ) -> Result<PipelineData, ShellError> {
-    let results = run_kv_store_ops(state, engine_state, stack, call, input, build_req)?;
-
-    Ok(Value::List {
-        vals: results,
-        internal_span: call.head,
-    }
-    .into_pipeline_data())
+    run_kv_store_ops(state, engine_state, stack, call, input, build_req)
 }
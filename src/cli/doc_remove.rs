@@ -1,24 +1,14 @@
 //! The `doc remove` command performs a KV remove operation.
 
-use crate::cli::doc_common::{
-    build_batched_kv_items, get_active_cluster_client_cid, process_kv_workers, MutationResult,
-};
-use crate::cli::doc_get::ids_from_input;
-use crate::cli::util::cluster_identifiers_from;
+use crate::cli::doc_common::{ids_and_cas_from_input, run_kv_mutations};
+use crate::client::Durability;
 use crate::client::KeyValueRequest;
 use crate::state::State;
-use futures::stream::FuturesUnordered;
 use nu_engine::command_prelude::Call;
 use nu_engine::CallExt;
 use nu_protocol::engine::{Command, EngineState, Stack};
-use nu_protocol::{
-    Category, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
-};
-use std::collections::HashSet;
-use std::ops::Add;
+use nu_protocol::{Category, PipelineData, ShellError, Signature, SyntaxShape};
 use std::sync::{Arc, Mutex};
-use tokio::runtime::Runtime;
-use tokio::time::Instant;
 
 #[derive(Clone)]
 pub struct DocRemove {
@@ -70,7 +60,29 @@ impl Command for DocRemove {
                 "the maximum number of items to batch send at a time",
                 None,
             )
+            .named(
+                "cas",
+                SyntaxShape::Int,
+                "only remove the document if its CAS matches, a cas column in the input stream takes precedence",
+                None,
+            )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
             .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
             .category(Category::Custom("couchbase".to_string()))
     }
 
@@ -93,6 +105,21 @@ impl Command for DocRemove {
     }
 }
 
+fn build_req(
+    key: String,
+    _value: Vec<u8>,
+    _flags: u32,
+    _expiry: u32,
+    cas: u64,
+    durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Remove {
+        key,
+        cas,
+        durability,
+    }
+}
+
 fn run_remove(
     state: Arc<Mutex<State>>,
     engine_state: &EngineState,
@@ -100,98 +127,26 @@ fn run_remove(
     call: &Call,
     input: PipelineData,
 ) -> Result<PipelineData, ShellError> {
-    let span = call.head;
-    let signals = engine_state.signals().clone();
-
     let id_column = call
         .get_flag(engine_state, stack, "id-column")?
         .unwrap_or_else(|| String::from("id"));
 
-    let ids = ids_from_input(input, id_column.clone(), call.positional_nth(stack, 0))?;
-    let batch_size: Option<i64> = call.get_flag(engine_state, stack, "batch-size")?;
-    let mut all_ids: Vec<Vec<String>> = vec![];
-    if let Some(size) = batch_size {
-        all_ids = build_batched_kv_items(size as u32, ids.clone());
-    }
-
-    let bucket_flag = call.get_flag(engine_state, stack, "bucket")?;
-    let scope_flag = call.get_flag(engine_state, stack, "scope")?;
-    let collection_flag = call.get_flag(engine_state, stack, "collection")?;
-    let halt_on_error = call.has_flag(engine_state, stack, "halt-on-error")?;
-
-    let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
-
-    let guard = state.lock().unwrap();
-
-    let mut results = vec![];
-    for identifier in cluster_identifiers {
-        let rt = Runtime::new().unwrap();
-        let (active_cluster, client, cid) = match get_active_cluster_client_cid(
-            &rt,
-            identifier.clone(),
-            &guard,
-            bucket_flag.clone(),
-            scope_flag.clone(),
-            collection_flag.clone(),
-            signals.clone(),
-            span,
-        ) {
-            Ok(c) => c,
-            Err(e) => {
-                if halt_on_error {
-                    return Err(e);
-                }
-
-                let mut failures = HashSet::new();
-                failures.insert(e.to_string());
-                let collected = MutationResult::new(identifier.clone())
-                    .fail_reasons(failures)
-                    .into_value(call.head);
-                results.push(collected);
-                continue;
-            }
-        };
-
-        if all_ids.is_empty() {
-            all_ids = build_batched_kv_items(active_cluster.kv_batch_size(), ids.clone());
-        }
-
-        let mut workers = FuturesUnordered::new();
-        let mut success = 0;
-        let mut failed = 0;
-        let mut fail_reasons: HashSet<String> = HashSet::new();
-        for items in all_ids.clone() {
-            for item in items.clone() {
-                let deadline = Instant::now().add(active_cluster.timeouts().data_timeout());
-                let signal = signals.clone();
-                let client = client.clone();
-
-                workers.push(async move {
-                    client
-                        .request(KeyValueRequest::Remove { key: item }, cid, deadline, signal)
-                        .await
-                });
-            }
-
-            let worked = process_kv_workers(workers, &rt, halt_on_error, span)?;
-
-            success += worked.success;
-            failed += worked.failed;
-            fail_reasons.extend(worked.fail_reasons);
-            workers = FuturesUnordered::new()
-        }
-
-        let collected = MutationResult::new(identifier.clone())
-            .success(success)
-            .failed(failed)
-            .fail_reasons(fail_reasons);
-
-        results.push(collected.into_value(span));
-    }
-
-    Ok(Value::List {
-        vals: results,
-        internal_span: span,
-    }
-    .into_pipeline_data())
+    let cas: Option<i64> = call.get_flag(engine_state, stack, "cas")?;
+    let all_items = ids_and_cas_from_input(
+        input,
+        id_column,
+        call.positional_nth(stack, 0),
+        cas.unwrap_or(0) as u64,
+    )
+    .map(|(id, cas)| Ok((id, vec![], 0, cas)));
+
+    run_kv_mutations(
+        state,
+        engine_state,
+        stack,
+        call,
+        call.head,
+        all_items,
+        build_req,
+    )
 }
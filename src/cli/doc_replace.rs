@@ -1,15 +1,14 @@
 //! The `doc replace` command performs a KV replace operation.
 
 use crate::cli::doc_common::run_kv_store_ops;
+use crate::client::Durability;
 use crate::client::KeyValueRequest;
 use crate::state::State;
 use std::sync::{Arc, Mutex};
 
 use nu_engine::command_prelude::Call;
 use nu_protocol::engine::{Command, EngineState, Stack};
-use nu_protocol::{
-    Category, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
-};
+use nu_protocol::{Category, PipelineData, ShellError, Signature, SyntaxShape};
 
 #[derive(Clone)]
 pub struct DocReplace {
@@ -49,6 +48,12 @@ impl Command for DocReplace {
                 "the name of the content column if used with an input stream",
                 None,
             )
+            .named(
+                "format",
+                SyntaxShape::String,
+                "how to store the content, one of json, string or binary, binary content is stored as binary by default and anything else as json",
+                None,
+            )
             .named(
                 "expiry",
                 SyntaxShape::Number,
@@ -74,7 +79,29 @@ impl Command for DocReplace {
                 "the maximum number of items to batch send at a time",
                 None,
             )
+            .named(
+                "cas",
+                SyntaxShape::Int,
+                "only replace the document if its CAS matches, a cas column in the input stream takes precedence",
+                None,
+            )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
             .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
             .category(Category::Custom("couchbase".to_string()))
     }
 
@@ -93,8 +120,22 @@ impl Command for DocReplace {
     }
 }
 
-fn build_req(key: String, value: Vec<u8>, expiry: u32) -> KeyValueRequest {
-    KeyValueRequest::Replace { key, value, expiry }
+fn build_req(
+    key: String,
+    value: Vec<u8>,
+    flags: u32,
+    expiry: u32,
+    cas: u64,
+    durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Replace {
+        key,
+        value,
+        flags,
+        expiry,
+        cas,
+        durability,
+    }
 }
 
 fn run_replace(
@@ -104,11 +145,5 @@ fn run_replace(
     call: &Call,
     input: PipelineData,
 ) -> Result<PipelineData, ShellError> {
-    let results = run_kv_store_ops(state, engine_state, stack, call, input, build_req)?;
-
-    Ok(Value::List {
-        vals: results,
-        internal_span: call.head,
-    }
-    .into_pipeline_data())
+    run_kv_store_ops(state, engine_state, stack, call, input, build_req)
 }
@@ -0,0 +1,307 @@
+//! The `doc scan` command iterates the documents of a collection using KV range scans.
+
+use super::util::convert_json_value_to_nu_value;
+use crate::state::State;
+
+use crate::cli::doc_common::get_active_cluster_client_cid;
+use crate::cli::error::{client_error_to_shell_error, generic_error};
+use crate::cli::util::{cluster_identifiers_from, NuValueMap};
+use crate::client::{ClientError, RangeScan, ScanItem, ScanType};
+use log::debug;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, ListStream, PipelineData, ShellError, Signature, Span, SyntaxShape, Value,
+};
+
+// The number of vbuckets scanned at once when no concurrency is given.
+const DEFAULT_CONCURRENCY: usize = 1;
+
+#[derive(Clone)]
+pub struct DocScan {
+    state: Arc<Mutex<State>>,
+}
+
+impl DocScan {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for DocScan {
+    fn name(&self) -> &str {
+        "doc scan"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("doc scan")
+            .named(
+                "prefix",
+                SyntaxShape::String,
+                "only scan documents with ids starting with the prefix",
+                None,
+            )
+            .named(
+                "from",
+                SyntaxShape::String,
+                "the document id to start scanning from (inclusive)",
+                None,
+            )
+            .named(
+                "to",
+                SyntaxShape::String,
+                "the document id to stop scanning at (inclusive)",
+                None,
+            )
+            .named(
+                "sample",
+                SyntaxShape::Int,
+                "scan a random sample of up to this many documents",
+                None,
+            )
+            .named(
+                "seed",
+                SyntaxShape::Int,
+                "the seed to use for random sampling",
+                None,
+            )
+            .switch("ids-only", "only return the document ids", None)
+            .named(
+                "concurrency",
+                SyntaxShape::Int,
+                "the number of vbuckets to scan concurrently",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Scans the documents of a collection through the data service"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_scan(self.state.clone(), engine_state, stack, call, input)
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Scans every document in the collection",
+                example: "doc scan",
+                result: None,
+            },
+            Example {
+                description: "Scans the ids of the documents with ids starting with airline_",
+                example: "doc scan --prefix airline_ --ids-only",
+                result: None,
+            },
+            Example {
+                description: "Scans the documents with ids between airline_1 and airline_2, 4 vbuckets at a time",
+                example: "doc scan --from airline_1 --to airline_2 --concurrency 4",
+                result: None,
+            },
+            Example {
+                description: "Fetches a random sample of 10 documents",
+                example: "doc scan --sample 10",
+                result: None,
+            },
+        ]
+    }
+}
+
+fn run_scan(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    _input: PipelineData,
+) -> Result<PipelineData, ShellError> {
+    let span = call.head;
+    let signals = engine_state.signals().clone();
+
+    let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
+
+    let bucket_flag = call.get_flag(engine_state, stack, "bucket")?;
+    let scope_flag = call.get_flag(engine_state, stack, "scope")?;
+    let collection_flag = call.get_flag(engine_state, stack, "collection")?;
+    let prefix: Option<String> = call.get_flag(engine_state, stack, "prefix")?;
+    let from: Option<String> = call.get_flag(engine_state, stack, "from")?;
+    let to: Option<String> = call.get_flag(engine_state, stack, "to")?;
+    let sample: Option<i64> = call.get_flag(engine_state, stack, "sample")?;
+    let seed: Option<i64> = call.get_flag(engine_state, stack, "seed")?;
+    let ids_only = call.has_flag(engine_state, stack, "ids-only")?;
+    let concurrency = match call.get_flag::<i64>(engine_state, stack, "concurrency")? {
+        Some(c) if c < 1 => {
+            return Err(generic_error(
+                format!("Invalid concurrency {}", c),
+                "The concurrency must be at least 1".to_string(),
+                span,
+            ));
+        }
+        Some(c) => c as usize,
+        None => DEFAULT_CONCURRENCY,
+    };
+
+    let scan_type = match (prefix, sample) {
+        (Some(_), _) if from.is_some() || to.is_some() => {
+            return Err(generic_error(
+                "Invalid flags",
+                "The prefix flag cannot be used with the from or to flags".to_string(),
+                span,
+            ));
+        }
+        (Some(_), Some(_)) => {
+            return Err(generic_error(
+                "Invalid flags",
+                "The sample flag cannot be used with the prefix flag".to_string(),
+                span,
+            ));
+        }
+        (Some(prefix), None) => ScanType::prefix(prefix),
+        (None, Some(_)) if from.is_some() || to.is_some() => {
+            return Err(generic_error(
+                "Invalid flags",
+                "The sample flag cannot be used with the from or to flags".to_string(),
+                span,
+            ));
+        }
+        (None, Some(samples)) if samples < 1 => {
+            return Err(generic_error(
+                format!("Invalid sample size {}", samples),
+                "The sample size must be at least 1".to_string(),
+                span,
+            ));
+        }
+        (None, Some(samples)) => ScanType::Sampling {
+            samples: samples as u64,
+            seed: seed.map(|s| s as u64).unwrap_or_else(rand::random),
+        },
+        (None, None) => ScanType::range(from, to),
+    };
+    if seed.is_some() && sample.is_none() {
+        return Err(generic_error(
+            "Invalid flags",
+            "The seed flag can only be used with the sample flag".to_string(),
+            span,
+        ));
+    }
+
+    let guard = state.lock().unwrap();
+
+    let mut scans = VecDeque::new();
+    for identifier in cluster_identifiers {
+        let rt = Runtime::new().unwrap();
+        let (active_cluster, client, cid) = get_active_cluster_client_cid(
+            &rt,
+            identifier.clone(),
+            &guard,
+            bucket_flag.clone(),
+            scope_flag.clone(),
+            collection_flag.clone(),
+            signals.clone(),
+            span,
+        )?;
+
+        debug!("Running kv range scan {:?} on {}", &scan_type, &identifier);
+
+        let scan = RangeScan {
+            scan_type: scan_type.clone(),
+            ids_only,
+        };
+        let items = client.range_scan(
+            scan,
+            cid,
+            concurrency,
+            active_cluster.timeouts().data_timeout(),
+            signals.clone(),
+        );
+        scans.push_back((identifier, items));
+    }
+
+    let stream = ScanStream {
+        scans,
+        ids_only,
+        span,
+    };
+
+    Ok(PipelineData::from(ListStream::new(stream, span, signals)))
+}
+
+// ScanStream yields the items of the scans of each cluster in turn.
+struct ScanStream {
+    scans: VecDeque<(String, mpsc::Receiver<Result<ScanItem, ClientError>>)>,
+    ids_only: bool,
+    span: Span,
+}
+
+impl Iterator for ScanStream {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (cluster, items) = self.scans.front_mut()?;
+            let item = match items.blocking_recv() {
+                Some(Ok(item)) => item,
+                Some(Err(e)) => {
+                    return Some(Value::error(
+                        client_error_to_shell_error(e, self.span),
+                        self.span,
+                    ));
+                }
+                None => {
+                    self.scans.pop_front();
+                    continue;
+                }
+            };
+
+            let mut collected = NuValueMap::default();
+            collected.add_string("id", item.key, self.span);
+            if !self.ids_only {
+                let content = item.content.unwrap_or_default();
+                let content = match serde_json::from_slice(&content) {
+                    Ok(json) => match convert_json_value_to_nu_value(&json, self.span) {
+                        Ok(c) => c,
+                        Err(e) => return Some(Value::error(e, self.span)),
+                    },
+                    Err(_) => Value::string(String::from_utf8_lossy(&content), self.span),
+                };
+                collected.add("content", content);
+                collected.add_i64("cas", item.cas as i64, self.span);
+            }
+            collected.add_string("cluster", cluster.clone(), self.span);
+
+            return Some(collected.into_value(self.span));
+        }
+    }
+}
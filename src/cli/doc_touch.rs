@@ -0,0 +1,151 @@
+//! The `doc touch` command performs a KV touch operation.
+
+use crate::cli::doc_common::{ids_and_cas_from_input, run_kv_mutations};
+use crate::client::Durability;
+use crate::client::KeyValueRequest;
+use crate::state::State;
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct DocTouch {
+    state: Arc<Mutex<State>>,
+}
+
+impl DocTouch {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for DocTouch {
+    fn name(&self) -> &str {
+        "doc touch"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("doc touch")
+            .optional("id", SyntaxShape::String, "the document id")
+            .required_named(
+                "expiry",
+                SyntaxShape::Number,
+                "the new expiry for the documents in seconds, or absolute, 0 removes the expiry",
+                None,
+            )
+            .named(
+                "id-column",
+                SyntaxShape::String,
+                "the name of the id column if used with an input stream",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .named(
+                "batch-size",
+                SyntaxShape::Number,
+                "the maximum number of items to batch send at a time",
+                None,
+            )
+            .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Updates the expiry of documents through the data service without changing their content"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_touch(self.state.clone(), engine_state, stack, call, input)
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Sets the document with the ID session_123 to expire in an hour",
+                example: "doc touch session_123 --expiry 3600",
+                result: None,
+            },
+            Example {
+                description: "Extends the expiry of every session document to a day from now",
+                example: "query 'SELECT meta().id FROM `travel-sample`.sessions.active' | doc touch --expiry 86400",
+                result: None,
+            },
+        ]
+    }
+
+    fn requires_ast_for_arguments(&self) -> bool {
+        true
+    }
+}
+
+fn build_req(
+    key: String,
+    _value: Vec<u8>,
+    _flags: u32,
+    expiry: u32,
+    _cas: u64,
+    _durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Touch { key, expiry }
+}
+
+fn run_touch(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    input: PipelineData,
+) -> Result<PipelineData, ShellError> {
+    let id_column = call
+        .get_flag(engine_state, stack, "id-column")?
+        .unwrap_or_else(|| String::from("id"));
+
+    let all_items = ids_and_cas_from_input(input, id_column, call.positional_nth(stack, 0), 0)
+        .map(|(id, _)| Ok((id, vec![], 0, 0)));
+
+    run_kv_mutations(
+        state,
+        engine_state,
+        stack,
+        call,
+        call.head,
+        all_items,
+        build_req,
+    )
+}
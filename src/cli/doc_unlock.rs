@@ -0,0 +1,162 @@
+//! The `doc unlock` command performs a KV unlock operation.
+
+use crate::cli::doc_common::{ids_and_cas_from_input, run_kv_mutations};
+use crate::cli::error::generic_error;
+use crate::client::Durability;
+use crate::client::KeyValueRequest;
+use crate::state::State;
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct DocUnlock {
+    state: Arc<Mutex<State>>,
+}
+
+impl DocUnlock {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for DocUnlock {
+    fn name(&self) -> &str {
+        "doc unlock"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("doc unlock")
+            .optional("id", SyntaxShape::String, "the document id")
+            .named(
+                "cas",
+                SyntaxShape::Int,
+                "the CAS returned when the document was locked, a cas column in the input stream takes precedence",
+                None,
+            )
+            .named(
+                "id-column",
+                SyntaxShape::String,
+                "the name of the id column if used with an input stream",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .named(
+                "batch-size",
+                SyntaxShape::Number,
+                "the maximum number of items to batch send at a time",
+                None,
+            )
+            .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Unlocks documents locked with doc get --lock through the data service"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_unlock(self.state.clone(), engine_state, stack, call, input)
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Unlocks a document using the CAS returned when it was locked",
+                example: "doc unlock my_doc_id --cas 1712345678901234567",
+                result: None,
+            },
+            Example {
+                description: "Locks documents and then unlocks them again",
+                example: "[doc_1 doc_2] | doc get --lock 10sec | doc unlock",
+                result: None,
+            },
+        ]
+    }
+
+    fn requires_ast_for_arguments(&self) -> bool {
+        true
+    }
+}
+
+fn build_req(
+    key: String,
+    _value: Vec<u8>,
+    _flags: u32,
+    _expiry: u32,
+    cas: u64,
+    _durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Unlock { key, cas }
+}
+
+fn run_unlock(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    input: PipelineData,
+) -> Result<PipelineData, ShellError> {
+    let id_column = call
+        .get_flag(engine_state, stack, "id-column")?
+        .unwrap_or_else(|| String::from("id"));
+    let cas: Option<i64> = call.get_flag(engine_state, stack, "cas")?;
+
+    let span = call.head;
+    let all_items = ids_and_cas_from_input(
+        input,
+        id_column,
+        call.positional_nth(stack, 0),
+        cas.unwrap_or(0) as u64,
+    )
+    .map(move |(id, cas)| {
+        // Unlocking needs the CAS of the lock, without one the server would reject the request.
+        if let (Ok(id), 0) = (&id, cas) {
+            return Err(generic_error(
+                format!("Missing CAS for document {}", id),
+                "Provide the CAS returned when the document was locked with --cas or a cas column"
+                    .to_string(),
+                span,
+            ));
+        }
+        Ok((id, vec![], 0, cas))
+    });
+
+    run_kv_mutations(state, engine_state, stack, call, span, all_items, build_req)
+}
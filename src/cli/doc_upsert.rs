@@ -1,13 +1,12 @@
 //! The `doc upsert` command performs a KV upsert operation.
 
 use crate::cli::doc_common::run_kv_store_ops;
+use crate::client::Durability;
 use crate::client::KeyValueRequest;
 use crate::state::State;
 use nu_engine::command_prelude::Call;
 use nu_protocol::engine::{Command, EngineState, Stack};
-use nu_protocol::{
-    Category, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
-};
+use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape};
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
@@ -36,6 +35,12 @@ impl Command for DocUpsert {
                 "the name of the id column if used with an input stream",
                 None,
             )
+            .named(
+                "id-template",
+                SyntaxShape::String,
+                "build the id of each document from a template such as {type}::{id}, with {uuid}, {monotonic} and {hash:field,...} to generate ids",
+                None,
+            )
             .named(
                 "bucket",
                 SyntaxShape::String,
@@ -48,6 +53,12 @@ impl Command for DocUpsert {
                 "the name of the content column if used with an input stream",
                 None,
             )
+            .named(
+                "format",
+                SyntaxShape::String,
+                "how to store the content, one of json, string or binary, binary content is stored as binary by default and anything else as json",
+                None,
+            )
             .named(
                 "expiry",
                 SyntaxShape::Number,
@@ -73,7 +84,23 @@ impl Command for DocUpsert {
                 "the maximum number of items to batch send at a time",
                 None,
             )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
             .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-timings",
+                "add a latency summary of the server durations and round trips to the results",
+                None,
+            )
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
             .category(Category::Custom("couchbase".to_string()))
     }
 
@@ -90,10 +117,43 @@ impl Command for DocUpsert {
     ) -> Result<PipelineData, ShellError> {
         run_upsert(self.state.clone(), engine_state, stack, call, input)
     }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Upserts a JSON document",
+                example: "doc upsert my_doc_id {name: airline}",
+                result: None,
+            },
+            Example {
+                description: "Upserts a string without quoting it as JSON",
+                example: "doc upsert greeting hello --format string",
+                result: None,
+            },
+            Example {
+                description: "Upserts the raw bytes of a file",
+                example: "open --raw image.png | doc upsert image_1 --format binary",
+                result: None,
+            },
+        ]
+    }
 }
 
-fn build_req(key: String, value: Vec<u8>, expiry: u32) -> KeyValueRequest {
-    KeyValueRequest::Set { key, value, expiry }
+fn build_req(
+    key: String,
+    value: Vec<u8>,
+    flags: u32,
+    expiry: u32,
+    _cas: u64,
+    durability: Durability,
+) -> KeyValueRequest {
+    KeyValueRequest::Set {
+        key,
+        value,
+        flags,
+        expiry,
+        durability,
+    }
 }
 
 fn run_upsert(
@@ -103,11 +163,5 @@ fn run_upsert(
     call: &Call,
     input: PipelineData,
 ) -> Result<PipelineData, ShellError> {
-    let results = run_kv_store_ops(state, engine_state, stack, call, input, build_req)?;
-
-    Ok(Value::List {
-        vals: results,
-        internal_span: call.head,
-    }
-    .into_pipeline_data())
+    run_kv_store_ops(state, engine_state, stack, call, input, build_req)
 }
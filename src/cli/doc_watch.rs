@@ -0,0 +1,231 @@
+//! The `doc watch` command streams the changes made to documents through DCP.
+
+use super::util::convert_json_value_to_nu_value;
+use crate::state::State;
+
+use crate::cli::doc_common::get_active_cluster_client_cid;
+use crate::cli::error::client_error_to_shell_error;
+use crate::cli::util::{cluster_identifiers_from, NuValueMap};
+use crate::client::{ClientError, DcpEvent, DcpStart};
+use log::debug;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{StreamExt, StreamMap};
+
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{
+    Category, Example, ListStream, PipelineData, ShellError, Signature, Span, SyntaxShape, Value,
+};
+
+#[derive(Clone)]
+pub struct DocWatch {
+    state: Arc<Mutex<State>>,
+}
+
+impl DocWatch {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for DocWatch {
+    fn name(&self) -> &str {
+        "doc watch"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("doc watch")
+            .rest(
+                "ids",
+                SyntaxShape::String,
+                "only show the changes to these document ids",
+            )
+            .switch(
+                "from-beginning",
+                "show every change still held by the server rather than only new ones",
+                None,
+            )
+            .switch(
+                "with-content",
+                "include the content of mutated documents",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Streams the changes made to the documents of a collection as they happen"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_watch(self.state.clone(), engine_state, stack, call, input)
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Shows changes to the documents in the collection as they happen",
+                example: "doc watch",
+                result: None,
+            },
+            Example {
+                description: "Shows who is changing a document, along with the new content",
+                example: "doc watch airline_10 --with-content",
+                result: None,
+            },
+            Example {
+                description: "Shows every change still held by the server, then new changes",
+                example: "doc watch --from-beginning",
+                result: None,
+            },
+        ]
+    }
+}
+
+pub(crate) fn run_watch(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    _input: PipelineData,
+) -> Result<PipelineData, ShellError> {
+    let span = call.head;
+    let signals = engine_state.signals().clone();
+
+    let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
+
+    let ids: Vec<String> = call.rest(engine_state, stack, 0)?;
+    let bucket_flag = call.get_flag(engine_state, stack, "bucket")?;
+    let scope_flag = call.get_flag(engine_state, stack, "scope")?;
+    let collection_flag = call.get_flag(engine_state, stack, "collection")?;
+    let with_content = call.has_flag(engine_state, stack, "with-content")?;
+    let start = if call.has_flag(engine_state, stack, "from-beginning")? {
+        DcpStart::Beginning
+    } else {
+        DcpStart::Now
+    };
+
+    let guard = state.lock().unwrap();
+
+    let rt = Arc::new(Runtime::new().unwrap());
+    let mut streams = StreamMap::new();
+    for identifier in cluster_identifiers {
+        let (active_cluster, client, cid) = get_active_cluster_client_cid(
+            &rt,
+            identifier.clone(),
+            &guard,
+            bucket_flag.clone(),
+            scope_flag.clone(),
+            collection_flag.clone(),
+            signals.clone(),
+            span,
+        )?;
+
+        debug!(
+            "Streaming changes from {} starting {:?}",
+            &identifier, start
+        );
+
+        let changes = client.changes(
+            cid,
+            start,
+            with_content,
+            active_cluster.timeouts().data_timeout(),
+            signals.clone(),
+        );
+        streams.insert(identifier, ReceiverStream::new(changes));
+    }
+
+    let stream = WatchStream {
+        streams,
+        ids: ids.into_iter().collect(),
+        with_content,
+        rt,
+        span,
+    };
+
+    Ok(PipelineData::from(ListStream::new(stream, span, signals)))
+}
+
+// WatchStream yields the changes from every cluster as they arrive.
+struct WatchStream {
+    streams: StreamMap<String, ReceiverStream<Result<DcpEvent, ClientError>>>,
+    ids: HashSet<String>,
+    with_content: bool,
+    rt: Arc<Runtime>,
+    span: Span,
+}
+
+impl Iterator for WatchStream {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (cluster, event) = self.rt.clone().block_on(self.streams.next())?;
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    return Some(Value::error(
+                        client_error_to_shell_error(e, self.span),
+                        self.span,
+                    ));
+                }
+            };
+            if !self.ids.is_empty() && !self.ids.contains(&event.key) {
+                continue;
+            }
+
+            let mut collected = NuValueMap::default();
+            collected.add_string("id", event.key, self.span);
+            collected.add_string("op", event.operation.as_str(), self.span);
+            collected.add_i64("seqno", event.seqno as i64, self.span);
+            collected.add_i64("vbucket", event.partition as i64, self.span);
+            collected.add_i64("cas", event.cas as i64, self.span);
+            if self.with_content {
+                let content = match event.content {
+                    Some(content) => match serde_json::from_slice(&content) {
+                        Ok(json) => match convert_json_value_to_nu_value(&json, self.span) {
+                            Ok(c) => c,
+                            Err(e) => return Some(Value::error(e, self.span)),
+                        },
+                        Err(_) => Value::string(String::from_utf8_lossy(&content), self.span),
+                    },
+                    None => Value::nothing(self.span),
+                };
+                collected.add("content", content);
+            }
+            collected.add_string("cluster", cluster, self.span);
+
+            return Some(collected.into_value(self.span));
+        }
+    }
+}
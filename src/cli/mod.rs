@@ -25,8 +25,10 @@ mod clusters_create;
 mod clusters_drop;
 mod clusters_get;
 mod collections;
+mod collections_changes;
 mod collections_create;
 mod collections_drop;
+mod collections_manifest;
 mod columnar;
 mod columnar_clusters;
 mod columnar_clusters_create;
@@ -37,13 +39,24 @@ mod credentials;
 mod credentials_create;
 mod credentials_drop;
 mod ctrlc_future;
+mod data;
+mod data_stats;
 mod doc;
+mod doc_append;
 mod doc_common;
+mod doc_decrement;
 mod doc_get;
+mod doc_id_template;
+mod doc_increment;
 mod doc_insert;
+mod doc_prepend;
 mod doc_remove;
 mod doc_replace;
+mod doc_scan;
+mod doc_touch;
+mod doc_unlock;
 mod doc_upsert;
+mod doc_watch;
 mod fake_data;
 mod health;
 mod help;
@@ -60,7 +73,9 @@ mod cbenv_llm;
 mod cbenv_project;
 mod cbenv_scope;
 mod cbenv_timeouts;
+mod cbenv_trace_kv;
 mod doc_import;
+mod doc_import_readers;
 mod error;
 mod projects;
 mod projects_create;
@@ -73,7 +88,17 @@ mod scopes;
 mod scopes_create;
 mod scopes_drop;
 mod search;
+mod subdoc;
+mod subdoc_array_add_unique;
+mod subdoc_array_append;
+mod subdoc_array_prepend;
+mod subdoc_common;
+mod subdoc_counter;
 mod subdoc_get;
+mod subdoc_insert;
+mod subdoc_remove;
+mod subdoc_replace;
+mod subdoc_upsert;
 mod transactions;
 mod transactions_list_atrs;
 mod tutorial;
@@ -120,8 +145,10 @@ pub use clusters_create::ClustersCreate;
 pub use clusters_drop::ClustersDrop;
 pub use clusters_get::ClustersGet;
 pub use collections::Collections;
+pub use collections_changes::CollectionsChanges;
 pub use collections_create::CollectionsCreate;
 pub use collections_drop::CollectionsDrop;
+pub use collections_manifest::CollectionsManifest;
 pub use columnar::Columnar;
 pub use columnar_clusters::ColumnarClusters;
 pub use columnar_clusters_create::ColumnarClustersCreate;
@@ -132,13 +159,23 @@ pub use credentials::Credentials;
 pub use credentials_create::CredentialsCreate;
 pub use credentials_drop::CredentialsDrop;
 pub use ctrlc_future::CtrlcFuture;
+pub use data::Data;
+pub use data_stats::DataStats;
 pub use doc::Doc;
+pub use doc_append::DocAppend;
+pub use doc_decrement::DocDecrement;
 pub use doc_get::DocGet;
 pub use doc_import::DocImport;
+pub use doc_increment::DocIncrement;
 pub use doc_insert::DocInsert;
+pub use doc_prepend::DocPrepend;
 pub use doc_remove::DocRemove;
 pub use doc_replace::DocReplace;
+pub use doc_scan::DocScan;
+pub use doc_touch::DocTouch;
+pub use doc_unlock::DocUnlock;
 pub use doc_upsert::DocUpsert;
+pub use doc_watch::DocWatch;
 pub use error::*;
 pub use fake_data::FakeData;
 pub use health::HealthCheck;
@@ -155,6 +192,7 @@ pub use cbenv_collection::UseCollection;
 pub use cbenv_project::UseProject;
 pub use cbenv_scope::UseScope;
 pub use cbenv_timeouts::UseTimeouts;
+pub use cbenv_trace_kv::CbEnvTraceKv;
 pub use projects::Projects;
 pub use projects_create::ProjectsCreate;
 pub use projects_drop::ProjectsDrop;
@@ -166,7 +204,16 @@ pub use scopes::Scopes;
 pub use scopes_create::ScopesCreate;
 pub use scopes_drop::ScopesDrop;
 pub use search::Search;
+pub use subdoc::SubDoc;
+pub use subdoc_array_add_unique::SubDocArrayAddUnique;
+pub use subdoc_array_append::SubDocArrayAppend;
+pub use subdoc_array_prepend::SubDocArrayPrepend;
+pub use subdoc_counter::SubDocCounter;
 pub use subdoc_get::SubDocGet;
+pub use subdoc_insert::SubDocInsert;
+pub use subdoc_remove::SubDocRemove;
+pub use subdoc_replace::SubDocReplace;
+pub use subdoc_upsert::SubDocUpsert;
 pub use transactions::Transactions;
 pub use transactions_list_atrs::TransactionsListAtrs;
 pub use tutorial::Tutorial;
@@ -0,0 +1,35 @@
+use nu_engine::command_prelude::Call;
+use nu_engine::get_full_help;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, IntoPipelineData, PipelineData, ShellError, Signature, Value};
+
+#[derive(Clone)]
+pub struct SubDoc;
+
+impl Command for SubDoc {
+    fn name(&self) -> &str {
+        "subdoc"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("subdoc").category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Perform sub-document operations against a bucket or collection"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        _input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        Ok(Value::String {
+            val: get_full_help(&SubDoc, engine_state, stack),
+            internal_span: call.head,
+        }
+        .into_pipeline_data())
+    }
+}
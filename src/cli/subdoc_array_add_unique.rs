@@ -0,0 +1,125 @@
+//! The `subdoc array-add-unique` command performs a KV sub-document array-add-unique operation.
+
+use crate::cli::subdoc_common::run_subdoc_mutation;
+use crate::client::SubdocMutationOp;
+use crate::state::State;
+use nu_engine::command_prelude::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct SubDocArrayAddUnique {
+    state: Arc<Mutex<State>>,
+}
+
+impl SubDocArrayAddUnique {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for SubDocArrayAddUnique {
+    fn name(&self) -> &str {
+        "subdoc array-add-unique"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("subdoc array-add-unique")
+            .required("path", SyntaxShape::String, "the path within the documents")
+            .optional("value", SyntaxShape::Any, "the value to add to the array")
+            .optional("id", SyntaxShape::String, "the document id")
+            .named(
+                "id-column",
+                SyntaxShape::String,
+                "the name of the id column if used with an input stream",
+                None,
+            )
+            .named(
+                "value-column",
+                SyntaxShape::String,
+                "the name of the value column if used with an input stream",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named(
+                "expiry",
+                SyntaxShape::Number,
+                "the expiry for the documents in seconds, or absolute",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .named(
+                "batch-size",
+                SyntaxShape::Number,
+                "the maximum number of items to batch send at a time",
+                None,
+            )
+            .switch(
+                "create-path",
+                "create any missing parent fields of the path",
+                Some('p'),
+            )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
+            .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Adds the value to the array at the provided path in the specified documents through the data service, if it is not already present"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_subdoc_mutation(
+            self.state.clone(),
+            engine_state,
+            stack,
+            call,
+            input,
+            SubdocMutationOp::ArrayAddUnique,
+        )
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                description: "Adds a tag to the tags array of the document with the ID airline_10 unless it is already there",
+                example: r#"subdoc array-add-unique tags regional airline_10"#,
+                result: None,
+            },
+        ]
+    }
+}
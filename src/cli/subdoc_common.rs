@@ -0,0 +1,112 @@
+use crate::cli::doc_common::{id_from_value, run_kv_mutations};
+use crate::cli::error::{generic_error, serialize_error};
+use crate::cli::util::convert_nu_value_to_json_value;
+use crate::client::{KeyValueRequest, SubdocMutationOp, SubdocMutationSpec};
+use crate::state::State;
+use nu_engine::command_prelude::Call;
+use nu_engine::CallExt;
+use nu_protocol::engine::{EngineState, Stack};
+use nu_protocol::{PipelineData, ShellError, Value};
+use std::sync::{Arc, Mutex};
+
+// run_subdoc_mutation performs a single path mutation against every document id provided, either as
+// arguments or through the input stream. Operations that take a value expect it as the second
+// positional argument, or in the value column of input records.
+pub(crate) fn run_subdoc_mutation(
+    state: Arc<Mutex<State>>,
+    engine_state: &EngineState,
+    stack: &mut Stack,
+    call: &Call,
+    input: PipelineData,
+    op: SubdocMutationOp,
+) -> Result<PipelineData, ShellError> {
+    let span = call.head;
+
+    let path: String = call.req(engine_state, stack, 0)?;
+    let create_path = call.has_flag(engine_state, stack, "create-path")?;
+
+    let id_column = call
+        .get_flag(engine_state, stack, "id-column")?
+        .unwrap_or_else(|| String::from("id"));
+
+    let value_column = call
+        .get_flag(engine_state, stack, "value-column")?
+        .unwrap_or_else(|| String::from("value"));
+
+    let (value_arg, id_arg) = if op.requires_value() {
+        (
+            call.opt::<Value>(engine_state, stack, 1)?,
+            call.opt::<String>(engine_state, stack, 2)?,
+        )
+    } else {
+        (None, call.opt::<String>(engine_state, stack, 1)?)
+    };
+
+    let id_value_arg = value_arg.clone();
+    let input_value_column = value_column.clone();
+    let items = input
+        .into_iter()
+        .filter_map(move |i| match i {
+            Value::String { val, .. } => Some((val, value_arg.clone())),
+            Value::Int { val, .. } => Some((val.to_string(), value_arg.clone())),
+            Value::Record { val, .. } => {
+                let id = val
+                    .get(&id_column)
+                    .and_then(|v| id_from_value(v, span))
+                    .unwrap_or_default();
+                let value = val.get(&input_value_column).cloned().or(value_arg.clone());
+                Some((id, value))
+            }
+            _ => None,
+        })
+        .chain(id_arg.map(|id| (id, id_value_arg)));
+
+    let spec_path = path.clone();
+    let all_items = items.map(move |(id, value)| {
+        let value = if op.requires_value() {
+            let value = match value {
+                Some(v) => v,
+                None => {
+                    return Err(generic_error(
+                        format!("No value provided for path {} in document {}", path, id),
+                        format!(
+                            "Provide the value as an argument or with the '{}' column of the input stream",
+                            value_column
+                        ),
+                        span,
+                    ));
+                }
+            };
+            let json = convert_nu_value_to_json_value(&value, span)?;
+            serde_json::to_vec(&json).map_err(|e| serialize_error(e.to_string(), span))?
+        } else {
+            vec![]
+        };
+
+        Ok((Ok(id), value, 0, 0))
+    });
+
+    run_kv_mutations(
+        state,
+        engine_state,
+        stack,
+        call,
+        span,
+        all_items,
+        move |key, value, _flags, expiry, _cas, durability| KeyValueRequest::SubdocMultiMutation {
+            key,
+            specs: vec![SubdocMutationSpec {
+                op,
+                path: spec_path.clone(),
+                value: if op.requires_value() {
+                    Some(value)
+                } else {
+                    None
+                },
+                create_path,
+            }],
+            expiry,
+            durability,
+        },
+    )
+}
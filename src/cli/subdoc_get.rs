@@ -3,9 +3,9 @@ use crate::state::State;
 
 use crate::cli::doc_common::{build_batched_kv_items, get_active_cluster_client_cid};
 use crate::cli::doc_get::ids_from_input;
-use crate::cli::doc_get::GetResult;
+use crate::cli::doc_get::{convert_virtual_document_to_nu_value, GetResult};
 use crate::cli::util::cluster_identifiers_from;
-use crate::client::KeyValueRequest;
+use crate::client::{KeyValueRequest, SubdocLookupSpec};
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use log::debug;
@@ -22,7 +22,7 @@ use nu_engine::command_prelude::Call;
 use nu_engine::CallExt;
 use nu_protocol::engine::{Command, EngineState, Stack};
 use nu_protocol::{
-    Category, IntoPipelineData, PipelineData, ShellError, Signature, SyntaxShape, Value,
+    Category, IntoPipelineData, PipelineData, ShellError, Signature, Span, SyntaxShape, Value,
 };
 
 #[derive(Clone)]
@@ -43,10 +43,10 @@ impl Command for SubDocGet {
 
     fn signature(&self) -> Signature {
         Signature::build("subdoc get")
-            .required(
+            .optional(
                 "path",
                 SyntaxShape::Any,
-                "the path(s) to be fetched from the documents",
+                "the path(s) to be fetched from the documents, the id is given in its place with --xattr",
             )
             .optional("id", SyntaxShape::String, "the document id")
             .named(
@@ -80,6 +80,12 @@ impl Command for SubDocGet {
                 "the maximum number of items to batch send at a time",
                 None,
             )
+            .named(
+                "xattr",
+                SyntaxShape::Any,
+                "the path(s) to be fetched from the extended attributes of the documents rather than the body",
+                None,
+            )
             .switch("halt-on-error", "halt on any errors", Some('e'))
             .category(Category::Custom("couchbase".to_string()))
     }
@@ -110,6 +116,11 @@ impl Command for SubDocGet {
                 example: "[landmark_10019 landmark_10020] | subdoc get address",
                 result: None
             },
+            Example{
+                description: "Fetches the virtual $document extended attribute from the document with the ID landmark_10019",
+                example: "subdoc get --xattr '$document' landmark_10019",
+                result: None
+            },
         ]
     }
 
@@ -130,27 +141,41 @@ fn run_subdoc_lookup(
 
     let cluster_identifiers = cluster_identifiers_from(engine_state, stack, &state, call, true)?;
 
-    let paths: Vec<String> = match call.req::<Value>(engine_state, stack, 0)? {
-        Value::String { val, .. } => {
-            vec![val]
-        }
-        Value::List { vals, .. } => vals
-            .iter()
-            .map(|s| s.as_str().unwrap().to_string())
-            .collect(),
-        _ => {
-            return Err(generic_error(
-                "Field(s) must be a string or list",
-                "Run 'subdoc get --help' to see examples".to_string(),
-                None,
-            ));
+    // The xattr paths take the place of the body paths, so the id is the first argument.
+    let xattr_paths: Option<Value> = call.get_flag(engine_state, stack, "xattr")?;
+    let xattr = xattr_paths.is_some();
+    let (paths, id_position) = match xattr_paths {
+        Some(p) => {
+            if call.positional_nth(stack, 1).is_some() {
+                return Err(generic_error(
+                    "Too many arguments",
+                    "With --xattr the paths are given to the flag and the only argument is the document id".to_string(),
+                    span,
+                ));
+            }
+            (paths_from_value(p)?, 0)
         }
+        None => match call.opt::<Value>(engine_state, stack, 0)? {
+            Some(p) => (paths_from_value(p)?, 1),
+            None => {
+                return Err(generic_error(
+                    "Missing path",
+                    "The path(s) to fetch must be given as the first argument, or with --xattr"
+                        .to_string(),
+                    span,
+                ));
+            }
+        },
     };
 
     let id_column: String = call
         .get_flag(engine_state, stack, "id-column")?
         .unwrap_or_else(|| "id".to_string());
-    let ids = ids_from_input(input, id_column.clone(), call.positional_nth(stack, 1))?;
+    let ids = ids_from_input(
+        input,
+        id_column.clone(),
+        call.positional_nth(stack, id_position),
+    )?;
 
     let mut workers = FuturesUnordered::new();
     let guard = state.lock().unwrap();
@@ -207,17 +232,23 @@ fn run_subdoc_lookup(
                 let id = id.clone();
 
                 let client = client.clone();
-                let copy = paths.clone();
 
                 let request = if paths.len() > 1 {
                     KeyValueRequest::SubdocMultiLookup {
                         key: id,
-                        paths: copy,
+                        specs: paths
+                            .iter()
+                            .map(|path| SubdocLookupSpec {
+                                path: path.clone(),
+                                xattr,
+                            })
+                            .collect(),
                     }
                 } else {
                     KeyValueRequest::SubDocGet {
                         key: id.clone(),
                         path: paths[0].clone(),
+                        xattr,
                     }
                 };
 
@@ -232,30 +263,37 @@ fn run_subdoc_lookup(
                                 .key(res.key())
                                 .cas(res.cas() as i64);
 
+                            let path_errors = res.path_errors().to_vec();
                             let content = res.content().unwrap_or_default();
 
                             // Create a record where cols =  field and
-                            match convert_json_value_to_nu_value(&content, call.head) {
+                            let converted = if paths.len() == 1 {
+                                convert_lookup_value(&paths[0], xattr, &content, span)
+                            } else {
+                                let values = content.as_array().cloned().unwrap_or_default();
+                                paths
+                                    .iter()
+                                    .zip(values.iter())
+                                    .map(|(path, value)| {
+                                        convert_lookup_value(path, xattr, value, span)
+                                    })
+                                    .collect::<Result<Vec<Value>, ShellError>>()
+                                    .map(|list| Value::Record {
+                                        val: SharedCow::new(
+                                            Record::from_raw_cols_vals(
+                                                paths.clone(),
+                                                list,
+                                                span,
+                                                span,
+                                            )
+                                            .unwrap(),
+                                        ),
+                                        internal_span: span,
+                                    })
+                            };
+                            match converted {
                                 Ok(c) => {
-                                    if paths.len() == 1 {
-                                        collected = collected.content(c);
-                                    } else {
-                                        let list = c.as_list().unwrap().to_vec();
-
-                                        let record = Value::Record {
-                                            val: SharedCow::new(
-                                                Record::from_raw_cols_vals(
-                                                    paths.clone(),
-                                                    list,
-                                                    span,
-                                                    span,
-                                                )
-                                                .unwrap(),
-                                            ),
-                                            internal_span: span,
-                                        };
-                                        collected = collected.content(record);
-                                    }
+                                    collected = collected.content(c);
                                 }
                                 Err(e) => {
                                     if halt_on_error {
@@ -265,6 +303,23 @@ fn run_subdoc_lookup(
                                 }
                             }
 
+                            // Paths which could not be looked up are null in the content.
+                            let failed: Vec<String> = paths
+                                .iter()
+                                .zip(path_errors)
+                                .filter_map(|(path, e)| e.map(|e| format!("{}: {}", path, e)))
+                                .collect();
+                            if !failed.is_empty() {
+                                if halt_on_error {
+                                    return Err(generic_error(
+                                        "Failed to fetch paths",
+                                        Some(failed.join(", ")),
+                                        call.head,
+                                    ));
+                                }
+                                collected = collected.error(failed.join(", "));
+                            }
+
                             results.push(collected.into_value(call.head));
                         }
                         Err(e) => {
@@ -296,3 +351,33 @@ fn run_subdoc_lookup(
     }
     .into_pipeline_data())
 }
+
+fn paths_from_value(paths: Value) -> Result<Vec<String>, ShellError> {
+    match paths {
+        Value::String { val, .. } => Ok(vec![val]),
+        Value::List { vals, .. } => Ok(vals
+            .iter()
+            .map(|s| s.as_str().unwrap().to_string())
+            .collect()),
+        _ => Err(generic_error(
+            "Field(s) must be a string or list",
+            "Run 'subdoc get --help' to see examples".to_string(),
+            None,
+        )),
+    }
+}
+
+// convert_lookup_value converts the value of a looked up path, decoding the virtual $document
+// extended attribute into its natural types.
+fn convert_lookup_value(
+    path: &str,
+    xattr: bool,
+    value: &serde_json::Value,
+    span: Span,
+) -> Result<Value, ShellError> {
+    if xattr && path == "$document" {
+        convert_virtual_document_to_nu_value(value, span)
+    } else {
+        convert_json_value_to_nu_value(value, span)
+    }
+}
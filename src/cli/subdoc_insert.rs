@@ -0,0 +1,123 @@
+//! The `subdoc insert` command performs a KV sub-document insert operation.
+
+use crate::cli::subdoc_common::run_subdoc_mutation;
+use crate::client::SubdocMutationOp;
+use crate::state::State;
+use nu_engine::command_prelude::Call;
+use nu_protocol::engine::{Command, EngineState, Stack};
+use nu_protocol::{Category, Example, PipelineData, ShellError, Signature, SyntaxShape};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct SubDocInsert {
+    state: Arc<Mutex<State>>,
+}
+
+impl SubDocInsert {
+    pub fn new(state: Arc<Mutex<State>>) -> Self {
+        Self { state }
+    }
+}
+
+impl Command for SubDocInsert {
+    fn name(&self) -> &str {
+        "subdoc insert"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("subdoc insert")
+            .required("path", SyntaxShape::String, "the path within the documents")
+            .optional("value", SyntaxShape::Any, "the value to write to the path")
+            .optional("id", SyntaxShape::String, "the document id")
+            .named(
+                "id-column",
+                SyntaxShape::String,
+                "the name of the id column if used with an input stream",
+                None,
+            )
+            .named(
+                "value-column",
+                SyntaxShape::String,
+                "the name of the value column if used with an input stream",
+                None,
+            )
+            .named(
+                "bucket",
+                SyntaxShape::String,
+                "the name of the bucket",
+                None,
+            )
+            .named(
+                "expiry",
+                SyntaxShape::Number,
+                "the expiry for the documents in seconds, or absolute",
+                None,
+            )
+            .named("scope", SyntaxShape::String, "the name of the scope", None)
+            .named(
+                "collection",
+                SyntaxShape::String,
+                "the name of the collection",
+                None,
+            )
+            .named(
+                "clusters",
+                SyntaxShape::String,
+                "the clusters which should be contacted",
+                None,
+            )
+            .named(
+                "batch-size",
+                SyntaxShape::Number,
+                "the maximum number of items to batch send at a time",
+                None,
+            )
+            .switch(
+                "create-path",
+                "create any missing parent fields of the path",
+                Some('p'),
+            )
+            .named(
+                "durability",
+                SyntaxShape::String,
+                "the durability level required for the write",
+                None,
+            )
+            .switch("halt-on-error", "halt on any errors", Some('e'))
+            .switch(
+                "with-results",
+                "return a result for each document, with its status, error, CAS and mutation token, rather than a summary",
+                None,
+            )
+            .category(Category::Custom("couchbase".to_string()))
+    }
+
+    fn description(&self) -> &str {
+        "Inserts the value at the provided path in the specified documents through the data service, failing if the path already exists"
+    }
+
+    fn run(
+        &self,
+        engine_state: &EngineState,
+        stack: &mut Stack,
+        call: &Call,
+        input: PipelineData,
+    ) -> Result<PipelineData, ShellError> {
+        run_subdoc_mutation(
+            self.state.clone(),
+            engine_state,
+            stack,
+            call,
+            input,
+            SubdocMutationOp::Insert,
+        )
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            description: "Adds a new field to the document with the ID airline_10",
+            example: r#"subdoc insert alias Q5 airline_10"#,
+            result: None,
+        }]
+    }
+}
@@ -3,11 +3,13 @@
 use tokio_util::codec::Decoder;
 use tokio_util::codec::Encoder;
 
-use bytes::{BufMut, Bytes, BytesMut};
-use std::convert::TryInto;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+use std::convert::{TryFrom, TryInto};
 use std::io;
+use tokio::sync::mpsc;
 
-use super::protocol::HEADER_SIZE;
+use super::protocol::{Magic, Opcode, DATATYPE_SNAPPY, HEADER_SIZE};
 
 /// The `KeyValueCodec` aggregates byte chunks into full packets at their boundaries
 /// on decoding.
@@ -79,6 +81,173 @@ impl Encoder<Bytes> for KeyValueCodec {
     }
 }
 
+/// A message sent by the server on a DCP connection.
+///
+/// Only the fields needed to describe changes to documents are decoded, the sequence number is
+/// zero for anything other than mutations, deletions and expirations.
+#[derive(Debug)]
+pub struct DcpMessage {
+    pub opcode: Opcode,
+    pub partition: u16,
+    pub cas: u64,
+    pub seqno: u64,
+    pub key: Bytes,
+    pub value: Option<Bytes>,
+}
+
+impl DcpMessage {
+    /// Decodes a full packet, as split by the `KeyValueCodec`, into a message.
+    ///
+    /// If collections are enabled the collection id prefix is removed from the key and snappy
+    /// compressed values are always decompressed. Messages with an opcode which isn't known, such
+    /// as those added by newer servers, are skipped by returning nothing.
+    pub fn decode(frame: &Bytes, collections_enabled: bool) -> Result<Option<Self>, io::Error> {
+        if frame.len() < HEADER_SIZE {
+            return Err(invalid_dcp_message("truncated header"));
+        }
+
+        let mut slice = frame.slice(0..HEADER_SIZE);
+        let magic = Magic::from(slice.get_u8());
+        if !magic.is_request() {
+            return Err(invalid_dcp_message("unexpected magic"));
+        }
+        let opcode = match Opcode::try_from(slice.get_u8()) {
+            Ok(opcode) => opcode,
+            Err(opcode) => {
+                debug!("Skipping dcp message with unknown opcode {:#04x}", opcode);
+                return Ok(None);
+            }
+        };
+        let (flexible_extras_len, key_len) = if magic.is_flexible() {
+            (slice.get_u8() as usize, slice.get_u8() as usize)
+        } else {
+            (0, slice.get_u16() as usize)
+        };
+        let extras_len = slice.get_u8() as usize;
+        let datatype = slice.get_u8();
+        let partition = slice.get_u16();
+        let total_body_len = slice.get_u32() as usize;
+        let _opaque = slice.get_u32();
+        let cas = slice.get_u64();
+
+        let extras_start = HEADER_SIZE + flexible_extras_len;
+        let key_start = extras_start + extras_len;
+        let value_start = key_start + key_len;
+        if value_start > HEADER_SIZE + total_body_len || frame.len() < HEADER_SIZE + total_body_len
+        {
+            return Err(invalid_dcp_message("truncated body"));
+        }
+
+        // Mutations, deletions and expirations all start their extras with the sequence number.
+        let seqno = match opcode {
+            Opcode::DcpMutation | Opcode::DcpDeletion | Opcode::DcpExpiration
+                if extras_len >= 8 =>
+            {
+                frame.slice(extras_start..extras_start + 8).get_u64()
+            }
+            _ => 0,
+        };
+
+        let mut key = frame.slice(key_start..value_start);
+        if collections_enabled && !key.is_empty() {
+            skip_uleb128(&mut key)?;
+        }
+
+        let value = if value_start < HEADER_SIZE + total_body_len {
+            let value = frame.slice(value_start..HEADER_SIZE + total_body_len);
+            if datatype & DATATYPE_SNAPPY != 0 {
+                let decompressed = snap::raw::Decoder::new()
+                    .decompress_vec(&value)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Some(Bytes::from(decompressed))
+            } else {
+                Some(value)
+            }
+        } else {
+            None
+        };
+
+        Ok(Some(DcpMessage {
+            opcode,
+            partition,
+            cas,
+            seqno,
+            key,
+            value,
+        }))
+    }
+}
+
+/// The `DcpStreamReader` reads the messages sent on a DCP connection for as long as it is open.
+///
+/// The server stops sending once it has sent a full buffer of messages which have not been
+/// acknowledged, so the reader keeps track of how much has been read so that the caller can
+/// acknowledge it once at least half of the buffer has been used.
+#[derive(Debug)]
+pub struct DcpStreamReader {
+    frames: mpsc::UnboundedReceiver<Bytes>,
+    collections_enabled: bool,
+    unacknowledged: usize,
+    acknowledge_after: usize,
+}
+
+impl DcpStreamReader {
+    pub fn new(
+        frames: mpsc::UnboundedReceiver<Bytes>,
+        collections_enabled: bool,
+        buffer_size: usize,
+    ) -> Self {
+        DcpStreamReader {
+            frames,
+            collections_enabled,
+            unacknowledged: 0,
+            acknowledge_after: buffer_size / 2,
+        }
+    }
+
+    /// Waits for the next message, returning nothing once the connection has been closed.
+    pub async fn next(&mut self) -> Option<Result<DcpMessage, io::Error>> {
+        loop {
+            // Skipped messages still take up space in the buffer so are acknowledged too.
+            let frame = self.frames.recv().await?;
+            self.unacknowledged += frame.len();
+            match DcpMessage::decode(&frame, self.collections_enabled) {
+                Ok(Some(message)) => return Some(Ok(message)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Returns the number of bytes which should be acknowledged to the server, if enough have
+    /// been read since the last acknowledgement.
+    pub fn take_acknowledgement(&mut self) -> Option<u32> {
+        if self.unacknowledged < self.acknowledge_after {
+            return None;
+        }
+
+        let bytes = self.unacknowledged;
+        self.unacknowledged = 0;
+        Some(bytes as u32)
+    }
+}
+
+fn skip_uleb128(input: &mut Bytes) -> Result<(), io::Error> {
+    while input.has_remaining() {
+        if input.get_u8() & 0x80 == 0 {
+            return Ok(());
+        }
+    }
+    Err(invalid_dcp_message("truncated collection id"))
+}
+
+fn invalid_dcp_message(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid dcp message: {}", reason),
+    )
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -148,4 +317,119 @@ mod tests {
         assert_eq!(HEADER_SIZE, result.unwrap().unwrap().len());
         assert!(input.is_empty());
     }
+
+    fn dcp_mutation(key: &[u8], value: &[u8], datatype: u8) -> Bytes {
+        let mut extras = BytesMut::with_capacity(31);
+        extras.put_u64(42);
+        extras.put_u64(1);
+        extras.put_u32(0);
+        extras.put_u32(0);
+        extras.put_u32(0);
+        extras.put_u16(0);
+        extras.put_u8(0);
+
+        let mut frame = BytesMut::new();
+        frame.put_u8(Magic::Request.encoded());
+        frame.put_u8(Opcode::DcpMutation.encoded());
+        frame.put_u16(key.len() as u16);
+        frame.put_u8(extras.len() as u8);
+        frame.put_u8(datatype);
+        frame.put_u16(12);
+        frame.put_u32((extras.len() + key.len() + value.len()) as u32);
+        frame.put_u32(7);
+        frame.put_u64(0x1234);
+        frame.put(extras);
+        frame.put_slice(key);
+        frame.put_slice(value);
+        frame.freeze()
+    }
+
+    #[test]
+    fn decodes_dcp_mutation() {
+        let value = br#"{"name":"compressed compressed compressed"}"#;
+        let compressed = snap::raw::Encoder::new().compress_vec(value).unwrap();
+        // The key is prefixed with collection id 8.
+        let frame = dcp_mutation(b"\x08airline_10", &compressed, DATATYPE_SNAPPY | 0x01);
+
+        let message = DcpMessage::decode(&frame, true).unwrap().unwrap();
+        assert!(matches!(message.opcode, Opcode::DcpMutation));
+        assert_eq!(12, message.partition);
+        assert_eq!(0x1234, message.cas);
+        assert_eq!(42, message.seqno);
+        assert_eq!(Bytes::from("airline_10"), message.key);
+        assert_eq!(Some(Bytes::from(&value[..])), message.value);
+    }
+
+    #[test]
+    fn decodes_dcp_expiration() {
+        // Expirations carry the seqno, rev seqno and delete time, and have no value.
+        let mut extras = BytesMut::with_capacity(20);
+        extras.put_u64(43);
+        extras.put_u64(2);
+        extras.put_u32(1700000000);
+        let key = b"\x08airline_10";
+
+        let mut frame = BytesMut::new();
+        frame.put_u8(Magic::Request.encoded());
+        frame.put_u8(Opcode::DcpExpiration.encoded());
+        frame.put_u16(key.len() as u16);
+        frame.put_u8(extras.len() as u8);
+        frame.put_u8(0);
+        frame.put_u16(12);
+        frame.put_u32((extras.len() + key.len()) as u32);
+        frame.put_u32(7);
+        frame.put_u64(0x1234);
+        frame.put(extras);
+        frame.put_slice(key);
+
+        let message = DcpMessage::decode(&frame.freeze(), true).unwrap().unwrap();
+        assert!(matches!(message.opcode, Opcode::DcpExpiration));
+        assert_eq!(12, message.partition);
+        assert_eq!(43, message.seqno);
+        assert_eq!(Bytes::from("airline_10"), message.key);
+        assert_eq!(None, message.value);
+    }
+
+    #[test]
+    fn rejects_truncated_dcp_message() {
+        let frame = dcp_mutation(b"airline_10", b"{}", 0x01);
+
+        assert!(DcpMessage::decode(&frame.slice(0..30), false).is_err());
+    }
+
+    #[tokio::test]
+    async fn skips_unknown_dcp_messages() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let frame = dcp_mutation(b"airline_10", b"{}", 0x01);
+        let mut unknown = BytesMut::from(&frame[..]);
+        unknown[1] = 0x7f;
+        let mut reader = DcpStreamReader::new(rx, false, frame.len() * 4);
+
+        tx.send(unknown.freeze()).unwrap();
+        tx.send(frame.clone()).unwrap();
+        drop(tx);
+
+        let message = reader.next().await.unwrap().unwrap();
+        assert!(matches!(message.opcode, Opcode::DcpMutation));
+        assert_eq!(Some(frame.len() as u32 * 2), reader.take_acknowledgement());
+        assert!(reader.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn acknowledges_half_the_buffer() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let frame = dcp_mutation(b"airline_10", b"{}", 0x01);
+        let mut reader = DcpStreamReader::new(rx, false, frame.len() * 3);
+
+        tx.send(frame.clone()).unwrap();
+        tx.send(frame.clone()).unwrap();
+        drop(tx);
+
+        assert!(reader.next().await.unwrap().is_ok());
+        assert_eq!(None, reader.take_acknowledgement());
+        assert!(reader.next().await.unwrap().is_ok());
+        assert_eq!(Some(frame.len() as u32 * 2), reader.take_acknowledgement());
+        assert_eq!(None, reader.take_acknowledgement());
+        assert!(reader.next().await.is_none());
+    }
 }
@@ -0,0 +1,117 @@
+//! The collections manifest of a bucket, as published by the data service.
+
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CollectionsManifest {
+    #[serde(deserialize_with = "from_hex")]
+    pub uid: u64,
+    pub scopes: Vec<ManifestScope>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestScope {
+    pub name: String,
+    #[serde(deserialize_with = "from_hex")]
+    pub uid: u32,
+    pub collections: Vec<ManifestCollection>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestCollection {
+    pub name: String,
+    #[serde(deserialize_with = "from_hex")]
+    pub uid: u32,
+    // Only present if the collection has a max TTL of its own.
+    #[serde(rename = "maxTTL")]
+    pub max_ttl: Option<i64>,
+    #[serde(default)]
+    pub history: bool,
+}
+
+impl CollectionsManifest {
+    // from_slice parses the manifest from the body of a get collections manifest response.
+    pub(crate) fn from_slice(body: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(body)
+    }
+
+    // collection_ids maps the scope and collection names of every collection to its id.
+    pub fn collection_ids(&self) -> HashMap<(String, String), u32> {
+        self.scopes
+            .iter()
+            .flat_map(|scope| {
+                scope.collections.iter().map(move |collection| {
+                    (
+                        (scope.name.clone(), collection.name.clone()),
+                        collection.uid,
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+// Ids in the manifest are hex strings without a prefix.
+fn from_hex<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    let hex = String::deserialize(deserializer)?;
+    u64::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(|id| T::try_from(id).ok())
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid id {}", hex)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_manifest() {
+        let body = r#"{
+            "uid": "1a",
+            "scopes": [
+                {
+                    "name": "_default",
+                    "uid": "0",
+                    "collections": [{"name": "_default", "uid": "0"}]
+                },
+                {
+                    "name": "inventory",
+                    "uid": "8",
+                    "collections": [
+                        {"name": "airline", "uid": "b", "maxTTL": 3600, "history": true},
+                        {"name": "hotel", "uid": "c"}
+                    ]
+                }
+            ]
+        }"#;
+
+        let manifest = CollectionsManifest::from_slice(body.as_bytes()).unwrap();
+        assert_eq!(0x1a, manifest.uid);
+        assert_eq!(8, manifest.scopes[1].uid);
+
+        let airline = &manifest.scopes[1].collections[0];
+        assert_eq!(Some(3600), airline.max_ttl);
+        assert!(airline.history);
+        assert!(!manifest.scopes[1].collections[1].history);
+
+        let ids = manifest.collection_ids();
+        assert_eq!(3, ids.len());
+        assert_eq!(
+            Some(&0xc),
+            ids.get(&("inventory".to_string(), "hotel".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        let body = r#"{"uid": "xyz", "scopes": []}"#;
+
+        assert!(CollectionsManifest::from_slice(body.as_bytes()).is_err());
+    }
+}
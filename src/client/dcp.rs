@@ -0,0 +1,60 @@
+//! Types for following the changes made to the documents of a collection through DCP.
+
+use crate::client::codec::DcpMessage;
+use crate::client::protocol::Opcode;
+
+#[derive(Debug, Clone, Copy)]
+pub enum DcpStart {
+    // Now streams only the changes made after the stream is opened.
+    Now,
+    // Beginning streams every change still in the history of each vbucket.
+    Beginning,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DcpOperation {
+    Mutation,
+    Deletion,
+    Expiration,
+}
+
+impl DcpOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DcpOperation::Mutation => "mutation",
+            DcpOperation::Deletion => "deletion",
+            DcpOperation::Expiration => "expiration",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DcpEvent {
+    pub key: String,
+    pub seqno: u64,
+    pub partition: u16,
+    pub cas: u64,
+    pub operation: DcpOperation,
+    pub content: Option<Vec<u8>>,
+}
+
+impl DcpEvent {
+    // from_message creates an event from a message if it describes a change to a document.
+    pub(crate) fn from_message(message: DcpMessage) -> Option<Self> {
+        let operation = match message.opcode {
+            Opcode::DcpMutation => DcpOperation::Mutation,
+            Opcode::DcpDeletion => DcpOperation::Deletion,
+            Opcode::DcpExpiration => DcpOperation::Expiration,
+            _ => return None,
+        };
+
+        Some(DcpEvent {
+            key: String::from_utf8_lossy(&message.key).to_string(),
+            seqno: message.seqno,
+            partition: message.partition,
+            cas: message.cas,
+            operation,
+            content: message.value.map(|v| v.to_vec()),
+        })
+    }
+}
@@ -0,0 +1,109 @@
+//! The format of document values, as recorded by the SDKs in the common flags of a document.
+
+use crate::client::protocol::DATATYPE_JSON;
+use std::convert::TryFrom;
+
+// The SDKs store the format of a value in the top byte of the flags.
+const FORMAT_MASK: u32 = 0xff000000;
+const FORMAT_JSON: u32 = 0x02000000;
+const FORMAT_BINARY: u32 = 0x03000000;
+const FORMAT_STRING: u32 = 0x04000000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Json,
+    String,
+    Binary,
+}
+
+impl DocumentFormat {
+    pub fn flags(&self) -> u32 {
+        match self {
+            Self::Json => FORMAT_JSON,
+            Self::String => FORMAT_STRING,
+            Self::Binary => FORMAT_BINARY,
+        }
+    }
+
+    pub fn is_json(flags: u32) -> bool {
+        flags & FORMAT_MASK == FORMAT_JSON
+    }
+
+    // from_flags reads the format from the flags of a document, falling back to the datatype and
+    // then the value itself for documents written without common flags, such as by older SDKs.
+    pub fn from_flags(flags: u32, datatype: u8, value: &[u8]) -> Self {
+        match flags & FORMAT_MASK {
+            FORMAT_JSON => Self::Json,
+            FORMAT_STRING => Self::String,
+            FORMAT_BINARY => Self::Binary,
+            _ if datatype & DATATYPE_JSON != 0 => Self::Json,
+            _ => Self::detect(value),
+        }
+    }
+
+    fn detect(value: &[u8]) -> Self {
+        if serde_json::from_slice::<serde_json::Value>(value).is_ok() {
+            Self::Json
+        } else if std::str::from_utf8(value).is_ok() {
+            Self::String
+        } else {
+            Self::Binary
+        }
+    }
+}
+
+impl TryFrom<&str> for DocumentFormat {
+    type Error = String;
+
+    fn try_from(format: &str) -> Result<Self, Self::Error> {
+        match format {
+            "json" => Ok(Self::Json),
+            "string" => Ok(Self::String),
+            "binary" => Ok(Self::Binary),
+            _ => Err(format!("invalid document format {}", format)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_common_flags() {
+        assert_eq!(
+            DocumentFormat::Json,
+            DocumentFormat::from_flags(0x02000000, 0, b"{}")
+        );
+        assert_eq!(
+            DocumentFormat::String,
+            DocumentFormat::from_flags(0x04000000, 0, b"{}")
+        );
+        // The lower bits may carry compression or other SDK specific flags.
+        assert_eq!(
+            DocumentFormat::Binary,
+            DocumentFormat::from_flags(0x03000001, 0, b"{}")
+        );
+    }
+
+    #[test]
+    fn from_legacy_flags() {
+        assert_eq!(
+            DocumentFormat::Json,
+            DocumentFormat::from_flags(0, 0, br#"{"a":1}"#)
+        );
+        assert_eq!(
+            DocumentFormat::String,
+            DocumentFormat::from_flags(0, 0, b"hello")
+        );
+        assert_eq!(
+            DocumentFormat::Binary,
+            DocumentFormat::from_flags(0, 0, &[0xff, 0xfe])
+        );
+        // The datatype is trusted without checking the value.
+        assert_eq!(
+            DocumentFormat::Json,
+            DocumentFormat::from_flags(0, DATATYPE_JSON, b"{")
+        );
+    }
+}
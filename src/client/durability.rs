@@ -0,0 +1,22 @@
+//! The durability a mutation must reach before the data service acknowledges it.
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Durability {
+    #[default]
+    None,
+    Majority,
+    MajorityAndPersistActive,
+    PersistToMajority,
+}
+
+impl Durability {
+    // level is the value sent in the durability frame, writes without durability have no frame.
+    pub(crate) fn level(&self) -> Option<u8> {
+        match self {
+            Self::None => None,
+            Self::Majority => Some(0x01),
+            Self::MajorityAndPersistActive => Some(0x02),
+            Self::PersistToMajority => Some(0x03),
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use crate::client::protocol::{KvResponse, Status};
+use crate::client::error_map::{ErrorCode, ErrorMap};
+use crate::client::protocol::{KvResponse, Opcode, Status};
 use serde::Deserialize;
 use std::fmt;
 use std::fmt::Debug;
@@ -62,6 +63,31 @@ pub enum ClientError {
     KeyAlreadyExists {
         key: String,
     },
+    CasMismatch {
+        key: String,
+    },
+    DocumentLocked {
+        key: String,
+    },
+    NotMyVbucket {
+        key: String,
+        config: Option<Vec<u8>>,
+    },
+    DurabilityImpossible {
+        key: String,
+    },
+    DurabilityAmbiguous {
+        key: String,
+    },
+    SyncWriteInProgress {
+        key: String,
+    },
+    TemporaryFailure {
+        key: String,
+    },
+    RateLimited {
+        key: String,
+    },
     AccessError {
         reason: Option<String>,
     },
@@ -89,6 +115,14 @@ pub enum ClientError {
         key: String,
         path: String,
     },
+    PathAlreadyExists {
+        key: String,
+        path: String,
+    },
+    PathMismatch {
+        key: String,
+        path: String,
+    },
     InvalidSample {
         sample: String,
     },
@@ -99,6 +133,13 @@ pub enum ClientError {
     AccessDenied {
         reason: String,
     },
+    // KvServerError wraps a data service failure with the description of its status from the
+    // server error map.
+    KvServerError {
+        source: Box<ClientError>,
+        status: u16,
+        code: ErrorCode,
+    },
 }
 
 impl ClientError {
@@ -107,10 +148,21 @@ impl ClientError {
             ClientError::CollectionUnknownDuringRequest { key, .. } => Some(key.clone()),
             ClientError::KeyNotFound { key } => Some(key.clone()),
             ClientError::KeyAlreadyExists { key } => Some(key.clone()),
+            ClientError::CasMismatch { key } => Some(key.clone()),
+            ClientError::DocumentLocked { key } => Some(key.clone()),
+            ClientError::NotMyVbucket { key, .. } => Some(key.clone()),
+            ClientError::DurabilityImpossible { key } => Some(key.clone()),
+            ClientError::DurabilityAmbiguous { key } => Some(key.clone()),
+            ClientError::SyncWriteInProgress { key } => Some(key.clone()),
+            ClientError::TemporaryFailure { key } => Some(key.clone()),
+            ClientError::RateLimited { key } => Some(key.clone()),
             ClientError::Timeout { key, .. } => key.clone(),
             ClientError::Cancelled { key } => key.clone(),
             ClientError::RequestFailed { key, .. } => key.clone(),
             ClientError::PathNotFound { key, .. } => Some(key.clone()),
+            ClientError::PathAlreadyExists { key, .. } => Some(key.clone()),
+            ClientError::PathMismatch { key, .. } => Some(key.clone()),
+            ClientError::KvServerError { source, .. } => source.key(),
             _ => None,
         }
     }
@@ -146,6 +198,14 @@ impl ClientError {
             Self::ScopeNotFound { .. } => "Scope unknown".to_string(),
             Self::KeyNotFound { .. } => "Key not found".to_string(),
             Self::KeyAlreadyExists { .. } => "Key already exists".to_string(),
+            Self::CasMismatch { .. } => "CAS mismatch".to_string(),
+            Self::DocumentLocked { .. } => "Document locked".to_string(),
+            Self::NotMyVbucket { .. } => "Not my vbucket".to_string(),
+            Self::DurabilityImpossible { .. } => "Durability impossible".to_string(),
+            Self::DurabilityAmbiguous { .. } => "Durability ambiguous".to_string(),
+            Self::SyncWriteInProgress { .. } => "Sync write in progress".to_string(),
+            Self::TemporaryFailure { .. } => "Temporary failure".to_string(),
+            Self::RateLimited { .. } => "Rate limited".to_string(),
             Self::AccessError { .. } => "Access error".to_string(),
             Self::AuthError { .. } => "Authentication error".to_string(),
             Self::Timeout { .. } => "Timeout".to_string(),
@@ -161,10 +221,13 @@ impl ClientError {
             }
             Self::KVCouldNotConnect { .. } => "Could not establish kv connection".to_string(),
             Self::PathNotFound { .. } => "Path not found".to_string(),
+            Self::PathAlreadyExists { .. } => "Path already exists".to_string(),
+            Self::PathMismatch { .. } => "Path mismatch".to_string(),
             Self::InvalidSample { .. } => "Invalid sample bucket".to_string(),
             Self::SampleAlreadyLoaded { .. } => "Sample bucket already loaded".to_string(),
             Self::RequestUnauthorized {} => "Request unauthorized".to_string(),
             Self::AccessDenied { .. } => "Access Denied".to_string(),
+            Self::KvServerError { source, .. } => source.message(),
         }
     }
 
@@ -189,6 +252,14 @@ impl ClientError {
             },
             Self::KeyNotFound { key } => format!("Key {} was not found, does it exist in the specified collection?", key),
             Self::KeyAlreadyExists { key } => format!("Key {} already exists, is the correct collection being used?", key),
+            Self::CasMismatch { key } => format!("Key {} has been changed since the given CAS was read, fetch the document again and retry", key),
+            Self::DocumentLocked { key } => format!("Key {} is locked, unlock it with the CAS returned when it was locked or wait for the lock to expire", key),
+            Self::NotMyVbucket { key, .. } => format!("The node contacted for key {} does not own its vbucket, is the cluster rebalancing?", key),
+            Self::DurabilityImpossible { key } => format!("Durability requirements for key {} cannot be met, are there enough data nodes for the bucket replicas?", key),
+            Self::DurabilityAmbiguous { key } => format!("Durability of the write to key {} is unknown, the write may or may not have been applied", key),
+            Self::SyncWriteInProgress { key } => format!("A durable write is already in progress for key {}, try again later", key),
+            Self::TemporaryFailure { key } => format!("The server could not handle the request for key {} in time, is the bucket short of memory? Retries can be configured with kv-max-retries", key),
+            Self::RateLimited { key } => format!("The request for key {} was rate limited, are the limits of the user or scope being reached?", key),
             Self::AccessError { reason } => {
                 if let Some(r) = reason {
                     r.to_string()
@@ -222,6 +293,12 @@ impl ClientError {
             Self::PathNotFound { key, path } => {
                 format!("Path {} was not found in doc with key {}", path, key)
             }
+            Self::PathAlreadyExists { key, path } => {
+                format!("Path {} already exists in doc with key {}", path, key)
+            }
+            Self::PathMismatch { key, path } => {
+                format!("Path {} in doc with key {} does not match the type expected by the operation", path, key)
+            }
             Self::InvalidSample { sample } => {
                 format!("Sample {} is not a valid sample", sample)
             }
@@ -234,9 +311,121 @@ impl ClientError {
             Self::AccessDenied {reason} => {
                 reason.to_string()
             }
+            Self::KvServerError { source, status, code } => {
+                format!("{} - the server returned {} ({:#04x}): {}", source.expanded_message(), code.name, status, code.description)
+            }
+        }
+    }
+
+    // kind returns the underlying error, without any error map description attached.
+    pub fn kind(&self) -> &ClientError {
+        match self {
+            Self::KvServerError { source, .. } => source.kind(),
+            _ => self,
         }
     }
 
+    // kind_name is a short name for the kind of error, which is stable so that failures can be
+    // filtered on.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Self::ConfigurationLoadFailed { .. } => "configuration_load_failed",
+            Self::CollectionNotFound { .. } => "collection_not_found",
+            Self::ClusterNotContactable { .. } => "cluster_not_contactable",
+            Self::CollectionUnknownDuringRequest { .. } => "collection_unknown_during_request",
+            Self::ScopeNotFound { .. } => "scope_not_found",
+            Self::KeyNotFound { .. } => "key_not_found",
+            Self::KeyAlreadyExists { .. } => "key_already_exists",
+            Self::CasMismatch { .. } => "cas_mismatch",
+            Self::DocumentLocked { .. } => "document_locked",
+            Self::NotMyVbucket { .. } => "not_my_vbucket",
+            Self::DurabilityImpossible { .. } => "durability_impossible",
+            Self::DurabilityAmbiguous { .. } => "durability_ambiguous",
+            Self::SyncWriteInProgress { .. } => "sync_write_in_progress",
+            Self::TemporaryFailure { .. } => "temporary_failure",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::AccessError { .. } => "access_error",
+            Self::AuthError { .. } => "auth_error",
+            Self::Timeout { .. } => "timeout",
+            Self::Cancelled { .. } => "cancelled",
+            Self::CapellaClusterNotFound { .. } => "capella_cluster_not_found",
+            Self::RequestFailed { .. } => "request_failed",
+            Self::KVCouldNotConnect { .. } => "kv_could_not_connect",
+            Self::PathNotFound { .. } => "path_not_found",
+            Self::PathAlreadyExists { .. } => "path_already_exists",
+            Self::PathMismatch { .. } => "path_mismatch",
+            Self::InvalidSample { .. } => "invalid_sample",
+            Self::SampleAlreadyLoaded { .. } => "sample_already_loaded",
+            Self::RequestUnauthorized { .. } => "request_unauthorized",
+            Self::AccessDenied { .. } => "access_denied",
+            Self::KvServerError { source, .. } => source.kind_name(),
+        }
+    }
+
+    // is_temporary is whether the request failed because of the current state of the server or
+    // document, rather than the request itself, and so may succeed if retried.
+    pub fn is_temporary(&self) -> bool {
+        matches!(
+            self.kind(),
+            Self::TemporaryFailure { .. }
+                | Self::RateLimited { .. }
+                | Self::DocumentLocked { .. }
+                | Self::SyncWriteInProgress { .. }
+        )
+    }
+
+    // error_code returns the error map entry for the status that caused the error, if known.
+    pub fn error_code(&self) -> Option<&ErrorCode> {
+        match self {
+            Self::KvServerError { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
+    // with_error_map attaches the error map entry for the status to the error, if there is one.
+    pub(crate) fn with_error_map(self, status: Status, error_map: Option<&ErrorMap>) -> Self {
+        match error_map.and_then(|m| m.get(status.encoded())) {
+            Some(code) => ClientError::KvServerError {
+                source: Box::new(self),
+                status: status.encoded(),
+                code: code.clone(),
+            },
+            None => self,
+        }
+    }
+
+    // make_kv_doc_response_error creates the error for a failed response, consuming the body as
+    // needed.
+    pub fn make_kv_doc_response_error(
+        response: &mut KvResponse,
+        key: String,
+        cid: u32,
+        path: Option<String>,
+    ) -> Self {
+        let status = response.status();
+        if status == Status::NotMyVbucket {
+            // The body of a not my vbucket response may contain the current bucket config.
+            return ClientError::NotMyVbucket {
+                key,
+                config: response.body().map(|b| b.to_vec()),
+            };
+        }
+
+        // Writes which would overwrite an existing document only fail with key exists when the CAS
+        // given doesn't match the document's.
+        if status == Status::KeyExists
+            && matches!(
+                response.opcode(),
+                Opcode::Set | Opcode::Replace | Opcode::Remove | Opcode::Unlock
+            )
+        {
+            return ClientError::CasMismatch { key };
+        }
+
+        let reason = ClientError::try_parse_kv_fail_body(response);
+        ClientError::make_kv_doc_op_error(status, reason, key, cid, path)
+    }
+
     pub fn try_parse_kv_fail_body(response: &mut KvResponse) -> Option<String> {
         match response.body() {
             Some(b) => match serde_json::from_slice::<KVErrorContext>(&b) {
@@ -259,10 +448,32 @@ impl ClientError {
             Status::AccessError => ClientError::AccessError { reason },
             Status::KeyNotFound => ClientError::KeyNotFound { key },
             Status::KeyExists => ClientError::KeyAlreadyExists { key },
+            // Appending or prepending to a document which doesn't exist fails as not stored.
+            Status::NotStored => ClientError::KeyNotFound { key },
+            Status::Locked => ClientError::DocumentLocked { key },
+            Status::NotMyVbucket => ClientError::NotMyVbucket { key, config: None },
+            Status::DurabilityImpossible => ClientError::DurabilityImpossible { key },
+            Status::SyncWriteAmbiguous => ClientError::DurabilityAmbiguous { key },
+            Status::SyncWriteInProgress | Status::SyncWriteReCommitInProgress => {
+                ClientError::SyncWriteInProgress { key }
+            }
+            Status::Busy | Status::TemporaryFailure => ClientError::TemporaryFailure { key },
+            Status::RateLimitedNetworkIngress
+            | Status::RateLimitedNetworkEgress
+            | Status::RateLimitedMaxConnections
+            | Status::RateLimitedMaxCommands => ClientError::RateLimited { key },
             Status::PathNotFound => ClientError::PathNotFound {
                 key,
                 path: path.unwrap_or("".to_string()),
             },
+            Status::PathExists => ClientError::PathAlreadyExists {
+                key,
+                path: path.unwrap_or("".to_string()),
+            },
+            Status::PathMismatch => ClientError::PathMismatch {
+                key,
+                path: path.unwrap_or("".to_string()),
+            },
             Status::CollectionUnknown => ClientError::CollectionUnknownDuringRequest { key, cid },
             _ => ClientError::RequestFailed {
                 reason: Some(status.as_string()),
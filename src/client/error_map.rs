@@ -0,0 +1,193 @@
+//! The error map published by the data service, describing the status codes it can return.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// The version of the error map that we understand, version 2 adds retry specifications.
+pub(crate) const ERROR_MAP_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub struct ErrorMap {
+    errors: HashMap<u16, ErrorCode>,
+}
+
+impl ErrorMap {
+    // from_slice parses the error map from the body of a get error map response.
+    pub(crate) fn from_slice(body: &[u8]) -> Result<Self, serde_json::Error> {
+        let raw: RawErrorMap = serde_json::from_slice(body)?;
+
+        // Statuses are keyed by their hex value without a prefix.
+        let errors = raw
+            .errors
+            .into_iter()
+            .filter_map(|(status, code)| {
+                u16::from_str_radix(&status, 16)
+                    .ok()
+                    .map(|status| (status, code))
+            })
+            .collect();
+
+        Ok(Self { errors })
+    }
+
+    pub fn get(&self, status: u16) -> Option<&ErrorCode> {
+        self.errors.get(&status)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawErrorMap {
+    errors: HashMap<String, ErrorCode>,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Deserialize)]
+pub struct ErrorCode {
+    pub name: String,
+    #[serde(rename = "desc")]
+    pub description: String,
+    #[serde(default)]
+    pub attrs: Vec<ErrorAttribute>,
+    pub retry: Option<RetrySpecification>,
+}
+
+impl ErrorCode {
+    pub fn has_attribute(&self, attribute: ErrorAttribute) -> bool {
+        self.attrs.contains(&attribute)
+    }
+
+    // retry_delay is how long to wait before retrying a request for the given attempt, following
+    // the retry specification from the server if there is one.
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        match &self.retry {
+            Some(spec) => spec.delay(attempt),
+            None => DEFAULT_RETRY_DELAY,
+        }
+    }
+}
+
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Deserialize)]
+pub struct RetrySpecification {
+    strategy: RetryStrategy,
+    interval: u32,
+    #[serde(default)]
+    after: u32,
+    #[serde(default)]
+    ceil: u32,
+}
+
+impl RetrySpecification {
+    fn delay(&self, attempt: u32) -> Duration {
+        if attempt == 0 && self.after > 0 {
+            return Duration::from_millis(self.after as u64);
+        }
+
+        let interval = self.interval as u64;
+        let delay = match self.strategy {
+            RetryStrategy::Constant => interval,
+            RetryStrategy::Linear => interval.saturating_mul(attempt as u64 + 1),
+            RetryStrategy::Exponential => interval.saturating_pow(attempt + 1),
+        };
+
+        if self.ceil > 0 {
+            Duration::from_millis(delay.min(self.ceil as u64))
+        } else {
+            Duration::from_millis(delay)
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Deserialize)]
+enum RetryStrategy {
+    #[serde(rename = "exponential")]
+    Exponential,
+    #[serde(rename = "linear")]
+    Linear,
+    #[serde(rename = "constant")]
+    Constant,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Deserialize)]
+pub enum ErrorAttribute {
+    #[serde(rename = "success")]
+    Success,
+    #[serde(rename = "item-only")]
+    ItemOnly,
+    #[serde(rename = "invalid-input")]
+    InvalidInput,
+    #[serde(rename = "fetch-config")]
+    FetchConfig,
+    #[serde(rename = "conn-state-invalidated")]
+    ConnStateInvalidated,
+    #[serde(rename = "auth")]
+    Auth,
+    #[serde(rename = "special-handling")]
+    SpecialHandling,
+    #[serde(rename = "support")]
+    Support,
+    #[serde(rename = "temp")]
+    Temp,
+    #[serde(rename = "internal")]
+    Internal,
+    #[serde(rename = "retry-now")]
+    RetryNow,
+    #[serde(rename = "retry-later")]
+    RetryLater,
+    #[serde(rename = "subdoc")]
+    Subdoc,
+    #[serde(rename = "dcp")]
+    Dcp,
+    #[serde(rename = "auto-retry")]
+    AutoRetry,
+    #[serde(rename = "item-locked")]
+    ItemLocked,
+    #[serde(rename = "item-deleted")]
+    ItemDeleted,
+    // Newer servers may add attributes that we don't know about.
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_error_map() {
+        let body = r#"{
+            "version": 2,
+            "revision": 1,
+            "errors": {
+                "86": {
+                    "name": "ETMPFAIL",
+                    "desc": "Temporary failure",
+                    "attrs": ["temp", "retry-later", "some-new-attribute"],
+                    "retry": {"strategy": "exponential", "interval": 2, "after": 10, "max-duration": 500, "ceil": 100}
+                },
+                "1f": {
+                    "name": "AUTH_STALE",
+                    "desc": "Reauthentication required",
+                    "attrs": ["conn-state-invalidated", "auth"]
+                }
+            }
+        }"#;
+
+        let map = ErrorMap::from_slice(body.as_bytes()).unwrap();
+
+        let tmpfail = map.get(0x86).unwrap();
+        assert_eq!("ETMPFAIL", tmpfail.name);
+        assert!(tmpfail.has_attribute(ErrorAttribute::RetryLater));
+        assert!(tmpfail.has_attribute(ErrorAttribute::Unknown));
+        assert_eq!(Duration::from_millis(10), tmpfail.retry_delay(0));
+        assert_eq!(Duration::from_millis(4), tmpfail.retry_delay(1));
+        assert_eq!(Duration::from_millis(100), tmpfail.retry_delay(10));
+
+        let stale = map.get(0x1f).unwrap();
+        assert!(stale.has_attribute(ErrorAttribute::ConnStateInvalidated));
+        assert_eq!(DEFAULT_RETRY_DELAY, stale.retry_delay(0));
+
+        assert!(map.get(0x01).is_none());
+    }
+}
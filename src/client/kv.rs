@@ -1,6 +1,13 @@
-use crate::client::codec::KeyValueCodec;
-use crate::client::protocol::{request, KvRequest, KvResponse, Status};
-use crate::client::{protocol, ClientError};
+use crate::client::codec::{DcpStreamReader, KeyValueCodec};
+use crate::client::durability::Durability;
+use crate::client::error_map::{ErrorMap, ERROR_MAP_VERSION};
+use crate::client::kv_trace::{trace_frame, Direction};
+use crate::client::protocol::{request, KvRequest, KvResponse, Status, DATATYPE_JSON};
+use crate::client::sasl::{plain_body, ScramClient};
+use crate::client::{
+    protocol, ClientError, CollectionsManifest, DocumentFormat, SaslMechanism, SubdocLookupSpec,
+    SubdocMutationSpec,
+};
 use crate::RustTlsConfig;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::lock::Mutex as AsyncMutex;
@@ -8,8 +15,9 @@ use futures::{SinkExt, StreamExt};
 use log::{debug, trace, warn};
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio::sync::oneshot::Receiver;
@@ -19,15 +27,41 @@ use tokio_rustls::TlsConnector;
 use tokio_util::codec::{FramedRead, FramedWrite};
 use uuid::Uuid;
 
+// The percentage of its original size that a compressed value must be under to be sent compressed.
+const MIN_COMPRESSION_RATIO: usize = 83;
+
+enum ResponseSender {
+    Single(oneshot::Sender<KvResponse>),
+    Stream(mpsc::UnboundedSender<KvResponse>),
+}
+
+// The size of the buffer that the server fills with DCP messages before waiting for them to be
+// acknowledged.
+const DCP_BUFFER_SIZE: u32 = 20 * 1024 * 1024;
+
+// How often the server sends noops on an idle DCP connection, in seconds.
+const DCP_NOOP_INTERVAL: u32 = 20;
+
+// DCP open flags.
+const DCP_OPEN_PRODUCER: u32 = 0x01;
+const DCP_OPEN_NO_VALUE: u32 = 0x08;
+const DCP_OPEN_INCLUDE_DELETE_TIMES: u32 = 0x20;
+
 pub struct KvEndpoint {
     tx: mpsc::Sender<Bytes>,
     opaque: AtomicU32,
-    in_flight: Arc<AsyncMutex<HashMap<u32, oneshot::Sender<KvResponse>>>>,
+    in_flight: Arc<AsyncMutex<HashMap<u32, ResponseSender>>>,
+    // dcp receives the messages sent by the server once the connection has been opened for DCP.
+    dcp: Arc<StdMutex<Option<mpsc::UnboundedSender<Bytes>>>>,
+    closed: Arc<AtomicBool>,
     collections_enabled: bool,
+    sync_replication_enabled: bool,
+    snappy_enabled: bool,
+    json_enabled: bool,
     local_addr: String,
     remote_addr: String,
     uuid: String,
-    // error_map: Option<ErrorMap>,
+    error_map: Option<ErrorMap>,
 }
 
 impl KvEndpoint {
@@ -38,6 +72,8 @@ impl KvEndpoint {
         password: String,
         bucket: String,
         tls_config: Option<RustTlsConfig>,
+        sasl_mechanism: Option<SaslMechanism>,
+        for_dcp: bool,
     ) -> Result<KvEndpoint, ClientError> {
         let remote_addr = format!("{}:{}", hostname, port);
 
@@ -71,6 +107,9 @@ impl KvEndpoint {
                 socket,
                 local_addr.to_string(),
                 remote_addr,
+                true,
+                sasl_mechanism,
+                for_dcp,
             )
             .await
         } else {
@@ -89,11 +128,15 @@ impl KvEndpoint {
                 socket,
                 local_addr.to_string(),
                 remote_addr,
+                false,
+                sasl_mechanism,
+                for_dcp,
             )
             .await
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn setup<C: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
         username: String,
         password: String,
@@ -101,21 +144,29 @@ impl KvEndpoint {
         stream: C,
         local_addr: String,
         remote_addr: String,
+        tls_enabled: bool,
+        sasl_mechanism: Option<SaslMechanism>,
+        for_dcp: bool,
     ) -> Result<KvEndpoint, ClientError> {
         let uuid = Uuid::new_v4().to_string();
         let (tx, mut rx) = mpsc::channel::<Bytes>(1024);
-        let in_flight = Arc::new(AsyncMutex::new(
-            HashMap::<u32, oneshot::Sender<KvResponse>>::new(),
-        ));
+        let in_flight = Arc::new(AsyncMutex::new(HashMap::<u32, ResponseSender>::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let dcp = Arc::new(StdMutex::new(None::<mpsc::UnboundedSender<Bytes>>));
         let mut ep = KvEndpoint {
             opaque: AtomicU32::new(0),
             in_flight: Arc::clone(&in_flight),
+            dcp: Arc::clone(&dcp),
+            closed: Arc::clone(&closed),
             tx,
             collections_enabled: false,
+            sync_replication_enabled: false,
+            snappy_enabled: false,
+            json_enabled: false,
             local_addr,
             remote_addr,
             uuid: uuid.clone(),
-            // error_map: None,
+            error_map: None,
         };
 
         let (r, w) = tokio::io::split(stream);
@@ -124,12 +175,29 @@ impl KvEndpoint {
 
         // Read thread.
         let recv_uuid = uuid.clone();
+        let (recv_local_addr, recv_remote_addr) = (ep.local_addr.clone(), ep.remote_addr.clone());
+        // A weak sender is used to reply to the server so that the connection still closes once
+        // the endpoint is dropped.
+        let reply_tx = ep.tx.downgrade();
         tokio::spawn(async move {
             loop {
                 if let Some(frame) = input.next().await {
                     match frame {
                         Ok(input) => {
-                            let response = KvResponse::from(&input.freeze());
+                            let input = input.freeze();
+                            trace_frame(
+                                &recv_uuid,
+                                &recv_local_addr,
+                                &recv_remote_addr,
+                                Direction::Received,
+                                &input,
+                            );
+                            if protocol::Magic::from(input[0]).is_request() {
+                                handle_server_request(&recv_uuid, input, &dcp, &reply_tx).await;
+                                continue;
+                            }
+
+                            let response = KvResponse::from(&input);
                             trace!(
                                 "Resolving response on {}. Opcode={}. Opaque={}. Status={}",
                                 recv_uuid,
@@ -139,17 +207,31 @@ impl KvEndpoint {
                             );
                             let requests = Arc::clone(&in_flight);
                             let mut map = requests.lock().await;
-                            let t = map.remove(&response.opaque());
+                            let t = match map.remove(&response.opaque()) {
+                                // Streamed requests receive responses until one doesn't succeed.
+                                Some(ResponseSender::Stream(sender))
+                                    if response.status() == Status::Success
+                                        && !response.ends_stream() =>
+                                {
+                                    map.insert(
+                                        response.opaque(),
+                                        ResponseSender::Stream(sender.clone()),
+                                    );
+                                    Some(ResponseSender::Stream(sender))
+                                }
+                                t => t,
+                            };
                             drop(map);
                             drop(requests);
 
                             if let Some(sender) = t {
-                                match sender.send(response) {
-                                    Ok(_) => {}
-                                    Err(_e) => {
-                                        warn!("{} could not send kv response", recv_uuid)
-                                    }
+                                let sent = match sender {
+                                    ResponseSender::Single(sender) => sender.send(response).is_ok(),
+                                    ResponseSender::Stream(sender) => sender.send(response).is_ok(),
                                 };
+                                if !sent {
+                                    warn!("{} could not send kv response", recv_uuid)
+                                }
                             } else {
                                 warn!(
                                     "{} has no entry in request map for {}",
@@ -162,15 +244,31 @@ impl KvEndpoint {
                             warn!("{} failed to read frame {}", recv_uuid, e.to_string());
                         }
                     };
+                } else {
+                    // The connection has been closed, nothing in flight will ever be responded
+                    // to so drop the senders to fail the requests.
+                    debug!("{} connection closed", recv_uuid);
+                    closed.store(true, Ordering::SeqCst);
+                    in_flight.lock().await.clear();
+                    dcp.lock().unwrap().take();
+                    return;
                 }
             }
         });
 
         // Send thread.
         let send_uuid = uuid.clone();
+        let (send_local_addr, send_remote_addr) = (ep.local_addr.clone(), ep.remote_addr.clone());
         tokio::spawn(async move {
             loop {
                 if let Some(packet) = rx.recv().await {
+                    trace_frame(
+                        &send_uuid,
+                        &send_local_addr,
+                        &send_remote_addr,
+                        Direction::Sent,
+                        &packet,
+                    );
                     match output.send(packet).await {
                         Ok(_) => {}
                         Err(_e) => {
@@ -178,14 +276,18 @@ impl KvEndpoint {
                         }
                     };
                 } else {
+                    // The endpoint has been dropped, so close the connection.
+                    let _ = output.close().await;
                     return;
                 }
             }
         });
 
-        let hello_rcvr = ep.send_hello().await?;
-        // let err_map_rcvr = ep.send_error_map().await.map(|r| Some(r))?;
-        let auth_rcvr = ep.send_auth(username, password).await?;
+        let hello_rcvr = ep.send_hello(for_dcp).await?;
+        let err_map_rcvr = ep.send_error_map().await?;
+        ep.authenticate(username, password, tls_enabled, sasl_mechanism)
+            .await?;
+        debug!("{} authenticated successfully", ep.uuid);
         let bucket_rcvr = ep.send_select_bucket(bucket).await?;
 
         let features = match hello_rcvr.await {
@@ -203,31 +305,18 @@ impl KvEndpoint {
             }
         };
         debug!("{} negotiated features {:?}", ep.uuid, features);
-        // if let Some(rcvr) = err_map_rcvr {
-        //     let error_map = match rcvr.await {
-        //         Ok(r) => match r {
-        //             Ok(result) => Some(result),
-        //             Err(_e) => None,
-        //         },
-        //         Err(_e) => None,
-        //     };
-        //     ep.error_map = error_map;
-        // }
-        match auth_rcvr.await {
-            Ok(r) => match r {
-                Ok(result) => result,
-                Err(e) => {
-                    return Err(e);
-                }
-            },
+        // The error map only improves how failures are handled, so carry on without one.
+        ep.error_map = match err_map_rcvr.await {
+            Ok(Ok(error_map)) => Some(error_map),
+            Ok(Err(e)) => {
+                debug!("{} failed to fetch error map: {}", ep.uuid, e);
+                None
+            }
             Err(e) => {
-                return Err(ClientError::RequestFailed {
-                    reason: Some(e.to_string()),
-                    key: None,
-                });
+                debug!("{} failed to fetch error map: {}", ep.uuid, e);
+                None
             }
         };
-        debug!("{} authenticated successfully", ep.uuid);
         match bucket_rcvr.await {
             Ok(r) => match r {
                 Ok(result) => result,
@@ -248,7 +337,21 @@ impl KvEndpoint {
             ep.collections_enabled = true;
         }
 
-        // debug!("Error Map: {:?}", ep.error_map);
+        if features.contains(&ServerFeature::SyncReplication) {
+            debug!("{} enabling sync replication", ep.uuid);
+            ep.sync_replication_enabled = true;
+        }
+
+        if features.contains(&ServerFeature::Snappy) {
+            debug!("{} enabling snappy", ep.uuid);
+            ep.snappy_enabled = true;
+        }
+
+        if features.contains(&ServerFeature::Json) {
+            debug!("{} enabling json", ep.uuid);
+            ep.json_enabled = true;
+        }
+
         Ok(ep)
     }
 
@@ -273,71 +376,192 @@ impl KvEndpoint {
         cid: u32,
         path: impl Into<Option<String>>,
     ) -> Result<KvResponse, ClientError> {
-        let mut response = self.await_response(rx, key.clone()).await?;
-        let status = response.status();
-        if status != Status::Success {
-            let reason = ClientError::try_parse_kv_fail_body(&mut response);
-            return Err(ClientError::make_kv_doc_op_error(
-                status,
-                reason,
-                key,
-                cid,
-                path.into(),
-            ));
+        let response = self.await_response(rx, key.clone()).await?;
+        self.handle_doc_response(response, key, cid, path.into())
+    }
+
+    // handle_doc_response turns a response which was not successful into an error.
+    fn handle_doc_response(
+        &self,
+        mut response: KvResponse,
+        key: String,
+        cid: u32,
+        path: Option<String>,
+    ) -> Result<KvResponse, ClientError> {
+        if response.status() != Status::Success {
+            let status = response.status();
+            return Err(
+                ClientError::make_kv_doc_response_error(&mut response, key, cid, path)
+                    .with_error_map(status, self.error_map.as_ref()),
+            );
         }
         Ok(response)
     }
 
-    pub async fn get_cid(
+    pub async fn get_collections_manifest(&self) -> Result<CollectionsManifest, ClientError> {
+        let req = KvRequest::new(
+            protocol::Opcode::GetCollectionsManifest,
+            0,
+            0,
+            0,
+            None,
+            None,
+            None,
+            0,
+        );
+        let mut resp = self
+            .send_control_request(req, "fetching collections manifest")
+            .await?;
+
+        let body = resp.body().unwrap_or_default();
+        CollectionsManifest::from_slice(&body).map_err(|e| ClientError::RequestFailed {
+            reason: Some(format!("invalid collections manifest: {}", e)),
+            key: None,
+        })
+    }
+
+    pub async fn get(
+        &self,
+        key: String,
+        partition: u16,
+        collection_id: u32,
+    ) -> Result<KvResponse, ClientError> {
+        let req = KvRequest::new(
+            protocol::Opcode::Get,
+            0,
+            partition,
+            0,
+            Some(Bytes::from(key.clone())),
+            None,
+            None,
+            collection_id,
+        );
+
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        self.await_and_handle_doc_response(rx, key, collection_id, None)
+            .await
+    }
+
+    // get_and_touch fetches a document and updates its expiry.
+    pub async fn get_and_touch(
+        &self,
+        key: String,
+        expiry: u32,
+        partition: u16,
+        collection_id: u32,
+    ) -> Result<KvResponse, ClientError> {
+        let mut extras = BytesMut::with_capacity(4);
+        extras.put_u32(expiry);
+        let req = KvRequest::new(
+            protocol::Opcode::GetAndTouch,
+            0,
+            partition,
+            0,
+            Some(Bytes::from(key.clone())),
+            Some(extras.freeze()),
+            None,
+            collection_id,
+        );
+
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        self.await_and_handle_doc_response(rx, key, collection_id, None)
+            .await
+    }
+
+    // get_and_lock fetches a document and locks it for the lock time in seconds. The document can
+    // only be changed or unlocked using the CAS of the response until the lock expires.
+    pub async fn get_and_lock(
         &self,
-        scope_name: String,
-        collection_name: String,
+        key: String,
+        lock_time: u32,
+        partition: u16,
+        collection_id: u32,
     ) -> Result<KvResponse, ClientError> {
+        let mut extras = BytesMut::with_capacity(4);
+        extras.put_u32(lock_time);
         let req = KvRequest::new(
-            protocol::Opcode::GetCollectionID,
+            protocol::Opcode::GetAndLock,
             0,
+            partition,
             0,
+            Some(Bytes::from(key.clone())),
+            Some(extras.freeze()),
+            None,
+            collection_id,
+        );
+
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        self.await_and_handle_doc_response(rx, key, collection_id, None)
+            .await
+    }
+
+    pub async fn unlock(
+        &self,
+        key: String,
+        cas: u64,
+        partition: u16,
+        collection_id: u32,
+    ) -> Result<KvResponse, ClientError> {
+        let req = KvRequest::new(
+            protocol::Opcode::Unlock,
             0,
+            partition,
+            cas,
+            Some(Bytes::from(key.clone())),
             None,
             None,
-            Some(Bytes::from(
-                format!("{}.{}", scope_name, collection_name).into_bytes(),
-            )),
+            collection_id,
+        );
+
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        self.await_and_handle_doc_response(rx, key, collection_id, None)
+            .await
+    }
+
+    // touch updates the expiry of a document without changing its content.
+    pub async fn touch(
+        &self,
+        key: String,
+        expiry: u32,
+        partition: u16,
+        collection_id: u32,
+    ) -> Result<KvResponse, ClientError> {
+        let mut extras = BytesMut::with_capacity(4);
+        extras.put_u32(expiry);
+        let req = KvRequest::new(
+            protocol::Opcode::Touch,
             0,
+            partition,
+            0,
+            Some(Bytes::from(key.clone())),
+            Some(extras.freeze()),
+            None,
+            collection_id,
         );
 
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
 
-        let mut resp = self.await_response(rx, None).await?;
-        if resp.status() == Status::Success {
-            return Ok(resp);
-        }
-        let reason = ClientError::try_parse_kv_fail_body(&mut resp);
-        let error = match resp.status() {
-            Status::AuthError => ClientError::AuthError { reason },
-            Status::AccessError => ClientError::AccessError { reason },
-            Status::CollectionUnknown => ClientError::CollectionNotFound {
-                scope_name,
-                name: collection_name,
-            },
-            Status::ScopeUnknown => ClientError::ScopeNotFound { name: scope_name },
-            _ => ClientError::RequestFailed {
-                reason: Some(reason.unwrap_or_else(|| resp.status().as_string())),
-                key: None,
-            },
-        };
-        Err(error)
+        self.await_and_handle_doc_response(rx, key, collection_id, None)
+            .await
     }
 
-    pub async fn get(
+    pub async fn get_replica(
         &self,
         key: String,
         partition: u16,
         collection_id: u32,
     ) -> Result<KvResponse, ClientError> {
         let req = KvRequest::new(
-            protocol::Opcode::Get,
+            protocol::Opcode::GetReplica,
             0,
             partition,
             0,
@@ -360,12 +584,13 @@ impl KvEndpoint {
         partition: u16,
         collection_id: u32,
         path: String,
+        xattr: bool,
     ) -> Result<KvResponse, ClientError> {
         let mut extras = BytesMut::with_capacity(4);
         // Extras contain path length and flag
         extras.put_u16(path.len() as u16);
-        // 0x0 flag value indicates normal sub doc get
-        extras.put_u8(0);
+        // 0x04 flag value looks the path up in the extended attributes rather than the body
+        extras.put_u8(if xattr { 0x04 } else { 0 });
 
         let req = KvRequest::new(
             protocol::Opcode::SubdocGet,
@@ -390,25 +615,21 @@ impl KvEndpoint {
         key: String,
         partition: u16,
         collection_id: u32,
-        paths: Vec<String>,
+        specs: Vec<SubdocLookupSpec>,
     ) -> Result<KvResponse, ClientError> {
-        let mut path_bytes_list: Vec<Vec<u8>> = vec![];
-        let mut path_bytes_total = 0;
-
-        for p in paths {
-            let path_bytes = Bytes::from(p.clone());
-            path_bytes_list.push(path_bytes.to_vec());
-            path_bytes_total += path_bytes.len();
-        }
-
-        let mut value_buf = BytesMut::with_capacity(path_bytes_total * 4);
-        for path_bytes in path_bytes_list {
-            value_buf.put_u8(protocol::Opcode::SubdocGet.encoded());
-            value_buf.put_u8(0);
-            value_buf.put_u16(path_bytes.len() as u16);
-            for pb in path_bytes {
-                value_buf.put_u8(pb);
-            }
+        let mut value_buf = BytesMut::new();
+        for spec in &specs {
+            // An empty body path fetches the whole document
+            let opcode = if spec.path.is_empty() && !spec.xattr {
+                protocol::Opcode::Get
+            } else {
+                protocol::Opcode::SubdocGet
+            };
+            value_buf.put_u8(opcode.encoded());
+            // 0x04 flag value looks the path up in the extended attributes rather than the body
+            value_buf.put_u8(if spec.xattr { 0x04 } else { 0 });
+            value_buf.put_u16(spec.path.len() as u16);
+            value_buf.put(spec.path.as_bytes());
         }
 
         let mut extras = BytesMut::with_capacity(1);
@@ -428,24 +649,106 @@ impl KvEndpoint {
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
 
-        self.await_and_handle_doc_response(rx, key, collection_id, None)
-            .await
+        // When only some of the paths fail the status of each is in the body, the lookup as a
+        // whole only fails when the document cannot be read.
+        let response = self.await_response(rx, key.clone()).await?;
+        if response.status() == Status::SubdocMultiPathFailure {
+            return Ok(response);
+        }
+        self.handle_doc_response(response, key, collection_id, None)
+    }
+
+    pub async fn sub_doc_multi_mutation(
+        &self,
+        key: String,
+        partition: u16,
+        collection_id: u32,
+        specs: Vec<SubdocMutationSpec>,
+        expiry: u32,
+        durability: Durability,
+    ) -> Result<KvResponse, ClientError> {
+        let mut value_buf = BytesMut::new();
+        for spec in &specs {
+            let value = spec.value.clone().unwrap_or_default();
+            value_buf.put_u8(spec.op.opcode().encoded());
+            // 0x01 flag value creates any missing parent paths
+            value_buf.put_u8(if spec.create_path { 0x01 } else { 0 });
+            value_buf.put_u16(spec.path.len() as u16);
+            value_buf.put_u32(value.len() as u32);
+            value_buf.put(spec.path.as_bytes());
+            value_buf.put(value.as_slice());
+        }
+
+        // Expiry is only sent when set, the server determines the layout from the extras length
+        let extras = if expiry > 0 {
+            let mut extras = BytesMut::with_capacity(4);
+            extras.put_u32(expiry);
+            Some(extras.freeze())
+        } else {
+            None
+        };
+
+        let mut req = KvRequest::new(
+            protocol::Opcode::SubdocMultiMutation,
+            0,
+            partition,
+            0,
+            Some(Bytes::from(key.clone())),
+            extras,
+            Some(value_buf.freeze()),
+            collection_id,
+        );
+        req.set_framing_extras(self.durability_frame(durability, &key)?);
+
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        let mut response = self.await_response(rx, key.clone()).await?;
+        match response.status() {
+            Status::Success => Ok(response),
+            Status::SubdocMultiPathFailure => {
+                // The body contains the index of the failed spec and its status
+                let (path, status) = match response.body() {
+                    Some(mut body) if body.len() >= 3 => {
+                        let index = body.get_u8() as usize;
+                        let status = Status::from(body.get_u16());
+                        (specs.get(index).map(|s| s.path.clone()), status)
+                    }
+                    _ => (None, Status::SubdocMultiPathFailure),
+                };
+                Err(
+                    ClientError::make_kv_doc_op_error(status, None, key, collection_id, path)
+                        .with_error_map(status, self.error_map.as_ref()),
+                )
+            }
+            status => Err(ClientError::make_kv_doc_response_error(
+                &mut response,
+                key,
+                collection_id,
+                None,
+            )
+            .with_error_map(status, self.error_map.as_ref())),
+        }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn set(
         &self,
         key: String,
         value: Vec<u8>,
+        datatype: u8,
+        flags: u32,
         expiry: u32,
         partition: u16,
         collection_id: u32,
+        durability: Durability,
     ) -> Result<KvResponse, ClientError> {
         let mut extras = BytesMut::with_capacity(8);
-        extras.put_u32(0);
+        extras.put_u32(flags);
         extras.put_u32(expiry);
-        let req = KvRequest::new(
+        let mut req = KvRequest::new(
             protocol::Opcode::Set,
-            0,
+            datatype,
             partition,
             0,
             Some(Bytes::from(key.clone())),
@@ -453,6 +756,7 @@ impl KvEndpoint {
             Some(value.into()),
             collection_id,
         );
+        req.set_framing_extras(self.durability_frame(durability, &key)?);
 
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
@@ -461,20 +765,24 @@ impl KvEndpoint {
             .await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn add(
         &self,
         key: String,
         value: Vec<u8>,
+        datatype: u8,
+        flags: u32,
         expiry: u32,
         partition: u16,
         collection_id: u32,
+        durability: Durability,
     ) -> Result<KvResponse, ClientError> {
         let mut extras = BytesMut::with_capacity(8);
-        extras.put_u32(0);
+        extras.put_u32(flags);
         extras.put_u32(expiry);
-        let req = KvRequest::new(
+        let mut req = KvRequest::new(
             protocol::Opcode::Add,
-            0,
+            datatype,
             partition,
             0,
             Some(Bytes::from(key.clone())),
@@ -482,6 +790,7 @@ impl KvEndpoint {
             Some(value.into()),
             collection_id,
         );
+        req.set_framing_extras(self.durability_frame(durability, &key)?);
 
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
@@ -490,27 +799,33 @@ impl KvEndpoint {
             .await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn replace(
         &self,
         key: String,
         value: Vec<u8>,
+        datatype: u8,
+        flags: u32,
         expiry: u32,
+        cas: u64,
         partition: u16,
         collection_id: u32,
+        durability: Durability,
     ) -> Result<KvResponse, ClientError> {
         let mut extras = BytesMut::with_capacity(8);
-        extras.put_u32(0);
+        extras.put_u32(flags);
         extras.put_u32(expiry);
-        let req = KvRequest::new(
+        let mut req = KvRequest::new(
             protocol::Opcode::Replace,
-            0,
+            datatype,
             partition,
-            0,
+            cas,
             Some(Bytes::from(key.clone())),
             Some(extras.freeze()),
             Some(value.into()),
             collection_id,
         );
+        req.set_framing_extras(self.durability_frame(durability, &key)?);
 
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
@@ -522,19 +837,22 @@ impl KvEndpoint {
     pub async fn remove(
         &self,
         key: String,
+        cas: u64,
         partition: u16,
         collection_id: u32,
+        durability: Durability,
     ) -> Result<KvResponse, ClientError> {
-        let req = KvRequest::new(
+        let mut req = KvRequest::new(
             protocol::Opcode::Remove,
             0,
             partition,
-            0,
+            cas,
             Some(Bytes::from(key.clone())),
             None,
             None,
             collection_id,
         );
+        req.set_framing_extras(self.durability_frame(durability, &key)?);
 
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
@@ -543,37 +861,538 @@ impl KvEndpoint {
             .await
     }
 
-    pub async fn noop(&self) -> Result<KvResponse, ClientError> {
-        let req = KvRequest::new(protocol::Opcode::Noop, 0, 0, 0, None, None, None, 0);
+    // counter increments or decrements the numeric document at the key. When no initial value
+    // is given the document must already exist.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn counter(
+        &self,
+        opcode: protocol::Opcode,
+        key: String,
+        delta: u64,
+        initial: Option<u64>,
+        expiry: u32,
+        partition: u16,
+        collection_id: u32,
+        durability: Durability,
+    ) -> Result<KvResponse, ClientError> {
+        let mut extras = BytesMut::with_capacity(20);
+        extras.put_u64(delta);
+        extras.put_u64(initial.unwrap_or(0));
+        // An expiry of 0xffffffff tells the server not to create the document.
+        extras.put_u32(if initial.is_some() { expiry } else { u32::MAX });
+        let mut req = KvRequest::new(
+            opcode,
+            0,
+            partition,
+            0,
+            Some(Bytes::from(key.clone())),
+            Some(extras.freeze()),
+            None,
+            collection_id,
+        );
+        req.set_framing_extras(self.durability_frame(durability, &key)?);
 
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
 
-        let mut resp = self.await_response(rx, None).await?;
-        if resp.status() == Status::Success {
-            return Ok(resp);
-        }
-        let reason = ClientError::try_parse_kv_fail_body(&mut resp);
-        let error = match resp.status() {
-            Status::AuthError => ClientError::AuthError { reason },
-            Status::AccessError => ClientError::AccessError { reason },
-            _ => ClientError::RequestFailed {
-                reason: Some(reason.unwrap_or_else(|| resp.status().as_string())),
-                key: None,
-            },
-        };
-        Err(error)
+        self.await_and_handle_doc_response(rx, key, collection_id, None)
+            .await
     }
 
-    pub fn remote(&self) -> String {
-        self.remote_addr.clone()
-    }
+    // concat appends or prepends the raw value to the existing document at the key.
+    pub async fn concat(
+        &self,
+        opcode: protocol::Opcode,
+        key: String,
+        value: Vec<u8>,
+        partition: u16,
+        collection_id: u32,
+        durability: Durability,
+    ) -> Result<KvResponse, ClientError> {
+        let mut req = KvRequest::new(
+            opcode,
+            0,
+            partition,
+            0,
+            Some(Bytes::from(key.clone())),
+            None,
+            Some(value.into()),
+            collection_id,
+        );
+        req.set_framing_extras(self.durability_frame(durability, &key)?);
+
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        self.await_and_handle_doc_response(rx, key, collection_id, None)
+            .await
+    }
+
+    // stats fetches a group of stats from the node, the default group is fetched when the group
+    // is empty.
+    pub async fn stats(&self, group: String) -> Result<Vec<(String, String)>, ClientError> {
+        let key = if group.is_empty() {
+            None
+        } else {
+            Some(Bytes::from(group.clone()))
+        };
+        let req = KvRequest::new(protocol::Opcode::Stat, 0, 0, 0, key, None, None, 0);
+
+        let mut responses = self.send_streaming(req).await?;
+        let mut stats = vec![];
+        while let Some(mut response) = responses.recv().await {
+            if response.status() != Status::Success {
+                let reason = ClientError::try_parse_kv_fail_body(&mut response);
+                return Err(ClientError::RequestFailed {
+                    reason: Some(reason.unwrap_or_else(|| {
+                        format!("failed to fetch {} stats: {}", group, response.status())
+                    })),
+                    key: None,
+                }
+                .with_error_map(response.status(), self.error_map.as_ref()));
+            }
+
+            let name = match response.key() {
+                Some(name) => name,
+                None => return Ok(stats),
+            };
+            let value = response.body().unwrap_or_default();
+            stats.push((
+                String::from_utf8_lossy(&name).to_string(),
+                String::from_utf8_lossy(&value).to_string(),
+            ));
+        }
+
+        Err(ClientError::RequestFailed {
+            reason: Some(format!("connection to {} closed", self.remote_addr)),
+            key: None,
+        })
+    }
+
+    pub async fn noop(&self) -> Result<KvResponse, ClientError> {
+        let req = KvRequest::new(protocol::Opcode::Noop, 0, 0, 0, None, None, None, 0);
+
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        let mut resp = self.await_response(rx, None).await?;
+        if resp.status() == Status::Success {
+            return Ok(resp);
+        }
+        let reason = ClientError::try_parse_kv_fail_body(&mut resp);
+        let error = match resp.status() {
+            Status::AuthError => ClientError::AuthError { reason },
+            Status::AccessError => ClientError::AccessError { reason },
+            _ => ClientError::RequestFailed {
+                reason: Some(reason.unwrap_or_else(|| resp.status().as_string())),
+                key: None,
+            },
+        };
+        Err(error.with_error_map(resp.status(), self.error_map.as_ref()))
+    }
+
+    // range_scan_create creates a scan of a vbucket, returning the id of the scan or nothing if
+    // there's nothing in the range.
+    pub async fn range_scan_create(
+        &self,
+        partition: u16,
+        body: Vec<u8>,
+    ) -> Result<Option<Bytes>, ClientError> {
+        let req = KvRequest::new(
+            protocol::Opcode::RangeScanCreate,
+            DATATYPE_JSON,
+            partition,
+            0,
+            None,
+            None,
+            Some(Bytes::from(body)),
+            0,
+        );
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        let mut resp = self.await_response(rx, None).await?;
+        match resp.status() {
+            Status::Success => Ok(resp.body()),
+            Status::KeyNotFound => Ok(None),
+            _ => Err(self.range_scan_error(partition, &mut resp)),
+        }
+    }
+
+    // range_scan_continue requests the next batch of items from a scan, the items are streamed
+    // back in one or more responses.
+    pub async fn range_scan_continue(
+        &self,
+        partition: u16,
+        scan_id: Bytes,
+        time_limit: Duration,
+    ) -> Result<mpsc::UnboundedReceiver<KvResponse>, ClientError> {
+        let mut extras = BytesMut::with_capacity(28);
+        extras.put(scan_id);
+        // A limit of 0 means no limit on the number of items or bytes.
+        extras.put_u32(0);
+        extras.put_u32(time_limit.as_millis() as u32);
+        extras.put_u32(0);
+
+        let req = KvRequest::new(
+            protocol::Opcode::RangeScanContinue,
+            0,
+            partition,
+            0,
+            None,
+            Some(extras.freeze()),
+            None,
+            0,
+        );
+        self.send_streaming(req).await
+    }
+
+    pub async fn range_scan_cancel(&self, partition: u16, scan_id: Bytes) {
+        let req = KvRequest::new(
+            protocol::Opcode::RangeScanCancel,
+            0,
+            partition,
+            0,
+            None,
+            Some(scan_id),
+            None,
+            0,
+        );
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        if self.send(req, tx).await.is_ok() {
+            // The scan is abandoned either way, so there's nothing to do if this fails.
+            let _ = self.await_response(rx, None).await;
+        }
+    }
+
+    pub fn range_scan_error(&self, partition: u16, resp: &mut KvResponse) -> ClientError {
+        let status = resp.status();
+        let error = if status == Status::NotMyVbucket {
+            ClientError::NotMyVbucket {
+                key: String::new(),
+                config: resp.body().map(|b| b.to_vec()),
+            }
+        } else {
+            let reason = ClientError::try_parse_kv_fail_body(resp);
+            ClientError::RequestFailed {
+                reason: Some(format!(
+                    "range scan of vbucket {} failed: {}",
+                    partition,
+                    reason.unwrap_or_else(|| status.as_string())
+                )),
+                key: None,
+            }
+        };
+        error.with_error_map(status, self.error_map.as_ref())
+    }
+
+    // all_vb_seqnos fetches the current sequence number of each active vbucket on the node.
+    pub async fn all_vb_seqnos(&self) -> Result<HashMap<u16, u64>, ClientError> {
+        let mut extras = BytesMut::with_capacity(4);
+        // Only the vbuckets which are active on the node.
+        extras.put_u32(1);
+
+        let req = KvRequest::new(
+            protocol::Opcode::GetAllVbSeqnos,
+            0,
+            0,
+            0,
+            None,
+            Some(extras.freeze()),
+            None,
+            0,
+        );
+        let mut resp = self
+            .send_control_request(req, "fetching vbucket seqnos")
+            .await?;
+
+        let mut seqnos = HashMap::new();
+        let mut body = resp.body().unwrap_or_default();
+        while body.remaining() >= 10 {
+            seqnos.insert(body.get_u16(), body.get_u64());
+        }
+        Ok(seqnos)
+    }
+
+    // open_dcp turns the connection into a DCP producer connection, after which the server sends
+    // the changes for any streams which are requested to the returned reader. Document values are
+    // only sent if include_values is set.
+    pub async fn open_dcp(
+        &self,
+        name: String,
+        include_values: bool,
+    ) -> Result<DcpStreamReader, ClientError> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.dcp.lock().unwrap().replace(tx);
+
+        // Expirations are only sent as their own opcode when delete times are included and the
+        // expiry opcode is enabled, otherwise they are sent as deletions.
+        let mut flags = DCP_OPEN_PRODUCER | DCP_OPEN_INCLUDE_DELETE_TIMES;
+        if !include_values {
+            flags |= DCP_OPEN_NO_VALUE;
+        }
+        let mut extras = BytesMut::with_capacity(8);
+        extras.put_u32(0);
+        extras.put_u32(flags);
+
+        let req = KvRequest::new(
+            protocol::Opcode::DcpOpen,
+            0,
+            0,
+            0,
+            Some(Bytes::from(name)),
+            Some(extras.freeze()),
+            None,
+            0,
+        );
+        self.send_control_request(req, "opening dcp connection")
+            .await?;
+
+        self.dcp_control("enable_noop", "true").await?;
+        self.dcp_control("enable_expiry_opcode", "true").await?;
+        self.dcp_control("set_noop_interval", &DCP_NOOP_INTERVAL.to_string())
+            .await?;
+        self.dcp_control("connection_buffer_size", &DCP_BUFFER_SIZE.to_string())
+            .await?;
+
+        Ok(DcpStreamReader::new(
+            rx,
+            self.collections_enabled,
+            DCP_BUFFER_SIZE as usize,
+        ))
+    }
+
+    async fn dcp_control(&self, key: &str, value: &str) -> Result<(), ClientError> {
+        let req = KvRequest::new(
+            protocol::Opcode::DcpControl,
+            0,
+            0,
+            0,
+            Some(Bytes::from(key.to_string())),
+            None,
+            Some(Bytes::from(value.to_string())),
+            0,
+        );
+        self.send_control_request(req, &format!("setting dcp control {}", key))
+            .await?;
+        Ok(())
+    }
+
+    // dcp_failover_log fetches the uuid of the current history branch of a vbucket.
+    pub async fn dcp_failover_log(&self, partition: u16) -> Result<u64, ClientError> {
+        let req = KvRequest::new(
+            protocol::Opcode::DcpGetFailoverLog,
+            0,
+            partition,
+            0,
+            None,
+            None,
+            None,
+            0,
+        );
+        let mut resp = self
+            .send_control_request(
+                req,
+                &format!("fetching failover log of vbucket {}", partition),
+            )
+            .await?;
+
+        // The log is a list of uuid and seqno pairs, newest first.
+        match resp.body() {
+            Some(mut body) if body.remaining() >= 16 => Ok(body.get_u64()),
+            _ => Err(ClientError::RequestFailed {
+                reason: Some(format!("empty failover log for vbucket {}", partition)),
+                key: None,
+            }),
+        }
+    }
+
+    // dcp_stream_request starts streaming the changes to a vbucket after start_seqno, which must
+    // be part of the history identified by vbucket_uuid. If a filter is given it restricts the
+    // stream to particular collections.
+    pub async fn dcp_stream_request(
+        &self,
+        partition: u16,
+        start_seqno: u64,
+        vbucket_uuid: u64,
+        filter: Option<Vec<u8>>,
+    ) -> Result<(), ClientError> {
+        let mut extras = BytesMut::with_capacity(48);
+        extras.put_u32(0);
+        extras.put_u32(0);
+        extras.put_u64(start_seqno);
+        extras.put_u64(u64::MAX);
+        extras.put_u64(vbucket_uuid);
+        extras.put_u64(start_seqno);
+        extras.put_u64(start_seqno);
+
+        let datatype = if filter.is_some() { DATATYPE_JSON } else { 0 };
+        let req = KvRequest::new(
+            protocol::Opcode::DcpStreamReq,
+            datatype,
+            partition,
+            0,
+            None,
+            Some(extras.freeze()),
+            filter.map(Bytes::from),
+            0,
+        );
+        self.send_control_request(req, &format!("streaming vbucket {}", partition))
+            .await?;
+        Ok(())
+    }
+
+    // dcp_buffer_ack tells the server that it can send more messages, the server doesn't respond.
+    pub async fn dcp_buffer_ack(&self, bytes: u32) -> Result<(), ClientError> {
+        let mut extras = BytesMut::with_capacity(4);
+        extras.put_u32(bytes);
+
+        let mut req = KvRequest::new(
+            protocol::Opcode::DcpBufferAck,
+            0,
+            0,
+            0,
+            None,
+            Some(extras.freeze()),
+            None,
+            0,
+        );
+        req.set_opaque(self.opaque.fetch_add(1, Ordering::SeqCst));
+        self.tx
+            .send(request(req, self.collections_enabled).freeze())
+            .await
+            .map_err(|e| ClientError::RequestFailed {
+                reason: Some(e.to_string()),
+                key: None,
+            })
+    }
+
+    // send_control_request sends a request which isn't about a document, failing with a
+    // description of what was being done if it doesn't succeed.
+    async fn send_control_request(
+        &self,
+        req: KvRequest,
+        action: &str,
+    ) -> Result<KvResponse, ClientError> {
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        let mut resp = self.await_response(rx, None).await?;
+        let status = resp.status();
+        if status == Status::Success {
+            return Ok(resp);
+        }
+
+        let reason = ClientError::try_parse_kv_fail_body(&mut resp);
+        let error = match status {
+            Status::AccessError => ClientError::AccessError { reason },
+            _ => ClientError::RequestFailed {
+                reason: Some(format!(
+                    "{} failed: {}",
+                    action,
+                    reason.unwrap_or_else(|| status.as_string())
+                )),
+                key: None,
+            },
+        };
+        Err(error.with_error_map(status, self.error_map.as_ref()))
+    }
+
+    // compress compresses a value with snappy if it is at least threshold bytes and compression
+    // makes it meaningfully smaller, returning the value to send along with its datatype.
+    pub fn compress(&self, value: Vec<u8>, threshold: Option<u32>) -> (Vec<u8>, u8) {
+        let threshold = match threshold {
+            Some(t) if self.snappy_enabled => t as usize,
+            _ => return (value, 0),
+        };
+        if value.len() < threshold {
+            return (value, 0);
+        }
+
+        match snap::raw::Encoder::new().compress_vec(&value) {
+            // Decompressing costs the server too, so only send the compressed value if it saves
+            // a reasonable amount.
+            Ok(compressed) if compressed.len() * 100 < value.len() * MIN_COMPRESSION_RATIO => {
+                (compressed, protocol::DATATYPE_SNAPPY)
+            }
+            Ok(_) => (value, 0),
+            Err(e) => {
+                warn!("{} failed to compress value: {}", self.uuid, e);
+                (value, 0)
+            }
+        }
+    }
+
+    // json_datatype returns the datatype to send with a value written with the given flags. Once
+    // JSON has been negotiated the server trusts the datatype it is sent rather than checking the
+    // value, so JSON values must be marked as such.
+    pub fn json_datatype(&self, flags: u32) -> u8 {
+        if self.json_enabled && DocumentFormat::is_json(flags) {
+            DATATYPE_JSON
+        } else {
+            0
+        }
+    }
+
+    // durability_frame creates the framing extras for a durable write, if a durability level is set.
+    fn durability_frame(
+        &self,
+        durability: Durability,
+        key: &str,
+    ) -> Result<Option<Bytes>, ClientError> {
+        match durability.level() {
+            None => Ok(None),
+            Some(level) => {
+                if !self.sync_replication_enabled {
+                    return Err(ClientError::RequestFailed {
+                        reason: Some("durable writes are not supported by the cluster".to_string()),
+                        key: Some(key.to_string()),
+                    });
+                }
+                Ok(Some(protocol::durability_frame(level)))
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn remote(&self) -> String {
+        self.remote_addr.clone()
+    }
 
     async fn send(
         &self,
-        mut req: KvRequest,
+        req: KvRequest,
         chan: oneshot::Sender<KvResponse>,
     ) -> Result<(), ClientError> {
+        self.send_request(req, ResponseSender::Single(chan)).await
+    }
+
+    // send_streaming sends a request which the server responds to with a series of responses, all
+    // but the last of which are successful.
+    async fn send_streaming(
+        &self,
+        req: KvRequest,
+    ) -> Result<mpsc::UnboundedReceiver<KvResponse>, ClientError> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.send_request(req, ResponseSender::Stream(tx)).await?;
+        Ok(rx)
+    }
+
+    async fn send_request(
+        &self,
+        mut req: KvRequest,
+        chan: ResponseSender,
+    ) -> Result<(), ClientError> {
+        if self.is_closed() {
+            return Err(ClientError::RequestFailed {
+                reason: Some(format!("connection to {} is closed", self.remote_addr)),
+                key: None,
+            });
+        }
+
         let opaque = self.opaque.fetch_add(1, Ordering::SeqCst);
         req.set_opaque(opaque);
         trace!(
@@ -608,19 +1427,38 @@ impl KvEndpoint {
         }
     }
 
+    // send_hello negotiates the features the connection uses. DCP connections only negotiate
+    // what the stream needs, the server refuses to open DCP on a connection with unordered
+    // execution and tracing or mutation seqnos are of no use to it.
     async fn send_hello(
         &mut self,
+        for_dcp: bool,
     ) -> Result<oneshot::Receiver<Result<Vec<ServerFeature>, ClientError>>, ClientError> {
-        let features = vec![
-            ServerFeature::SelectBucket,
-            ServerFeature::Xattr,
-            ServerFeature::Xerror,
-            ServerFeature::AltRequest,
-            ServerFeature::SyncReplication,
-            ServerFeature::Collections,
-            ServerFeature::Tracing,
-            ServerFeature::UnorderedExecution,
-        ];
+        let features = if for_dcp {
+            vec![
+                ServerFeature::SelectBucket,
+                ServerFeature::Xattr,
+                ServerFeature::Xerror,
+                ServerFeature::AltRequest,
+                ServerFeature::Collections,
+                ServerFeature::Snappy,
+                ServerFeature::Json,
+            ]
+        } else {
+            vec![
+                ServerFeature::SelectBucket,
+                ServerFeature::Xattr,
+                ServerFeature::Xerror,
+                ServerFeature::AltRequest,
+                ServerFeature::SyncReplication,
+                ServerFeature::Collections,
+                ServerFeature::Tracing,
+                ServerFeature::UnorderedExecution,
+                ServerFeature::Snappy,
+                ServerFeature::Json,
+                ServerFeature::MutationSeqno,
+            ]
+        };
         let mut body = BytesMut::with_capacity(features.len() * 2);
         for feature in &features {
             body.put_u16(feature.encoded());
@@ -648,50 +1486,18 @@ impl KvEndpoint {
         Ok(completerx)
     }
 
-    // async fn send_error_map(
-    //     &mut self,
-    // ) -> Result<oneshot::Receiver<Result<ErrorMap, ClientError>>, ClientError> {
-    //     let mut body = BytesMut::with_capacity(2);
-    //     body.put_u16(protocol::ERROR_MAP_VERSION);
-    //
-    //     let req = KvRequest::new(
-    //         protocol::Opcode::ErrorMap,
-    //         0,
-    //         0,
-    //         0,
-    //         None,
-    //         None,
-    //         Some(body.freeze()),
-    //         0,
-    //     );
-    //     let (tx, rx) = oneshot::channel::<KvResponse>();
-    //     self.send(req, tx).await?;
-    //
-    //     let (completetx, completerx) = oneshot::channel::<Result<ErrorMap, ClientError>>();
-    //     tokio::spawn(async move {
-    //         receive_error_map(rx, completetx).await;
-    //     });
-    //
-    //     Ok(completerx)
-    // }
-
-    async fn send_auth(
+    async fn send_error_map(
         &mut self,
-        username: String,
-        password: String,
-    ) -> Result<oneshot::Receiver<Result<(), ClientError>>, ClientError> {
-        let mut body = BytesMut::with_capacity(username.len() + password.len() + 2);
-        body.put_u8(0);
-        body.put(username.as_bytes());
-        body.put_u8(0);
-        body.put(password.as_bytes());
+    ) -> Result<oneshot::Receiver<Result<ErrorMap, ClientError>>, ClientError> {
+        let mut body = BytesMut::with_capacity(2);
+        body.put_u16(ERROR_MAP_VERSION);
 
         let req = KvRequest::new(
-            protocol::Opcode::Auth,
+            protocol::Opcode::ErrorMap,
             0,
             0,
             0,
-            Some(Bytes::from("PLAIN")),
+            None,
             None,
             Some(body.freeze()),
             0,
@@ -699,14 +1505,140 @@ impl KvEndpoint {
         let (tx, rx) = oneshot::channel::<KvResponse>();
         self.send(req, tx).await?;
 
-        let (completetx, completerx) = oneshot::channel::<Result<(), ClientError>>();
+        let (completetx, completerx) = oneshot::channel::<Result<ErrorMap, ClientError>>();
         tokio::spawn(async move {
-            receive_auth(rx, completetx).await;
+            receive_error_map(rx, completetx).await;
         });
 
         Ok(completerx)
     }
 
+    // authenticate performs SASL authentication, negotiating the mechanism with the server unless
+    // one has been configured. Over TLS PLAIN is used as the connection is already secure.
+    async fn authenticate(
+        &self,
+        username: String,
+        password: String,
+        tls_enabled: bool,
+        sasl_mechanism: Option<SaslMechanism>,
+    ) -> Result<(), ClientError> {
+        let mechanism = match sasl_mechanism {
+            Some(m) => m,
+            None => {
+                if tls_enabled {
+                    SaslMechanism::Plain
+                } else {
+                    let supported = self.list_sasl_mechanisms().await?;
+                    let mechanism = SaslMechanism::preferred(&supported);
+                    if mechanism == SaslMechanism::Plain {
+                        warn!(
+                            "{} does not support SCRAM, credentials will be sent in plaintext",
+                            self.remote_addr
+                        );
+                    }
+                    mechanism
+                }
+            }
+        };
+        debug!("{} authenticating using {}", self.uuid, mechanism);
+
+        if mechanism == SaslMechanism::Plain {
+            self.sasl_request(
+                protocol::Opcode::Auth,
+                mechanism,
+                plain_body(&username, &password),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let mut scram = ScramClient::new(mechanism, &username, &password);
+        let mut resp = self
+            .sasl_request(
+                protocol::Opcode::Auth,
+                mechanism,
+                scram.client_first().into_bytes(),
+            )
+            .await?;
+        let server_first = String::from_utf8_lossy(&resp.body().unwrap_or_default()).to_string();
+
+        let client_final = scram.client_final(&server_first)?;
+        let mut resp = self
+            .sasl_request(
+                protocol::Opcode::SaslStep,
+                mechanism,
+                client_final.into_bytes(),
+            )
+            .await?;
+        let server_final = String::from_utf8_lossy(&resp.body().unwrap_or_default()).to_string();
+
+        scram.verify_server_final(&server_final)
+    }
+
+    async fn list_sasl_mechanisms(&self) -> Result<Vec<String>, ClientError> {
+        let req = KvRequest::new(
+            protocol::Opcode::SaslListMechs,
+            0,
+            0,
+            0,
+            None,
+            None,
+            None,
+            0,
+        );
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        let mut resp = self.await_response(rx, None).await?;
+        if resp.status() != Status::Success {
+            return Err(ClientError::RequestFailed {
+                reason: Some(format!(
+                    "failed to list sasl mechanisms: {}",
+                    resp.status().as_string()
+                )),
+                key: None,
+            });
+        }
+
+        let body = resp.body().unwrap_or_default();
+        Ok(String::from_utf8_lossy(&body)
+            .split_whitespace()
+            .map(|m| m.to_string())
+            .collect())
+    }
+
+    async fn sasl_request(
+        &self,
+        opcode: protocol::Opcode,
+        mechanism: SaslMechanism,
+        body: Vec<u8>,
+    ) -> Result<KvResponse, ClientError> {
+        let req = KvRequest::new(
+            opcode,
+            0,
+            0,
+            0,
+            Some(Bytes::from(mechanism.name())),
+            None,
+            Some(Bytes::from(body)),
+            0,
+        );
+        let (tx, rx) = oneshot::channel::<KvResponse>();
+        self.send(req, tx).await?;
+
+        let mut resp = self.await_response(rx, None).await?;
+        match resp.status() {
+            Status::Success | Status::AuthContinue => Ok(resp),
+            Status::AuthError => Err(ClientError::AuthError {
+                reason: ClientError::try_parse_kv_fail_body(&mut resp),
+            }),
+            status => Err(ClientError::RequestFailed {
+                reason: Some(status.as_string()),
+                key: None,
+            }),
+        }
+    }
+
     async fn send_select_bucket(
         &mut self,
         bucket: String,
@@ -736,6 +1668,38 @@ impl KvEndpoint {
     }
 }
 
+// handle_server_request handles a request sent by the server, replying to noops and passing
+// everything else on to the DCP stream if the connection has been opened for DCP.
+async fn handle_server_request(
+    uuid: &str,
+    input: Bytes,
+    dcp: &StdMutex<Option<mpsc::UnboundedSender<Bytes>>>,
+    reply_tx: &mpsc::WeakSender<Bytes>,
+) {
+    let opcode = protocol::Opcode::try_from(input[1]);
+    trace!("Received server request on {}. Opcode={:?}", uuid, opcode);
+
+    if let Ok(protocol::Opcode::DcpNoop) = opcode {
+        let opaque = input.slice(12..16).get_u32();
+        let reply =
+            protocol::_response(protocol::Opcode::DcpNoop, 0, 0, opaque, 0, None, None, None);
+        if let Some(tx) = reply_tx.upgrade() {
+            if tx.send(reply.freeze()).await.is_err() {
+                warn!("{} could not reply to dcp noop", uuid);
+            }
+        }
+        return;
+    }
+
+    let sent = match dcp.lock().unwrap().as_ref() {
+        Some(sender) => sender.send(input).is_ok(),
+        None => false,
+    };
+    if !sent {
+        warn!("{} has no dcp stream for server request {:?}", uuid, opcode);
+    }
+}
+
 async fn receive_hello(
     rx: oneshot::Receiver<KvResponse>,
     completetx: oneshot::Sender<Result<Vec<ServerFeature>, ClientError>>,
@@ -785,61 +1749,28 @@ async fn receive_hello(
     };
 }
 
-// async fn receive_error_map(
-//     rx: oneshot::Receiver<KvResponse>,
-//     completetx: oneshot::Sender<Result<ErrorMap, ClientError>>,
-// ) {
-//     let r = match rx.await {
-//         Ok(r) => Some(r),
-//         Err(_e) => None,
-//     };
-//     let result = if let Some(mut response) = r {
-//         let status = response.status();
-//
-//         match status {
-//             Status::Success => {
-//                 if let Some(body) = response.body() {
-//                     let error_map = serde_json::from_slice(body.as_ref()).unwrap();
-//                     Ok(error_map)
-//                 } else {
-//                     Err(ClientError::RequestFailed {
-//                         reason: None,
-//                         key: None,
-//                     })
-//                 }
-//             }
-//             _ => Err(ClientError::RequestFailed {
-//                 reason: Some(status.as_string()),
-//                 key: None,
-//             }),
-//         }
-//     } else {
-//         Err(ClientError::RequestFailed {
-//             reason: None,
-//             key: None,
-//         })
-//     };
-//
-//     match completetx.send(result) {
-//         Ok(()) => {}
-//         Err(_e) => {
-//             warn!("error map receive failed");
-//         }
-//     };
-// }
-
-async fn receive_auth(
+async fn receive_error_map(
     rx: oneshot::Receiver<KvResponse>,
-    completetx: oneshot::Sender<Result<(), ClientError>>,
+    completetx: oneshot::Sender<Result<ErrorMap, ClientError>>,
 ) {
     let r = match rx.await {
         Ok(r) => Some(r),
         Err(_e) => None,
     };
-    let result = if let Some(response) = r {
+    let result = if let Some(mut response) = r {
         let status = response.status();
+
         match status {
-            Status::Success => Ok(()),
+            Status::Success => {
+                if let Some(body) = response.body() {
+                    ErrorMap::from_slice(body.as_ref()).map_err(ClientError::from)
+                } else {
+                    Err(ClientError::RequestFailed {
+                        reason: None,
+                        key: None,
+                    })
+                }
+            }
             _ => Err(ClientError::RequestFailed {
                 reason: Some(status.as_string()),
                 key: None,
@@ -855,7 +1786,7 @@ async fn receive_auth(
     match completetx.send(result) {
         Ok(()) => {}
         Err(_e) => {
-            warn!("auth receive failed");
+            warn!("error map receive failed");
         }
     };
 }
@@ -903,6 +1834,7 @@ enum ServerFeature {
     Tracing,
     MutationSeqno,
     Snappy,
+    Json,
     UnorderedExecution,
     Vattr,
     CreateAsDeleted,
@@ -920,6 +1852,7 @@ impl ServerFeature {
             Self::Tracing => 0x0F,
             Self::MutationSeqno => 0x04,
             Self::Snappy => 0x0A,
+            Self::Json => 0x0B,
             Self::UnorderedExecution => 0x0E,
             Self::Vattr => 0x15,
             Self::CreateAsDeleted => 0x17,
@@ -947,6 +1880,7 @@ impl TryFrom<u16> for ServerFeature {
             0x0F => Self::Tracing,
             0x04 => Self::MutationSeqno,
             0x0A => Self::Snappy,
+            0x0B => Self::Json,
             0x0E => Self::UnorderedExecution,
             0x15 => Self::Vattr,
             0x17 => Self::CreateAsDeleted,
@@ -954,76 +1888,3 @@ impl TryFrom<u16> for ServerFeature {
         })
     }
 }
-
-// #[derive(Debug, Deserialize)]
-// struct ErrorMap {
-//     version: u16,
-//     revision: u16,
-//     errors: HashMap<String, ErrorCode>,
-// }
-//
-// #[derive(Debug, Deserialize)]
-// struct ErrorCode {
-//     name: String,
-//     desc: String,
-//     attrs: HashSet<ErrorAttribute>,
-//     retry: Option<RetrySpecification>,
-// }
-//
-// #[derive(Debug, Deserialize)]
-// struct RetrySpecification {
-//     strategy: RetryStrategy,
-//     interval: u32,
-//     after: u32,
-//     #[serde(rename = "max-duration")]
-//     max_duration: u32,
-//     ceil: u32,
-// }
-//
-// #[derive(Debug, Deserialize, Eq, PartialEq, Hash)]
-// enum ErrorAttribute {
-//     #[serde(rename = "success")]
-//     Success,
-//     #[serde(rename = "item-only")]
-//     ItemOnly,
-//     #[serde(rename = "invalid-input")]
-//     InvalidInput,
-//     #[serde(rename = "fetch-config")]
-//     FetchConfig,
-//     #[serde(rename = "conn-state-invalidated")]
-//     ConnStateInvalidated,
-//     #[serde(rename = "auth")]
-//     Auth,
-//     #[serde(rename = "special-handling")]
-//     SpecialHandling,
-//     #[serde(rename = "support")]
-//     Support,
-//     #[serde(rename = "temp")]
-//     Temp,
-//     #[serde(rename = "internal")]
-//     Internal,
-//     #[serde(rename = "retry-now")]
-//     RetryNow,
-//     #[serde(rename = "retry-later")]
-//     RetryLater,
-//     #[serde(rename = "subdoc")]
-//     Subdoc,
-//     #[serde(rename = "dcp")]
-//     Dcp,
-//     #[serde(rename = "auto-retry")]
-//     AutoRetry,
-//     #[serde(rename = "item-locked")]
-//     ItemLocked,
-//     #[serde(rename = "item-deleted")]
-//     ItemDeleted,
-// }
-//
-// #[derive(Debug, Deserialize)]
-// enum RetryStrategy {
-//     #[serde(rename = "exponential")]
-//     Exponential,
-//     #[serde(rename = "linear")]
-//     Linear,
-//     #[serde(rename = "constant")]
-//     Constant,
-// }
@@ -1,30 +1,76 @@
 use crate::cli::CtrlcFuture;
 use crate::client::crc::cb_vb_map;
+use crate::client::durability::Durability;
 use crate::client::error::ClientError;
 use crate::client::http_client::{Config, PingResponse, ServiceType};
 use crate::client::http_handler::HTTPHandler;
 use crate::client::kv::KvEndpoint;
-use crate::client::{protocol, HTTPClient};
+use crate::client::{
+    protocol, CollectionsManifest, DcpEvent, DcpStart, DocumentFormat, ErrorAttribute, HTTPClient,
+    RangeScan, RetryPolicy, SaslMechanism, ScanItem,
+};
 use crate::RustTlsConfig;
-use bytes::{Buf, Bytes};
+use bytes::Buf;
+use futures::future::select_ok;
 use futures::stream::FuturesUnordered;
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
 use log::{debug, trace};
 use nu_protocol::Signals;
 use serde::Deserialize;
 use serde_json::json;
 use std::future::Future;
+use std::ops::Add;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use std::{collections::HashMap, ops::Sub};
+use tokio::runtime::Handle;
 use tokio::select;
-use tokio::time::{sleep, Instant, Sleep};
+use tokio::sync::mpsc;
+use tokio::time::{interval, sleep, timeout_at, Instant, Sleep};
+use uuid::Uuid;
+
+const NOT_MY_VBUCKET_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// The maximum number of specs which can be sent in a single sub-document request.
+const MAX_SUBDOC_SPECS: usize = 16;
+
+// The number of scanned items which can be waiting to be consumed before the scan is paused.
+const RANGE_SCAN_BUFFER: usize = 1024;
+
+// The number of changes which can be waiting to be consumed before DCP streaming is paused.
+const DCP_EVENT_BUFFER: usize = 1024;
+
+// How often an idle DCP stream checks whether it is still wanted.
+const DCP_CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Debug)]
 pub struct KvResponse {
     content: Option<serde_json::Value>,
+    // raw_content is the value of documents which are not JSON, content is None for these.
+    raw_content: Option<Vec<u8>>,
+    format: DocumentFormat,
     cas: u64,
     key: String,
-    extras: Option<Bytes>,
+    node: String,
+    replica: bool,
+    partition: u16,
+    server_duration: Option<Duration>,
+    round_trip: Duration,
+    retries: u32,
+    mutation_token: Option<MutationToken>,
+    path_errors: Vec<Option<String>>,
+}
+
+// MutationToken identifies a mutation by the vbucket it was made in and the sequence number that
+// the server assigned to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutationToken {
+    pub bucket: String,
+    pub partition: u16,
+    pub partition_uuid: u64,
+    pub sequence: u64,
 }
 
 impl KvResponse {
@@ -32,6 +78,14 @@ impl KvResponse {
         self.content.take()
     }
 
+    pub fn raw_content(&mut self) -> Option<Vec<u8>> {
+        self.raw_content.take()
+    }
+
+    pub fn format(&self) -> DocumentFormat {
+        self.format
+    }
+
     pub fn cas(&self) -> u64 {
         self.cas
     }
@@ -40,15 +94,66 @@ impl KvResponse {
         self.key.clone()
     }
 
-    pub fn extras(&mut self) -> Option<Bytes> {
-        self.extras.take()
+    // node is the address of the node which served the request.
+    pub fn node(&self) -> String {
+        self.node.clone()
+    }
+
+    // replica is whether the response was served by a replica rather than the active node.
+    pub fn replica(&self) -> bool {
+        self.replica
+    }
+
+    // partition is the vbucket which the document belongs to.
+    pub fn partition(&self) -> u16 {
+        self.partition
+    }
+
+    // server_duration is how long the server spent on the request, as reported by the server.
+    pub fn server_duration(&self) -> Option<Duration> {
+        self.server_duration
+    }
+
+    // round_trip is how long the request took from being sent until the response was received.
+    pub fn round_trip(&self) -> Duration {
+        self.round_trip
+    }
+
+    // retries is how many times the request was retried before it succeeded.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    // mutation_token is the token of a successful mutation, only mutations have one.
+    pub fn mutation_token(&self) -> Option<MutationToken> {
+        self.mutation_token.clone()
+    }
+
+    // path_errors is why each path of a multi lookup failed, or None for those which succeeded.
+    // The value of a path which failed is null.
+    pub fn path_errors(&self) -> &[Option<String>] {
+        &self.path_errors
     }
 }
 
 pub struct KvClient {
-    endpoints: HashMap<String, KvEndpoint>,
-    config: BucketConfig,
-    tls_enabled: bool,
+    endpoints: RwLock<HashMap<String, Arc<KvEndpoint>>>,
+    config: RwLock<Arc<BucketConfig>>,
+    seeds: Vec<String>,
+    username: String,
+    password: String,
+    bucket: String,
+    tls_config: Option<RustTlsConfig>,
+    sasl_mechanism: Option<SaslMechanism>,
+    cids: RwLock<HashMap<(String, String), u32>>,
+    // cid_names remembers the names of every collection id seen, so that a stale id can be
+    // resolved again after its collection has been recreated.
+    cid_names: RwLock<HashMap<u32, (String, String)>>,
+    compression_threshold: Option<u32>,
+    retry_policy: RetryPolicy,
+    // handle is the runtime that the connections were created on, new connections must be
+    // created on the same runtime so that they live as long as the others.
+    handle: Handle,
 }
 
 impl KvClient {
@@ -57,6 +162,7 @@ impl KvClient {
         username: String,
         password: String,
         tls_config: Option<RustTlsConfig>,
+        sasl_mechanism: Option<SaslMechanism>,
         bucket: String,
         deadline: Instant,
         signals: Signals,
@@ -65,11 +171,6 @@ impl KvClient {
         if now >= deadline {
             return Err(ClientError::Timeout { key: None });
         }
-        let deadline_sleep = sleep(deadline.sub(now));
-        tokio::pin!(deadline_sleep);
-
-        let ctrlc_fut = CtrlcFuture::new(signals.clone());
-        tokio::pin!(ctrlc_fut);
 
         let http_agent = HTTPHandler::new(username.clone(), password.clone(), tls_config.clone());
         let config: BucketConfig = HTTPClient::get_config(
@@ -82,8 +183,63 @@ impl KvClient {
         )
         .await?;
 
+        let handle = Handle::current();
+        let endpoints = KvClient::connect_endpoints(
+            &handle,
+            config.key_value_seeds(tls_config.is_some()),
+            username.clone(),
+            password.clone(),
+            bucket.clone(),
+            tls_config.clone(),
+            sasl_mechanism,
+            deadline,
+            signals,
+            false,
+        )
+        .await?;
+
+        Ok(Self {
+            config: RwLock::new(Arc::new(config)),
+            endpoints: RwLock::new(endpoints),
+            seeds,
+            username,
+            password,
+            bucket,
+            tls_config,
+            sasl_mechanism,
+            cids: RwLock::new(HashMap::new()),
+            cid_names: RwLock::new(HashMap::new()),
+            compression_threshold: None,
+            retry_policy: RetryPolicy::default(),
+            handle,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn connect_endpoints(
+        handle: &Handle,
+        addrs: Vec<(String, u32)>,
+        username: String,
+        password: String,
+        bucket: String,
+        tls_config: Option<RustTlsConfig>,
+        sasl_mechanism: Option<SaslMechanism>,
+        deadline: Instant,
+        signals: Signals,
+        for_dcp: bool,
+    ) -> Result<HashMap<String, Arc<KvEndpoint>>, ClientError> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(ClientError::Timeout { key: None });
+        }
+        let deadline_sleep = sleep(deadline.sub(now));
+        tokio::pin!(deadline_sleep);
+
+        let ctrlc_fut = CtrlcFuture::new(signals.clone());
+        tokio::pin!(ctrlc_fut);
+
         let mut workers = FuturesUnordered::new();
-        for addr in config.key_value_seeds(tls_config.is_some()) {
+        for addr in addrs {
             let hostname = addr.0.clone();
             let port = addr.1;
             let u = username.clone();
@@ -91,12 +247,13 @@ impl KvClient {
             let b = bucket.clone();
             let tls = tls_config.clone();
 
-            workers.push(tokio::spawn(async move {
-                KvEndpoint::connect(hostname, port, u, p, b, tls).await
+            workers.push(handle.spawn(async move {
+                KvEndpoint::connect(hostname, port, u, p, b, tls, sasl_mechanism, for_dcp).await
             }));
         }
 
         let mut endpoints = HashMap::new();
+        let mut last_error = None;
         loop {
             let endpoint = select! {
                 res = workers.next() => {
@@ -112,29 +269,227 @@ impl KvClient {
                         None => break
                     }
                 },
-                () = &mut deadline_sleep => Err(ClientError::Timeout{key: None}),
-                () = &mut ctrlc_fut => Err(ClientError::Cancelled{key: None}),
+                () = &mut deadline_sleep => return Err(ClientError::Timeout{key: None}),
+                () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: None}),
                 else => {break}
-            }?;
-            endpoints.insert(endpoint.remote(), endpoint);
+            };
+
+            // A node being unavailable shouldn't prevent us from talking to the rest of the
+            // cluster, e.g. to read from replicas, so only fail if we couldn't connect to any node.
+            match endpoint {
+                Ok(ep) => {
+                    endpoints.insert(ep.remote(), Arc::new(ep));
+                }
+                Err(e) => {
+                    debug!("Failed to connect to kv endpoint: {}", e);
+                    last_error = Some(e);
+                }
+            }
         }
 
-        Ok(Self {
-            config,
-            endpoints,
-            tls_enabled: tls_config.is_some(),
-        })
+        if endpoints.is_empty() {
+            if let Some(e) = last_error {
+                return Err(e);
+            }
+        }
+
+        Ok(endpoints)
+    }
+
+    fn config(&self) -> Arc<BucketConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    fn endpoint(&self, addr: &str) -> Option<Arc<KvEndpoint>> {
+        self.endpoints.read().unwrap().get(addr).cloned()
+    }
+
+    // is_healthy is whether the client is still connected to every node in the cluster, a client
+    // which isn't healthy should be replaced.
+    pub fn is_healthy(&self) -> bool {
+        let config = self.config();
+        let endpoints = self.endpoints.read().unwrap();
+        if endpoints.values().any(|ep| ep.is_closed()) {
+            return false;
+        }
+
+        config
+            .key_value_seeds(self.tls_enabled())
+            .iter()
+            .all(|(host, port)| endpoints.contains_key(&format!("{}:{}", host, port)))
+    }
+
+    // set_compression_threshold sets the size in bytes above which document values are compressed
+    // before being sent, values are sent uncompressed if no threshold is set.
+    pub fn set_compression_threshold(&mut self, threshold: Option<u32>) {
+        self.compression_threshold = threshold;
+    }
+
+    // set_retry_policy sets how requests which fail temporarily are retried.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    fn tls_enabled(&self) -> bool {
+        self.tls_config.is_some()
+    }
+
+    // reconnect_endpoint replaces the connection to a node, e.g. when the server tells us that the
+    // state of the connection is no longer valid.
+    async fn reconnect_endpoint(
+        &self,
+        node: &str,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Result<(), ClientError> {
+        let addr = match node.rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse::<u32>().unwrap_or_default()),
+            None => {
+                return Err(ClientError::RequestFailed {
+                    reason: Some(format!("Invalid node address {}", node)),
+                    key: None,
+                })
+            }
+        };
+
+        self.endpoints.write().unwrap().remove(node);
+        let connected = KvClient::connect_endpoints(
+            &self.handle,
+            vec![addr],
+            self.username.clone(),
+            self.password.clone(),
+            self.bucket.clone(),
+            self.tls_config.clone(),
+            self.sasl_mechanism,
+            deadline,
+            signals,
+            false,
+        )
+        .await?;
+
+        let mut endpoints = self.endpoints.write().unwrap();
+        for (addr, ep) in connected {
+            endpoints.insert(addr, ep);
+        }
+
+        Ok(())
+    }
+
+    // refresh_config applies a newer bucket config, either the one piggybacked on a not my vbucket
+    // response or one fetched from the cluster manager, and updates the endpoints to match it.
+    async fn refresh_config(
+        &self,
+        piggybacked: Option<Vec<u8>>,
+        host: String,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Result<bool, ClientError> {
+        let current = self.config();
+
+        let piggybacked = piggybacked.and_then(|config| {
+            let config = String::from_utf8_lossy(&config).replace("$HOST", &host);
+            match serde_json::from_str::<BucketConfig>(&config) {
+                Ok(mut c) => {
+                    c.loaded_from = current.loaded_from.clone();
+                    Some(c)
+                }
+                Err(e) => {
+                    debug!("Failed to parse config from not my vbucket response: {}", e);
+                    None
+                }
+            }
+        });
+
+        let config = match piggybacked {
+            Some(c) => c,
+            None => {
+                let http_agent = HTTPHandler::new(
+                    self.username.clone(),
+                    self.password.clone(),
+                    self.tls_config.clone(),
+                );
+                HTTPClient::get_config(
+                    &self.seeds,
+                    self.tls_enabled(),
+                    &http_agent,
+                    self.bucket.clone(),
+                    deadline,
+                    signals.clone(),
+                )
+                .await?
+            }
+        };
+
+        if config.rev <= current.rev {
+            trace!(
+                "Ignoring config with revision {}, current revision is {}",
+                config.rev,
+                current.rev
+            );
+            return Ok(false);
+        }
+
+        debug!("Applying bucket config with revision {}", config.rev);
+
+        let addrs = config.key_value_seeds(self.tls_enabled());
+        let missing: Vec<(String, u32)> = {
+            let endpoints = self.endpoints.read().unwrap();
+            addrs
+                .iter()
+                .filter(|(host, port)| !endpoints.contains_key(&format!("{}:{}", host, port)))
+                .cloned()
+                .collect()
+        };
+
+        let connected = match KvClient::connect_endpoints(
+            &self.handle,
+            missing,
+            self.username.clone(),
+            self.password.clone(),
+            self.bucket.clone(),
+            self.tls_config.clone(),
+            self.sasl_mechanism,
+            deadline,
+            signals,
+            false,
+        )
+        .await
+        {
+            Ok(eps) => eps,
+            Err(e) => {
+                debug!("Failed to connect to new kv endpoints: {}", e);
+                HashMap::new()
+            }
+        };
+
+        let mut endpoints = self.endpoints.write().unwrap();
+        for (addr, ep) in connected {
+            endpoints.entry(addr).or_insert(ep);
+        }
+        endpoints.retain(|addr, _| {
+            addrs
+                .iter()
+                .any(|(host, port)| &format!("{}:{}", host, port) == addr)
+        });
+
+        let mut current = self.config.write().unwrap();
+        if config.rev > current.rev {
+            *current = Arc::new(config);
+        }
+
+        Ok(true)
     }
 
     fn partition_for_key(&self, key: String) -> u32 {
-        let num_partitions = self.config.vbucket_server_map.vbucket_map.len() as u32;
+        let num_partitions = self.config().vbucket_server_map.vbucket_map.len() as u32;
 
         cb_vb_map(key.as_bytes().to_vec(), num_partitions)
     }
 
     fn node_for_partition(&self, partition: u32) -> (String, u32) {
-        let seeds = self.config.key_value_seeds(self.tls_enabled);
-        let node = self.config.vbucket_server_map.vbucket_map[partition as usize][0];
+        let config = self.config();
+        let seeds = config.key_value_seeds(self.tls_enabled());
+        let node = config.vbucket_server_map.vbucket_map[partition as usize][0];
 
         let seed = &seeds[node as usize];
         let addr = seed.0.clone();
@@ -143,6 +498,24 @@ impl KvClient {
         (addr, port)
     }
 
+    // node_for_replica returns the node hosting the given replica (0 based) of a partition, if
+    // the replica is currently assigned to a node.
+    fn node_for_replica(&self, partition: u32, replica: u32) -> Option<(String, u32)> {
+        let config = self.config();
+        let seeds = config.key_value_seeds(self.tls_enabled());
+        let node =
+            *config.vbucket_server_map.vbucket_map[partition as usize].get(replica as usize + 1)?;
+        if node < 0 {
+            return None;
+        }
+
+        seeds.get(node as usize).cloned()
+    }
+
+    pub fn num_replicas(&self) -> u32 {
+        self.config().vbucket_server_map.num_replicas
+    }
+
     pub async fn ping_all(
         &mut self,
         deadline: Instant,
@@ -160,21 +533,24 @@ impl KvClient {
         tokio::pin!(ctrlc_fut);
 
         let mut results: Vec<PingResponse> = Vec::new();
-        for seed in self.config.key_value_seeds(self.tls_enabled) {
+        for seed in self.config().key_value_seeds(self.tls_enabled()) {
             let addr = seed.0.clone();
             let port = seed.1;
-            let ep = self
-                .endpoints
-                .get(format!("{}:{}", addr.clone(), port).as_str())
-                .unwrap();
-
-            let op = ep.noop();
-
             let start = Instant::now();
-            let result = select! {
-                res = op => res,
-                () = &mut deadline_sleep => Err(ClientError::Timeout{key: None}),
-                () = &mut ctrlc_fut => Err(ClientError::Cancelled{key: None}),
+            let result = match self.endpoint(format!("{}:{}", addr.clone(), port).as_str()) {
+                Some(ep) => {
+                    let op = ep.noop();
+
+                    select! {
+                        res = op => res,
+                        () = &mut deadline_sleep => Err(ClientError::Timeout{key: None}),
+                        () = &mut ctrlc_fut => Err(ClientError::Cancelled{key: None}),
+                    }
+                }
+                None => Err(ClientError::RequestFailed {
+                    reason: Some(format!("Not connected to node {}:{}", addr, port)),
+                    key: None,
+                }),
             };
             let end = Instant::now();
 
@@ -200,6 +576,82 @@ impl KvClient {
         Ok(results)
     }
 
+    // stats fetches a group of stats from every node, returning the stats of each node along with
+    // its address.
+    pub async fn stats(
+        &self,
+        group: String,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Vec<(String, Result<Vec<(String, String)>, ClientError>)> {
+        let workers: FuturesUnordered<_> = self
+            .config()
+            .key_value_seeds(self.tls_enabled())
+            .into_iter()
+            .map(|(addr, port)| {
+                let node = format!("{}:{}", addr, port);
+                let group = group.clone();
+                let signals = signals.clone();
+                async move {
+                    let result = match self.endpoint(node.as_str()) {
+                        Some(ep) => self.with_deadline(ep.stats(group), deadline, signals).await,
+                        None => Err(ClientError::RequestFailed {
+                            reason: Some(format!("Not connected to node {}", node)),
+                            key: None,
+                        }),
+                    };
+                    (node, result)
+                }
+            })
+            .collect();
+
+        workers.collect().await
+    }
+
+    // key_stats fetches the stats of a document from the node hosting its active partition.
+    pub async fn key_stats(
+        &self,
+        key: String,
+        cid: u32,
+        deadline: Instant,
+        signals: Signals,
+    ) -> (String, Result<Vec<(String, String)>, ClientError>) {
+        let partition = self.partition_for_key(key.clone());
+        let (addr, port) = self.node_for_partition(partition);
+        let node = format!("{}:{}", addr, port);
+        let group = format!("key-byid {} {} 0x{:x}", key, partition, cid);
+
+        let result = match self.endpoint(node.as_str()) {
+            Some(ep) => self.with_deadline(ep.stats(group), deadline, signals).await,
+            None => Err(ClientError::RequestFailed {
+                reason: Some(format!("Not connected to node {}", node)),
+                key: Some(key),
+            }),
+        };
+        (node, result)
+    }
+
+    async fn with_deadline<T>(
+        &self,
+        op: impl Future<Output = Result<T, ClientError>>,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Result<T, ClientError> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(ClientError::Timeout { key: None });
+        }
+
+        let ctrlc_fut = CtrlcFuture::new(signals);
+        tokio::pin!(ctrlc_fut);
+
+        select! {
+            res = op => res,
+            () = sleep(deadline.sub(now)) => Err(ClientError::Timeout{key: None}),
+            () = &mut ctrlc_fut => Err(ClientError::Cancelled{key: None}),
+        }
+    }
+
     pub fn is_non_default_scope_collection(scope: String, collection: String) -> bool {
         (!scope.is_empty() && scope != "_default")
             || (!collection.is_empty() && collection != "_default")
@@ -208,7 +660,7 @@ impl KvClient {
     pub async fn request(
         &self,
         request: KeyValueRequest,
-        cid: u32,
+        mut cid: u32,
         deadline: Instant,
         signals: Signals,
     ) -> Result<KvResponse, ClientError> {
@@ -218,31 +670,221 @@ impl KvClient {
                 key: Some(request.key()),
             });
         }
-        let deadline = deadline.sub(now);
-        let deadline_sleep = sleep(deadline);
+        let deadline_sleep = sleep(deadline.sub(now));
         tokio::pin!(deadline_sleep);
 
         let ctrlc_fut = CtrlcFuture::new(signals.clone());
         tokio::pin!(ctrlc_fut);
 
+        let mut retries = 0;
+        let mut reauthenticated = false;
+        loop {
+            let (node, result) = self
+                .dispatch(
+                    request.clone(),
+                    cid,
+                    deadline_sleep.as_mut(),
+                    ctrlc_fut.as_mut(),
+                )
+                .await;
+            let error = match result {
+                Ok(mut response) => {
+                    response.retries = retries;
+                    return Ok(response);
+                }
+                Err(e) => e,
+            };
+
+            // During a rebalance vbuckets move between nodes, the node tells us when it no longer
+            // owns the vbucket so we update our view of the cluster and retry until the deadline.
+            let config = match error.kind() {
+                ClientError::CollectionUnknownDuringRequest { cid: unknown, .. } => {
+                    // The collection may have been dropped, or dropped and recreated in which case
+                    // we can retry with its new id.
+                    let refreshed = select! {
+                        res = self.refresh_cid(*unknown, deadline, signals.clone()) => res,
+                        () = &mut deadline_sleep => return Err(ClientError::Timeout{key: Some(request.key())}),
+                        () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: Some(request.key())}),
+                    };
+                    match refreshed {
+                        Some(refreshed) if refreshed != cid => {
+                            debug!("Collection id {} is now {}, retrying", cid, refreshed);
+                            cid = refreshed;
+                            continue;
+                        }
+                        _ => return Err(error),
+                    }
+                }
+                ClientError::NotMyVbucket { key, config } => {
+                    debug!(
+                        "Not my vbucket for key {} from {}, refreshing config",
+                        key, node
+                    );
+                    config.clone()
+                }
+                // Temporary failures, such as the server being short of memory, are retried with a
+                // backoff. Unlocking with the wrong CAS fails as locked, which will never succeed.
+                _ if error.is_temporary() && !matches!(request, KeyValueRequest::Unlock { .. }) => {
+                    if retries >= self.retry_policy.max_retries() {
+                        return Err(error);
+                    }
+                    let backoff = self.retry_policy.backoff(retries);
+                    debug!(
+                        "Retrying request for key {} in {:?} after {} from {}",
+                        request.key(),
+                        backoff,
+                        error,
+                        node
+                    );
+                    select! {
+                        () = sleep(backoff) => {},
+                        () = &mut deadline_sleep => return Err(ClientError::Timeout{key: Some(request.key())}),
+                        () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: Some(request.key())}),
+                    }
+                    retries += 1;
+                    continue;
+                }
+                _ => {
+                    // Otherwise the error map tells us whether the request can be retried.
+                    let code = match error.error_code() {
+                        Some(code) => code.clone(),
+                        None => return Err(error),
+                    };
+
+                    let invalidated = code.has_attribute(ErrorAttribute::ConnStateInvalidated);
+                    let auth = code.has_attribute(ErrorAttribute::Auth);
+                    if invalidated || (auth && !reauthenticated) {
+                        debug!(
+                            "{} from {} invalidated the connection, reconnecting",
+                            code.name, node
+                        );
+                        let reconnected = select! {
+                            res = self.reconnect_endpoint(&node, deadline, signals.clone()) => res,
+                            () = &mut deadline_sleep => return Err(ClientError::Timeout{key: Some(request.key())}),
+                            () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: Some(request.key())}),
+                        };
+                        if let Err(e) = reconnected {
+                            debug!("Failed to reconnect to {}: {}", node, e);
+                            return Err(error);
+                        }
+                    }
+
+                    if code.has_attribute(ErrorAttribute::RetryNow)
+                        || code.has_attribute(ErrorAttribute::RetryLater)
+                    {
+                        if retries >= self.retry_policy.max_retries() {
+                            return Err(error);
+                        }
+                        debug!(
+                            "Retrying request for key {} after {} from {}",
+                            request.key(),
+                            code.name,
+                            node
+                        );
+                        select! {
+                            () = sleep(code.retry_delay(retries)) => {},
+                            () = &mut deadline_sleep => return Err(ClientError::Timeout{key: Some(request.key())}),
+                            () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: Some(request.key())}),
+                        }
+                        retries += 1;
+                        continue;
+                    }
+                    // A fresh connection has fresh credentials, so an auth failure is worth one
+                    // more attempt.
+                    if auth && !reauthenticated {
+                        reauthenticated = true;
+                        continue;
+                    }
+
+                    return Err(error);
+                }
+            };
+
+            let host = match node.rsplit_once(':') {
+                Some((host, _)) => host.to_string(),
+                None => node,
+            };
+            let refreshed = select! {
+                res = self.refresh_config(config, host, deadline, signals.clone()) => res,
+                () = &mut deadline_sleep => return Err(ClientError::Timeout{key: Some(request.key())}),
+                () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: Some(request.key())}),
+            };
+
+            // If the config hasn't changed yet then give the cluster a moment before retrying.
+            if !matches!(refreshed, Ok(true)) {
+                select! {
+                    () = sleep(NOT_MY_VBUCKET_RETRY_DELAY) => {},
+                    () = &mut deadline_sleep => return Err(ClientError::Timeout{key: Some(request.key())}),
+                    () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: Some(request.key())}),
+                }
+            }
+        }
+    }
+
+    // dispatch sends the request to the node which owns its vbucket, returning the address and
+    // port of the node along with the result.
+    async fn dispatch(
+        &self,
+        request: KeyValueRequest,
+        cid: u32,
+        deadline_sleep: Pin<&mut Sleep>,
+        ctrlc_fut: Pin<&mut CtrlcFuture>,
+    ) -> (String, Result<KvResponse, ClientError>) {
         let key = match request {
             KeyValueRequest::Get { ref key } => key.clone(),
+            KeyValueRequest::GetReplica { ref key, .. } => key.clone(),
+            KeyValueRequest::GetAndTouch { ref key, .. } => key.clone(),
+            KeyValueRequest::GetAndLock { ref key, .. } => key.clone(),
+            KeyValueRequest::Unlock { ref key, .. } => key.clone(),
+            KeyValueRequest::Touch { ref key, .. } => key.clone(),
             KeyValueRequest::Set { ref key, .. } => key.clone(),
             KeyValueRequest::Insert { ref key, .. } => key.clone(),
             KeyValueRequest::Replace { ref key, .. } => key.clone(),
             KeyValueRequest::Remove { ref key, .. } => key.clone(),
+            KeyValueRequest::Increment { ref key, .. } => key.clone(),
+            KeyValueRequest::Decrement { ref key, .. } => key.clone(),
+            KeyValueRequest::Append { ref key, .. } => key.clone(),
+            KeyValueRequest::Prepend { ref key, .. } => key.clone(),
             KeyValueRequest::SubDocGet { ref key, .. } => key.clone(),
             KeyValueRequest::SubdocMultiLookup { ref key, .. } => key.clone(),
+            KeyValueRequest::SubdocMultiMutation { ref key, .. } => key.clone(),
         };
 
         let partition = self.partition_for_key(key.clone());
-        let (addr, port) = self.node_for_partition(partition);
-
-        let ep = self
-            .endpoints
-            .get(format!("{}:{}", addr.clone(), port).as_str())
-            .unwrap();
+        let (addr, port) = match request {
+            KeyValueRequest::GetReplica { replica, .. } => {
+                match self.node_for_replica(partition, replica) {
+                    Some(node) => node,
+                    None => {
+                        return (
+                            String::new(),
+                            Err(ClientError::RequestFailed {
+                                reason: Some(format!("No node is assigned to replica {}", replica)),
+                                key: Some(key),
+                            }),
+                        );
+                    }
+                }
+            }
+            _ => self.node_for_partition(partition),
+        };
+        let is_replica = matches!(request, KeyValueRequest::GetReplica { .. });
+
+        let node = format!("{}:{}", addr, port);
+        let ep = match self.endpoint(node.as_str()) {
+            Some(ep) => ep,
+            None => {
+                return (
+                    node.clone(),
+                    Err(ClientError::RequestFailed {
+                        reason: Some(format!("Not connected to node {}", node)),
+                        key: Some(key),
+                    }),
+                );
+            }
+        };
 
+        let sent = Instant::now();
         let result = match request {
             KeyValueRequest::Get { key } => {
                 let op = ep.get(key.clone(), partition as u16, cid);
@@ -250,45 +892,689 @@ impl KvClient {
                 self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
                     .await
             }
-            KeyValueRequest::Set { key, value, expiry } => {
-                let op = ep.set(key.clone(), value, expiry, partition as u16, cid);
+            KeyValueRequest::GetReplica { key, .. } => {
+                let op = ep.get_replica(key.clone(), partition as u16, cid);
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::GetAndTouch { key, expiry } => {
+                let op = ep.get_and_touch(key.clone(), expiry, partition as u16, cid);
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::GetAndLock { key, lock_time } => {
+                let op = ep.get_and_lock(key.clone(), lock_time, partition as u16, cid);
 
                 self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
                     .await
             }
-            KeyValueRequest::Insert { key, value, expiry } => {
-                let op = ep.add(key.clone(), value, expiry, partition as u16, cid);
+            KeyValueRequest::Unlock { key, cas } => {
+                let op = ep.unlock(key.clone(), cas, partition as u16, cid);
 
                 self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
                     .await
             }
-            KeyValueRequest::Replace { key, value, expiry } => {
-                let op = ep.replace(key.clone(), value, expiry, partition as u16, cid);
+            KeyValueRequest::Touch { key, expiry } => {
+                let op = ep.touch(key.clone(), expiry, partition as u16, cid);
 
                 self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
                     .await
             }
-            KeyValueRequest::Remove { key } => {
-                let op = ep.remove(key.clone(), partition as u16, cid);
+            KeyValueRequest::Set {
+                key,
+                value,
+                flags,
+                expiry,
+                durability,
+            } => {
+                let (value, datatype) = ep.compress(value, self.compression_threshold);
+                let datatype = datatype | ep.json_datatype(flags);
+                let op = ep.set(
+                    key.clone(),
+                    value,
+                    datatype,
+                    flags,
+                    expiry,
+                    partition as u16,
+                    cid,
+                    durability,
+                );
 
                 self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
                     .await
             }
-            KeyValueRequest::SubDocGet { key, path } => {
-                let op = ep.sub_doc_get(key.clone(), partition as u16, cid, path);
+            KeyValueRequest::Insert {
+                key,
+                value,
+                flags,
+                expiry,
+                durability,
+            } => {
+                let (value, datatype) = ep.compress(value, self.compression_threshold);
+                let datatype = datatype | ep.json_datatype(flags);
+                let op = ep.add(
+                    key.clone(),
+                    value,
+                    datatype,
+                    flags,
+                    expiry,
+                    partition as u16,
+                    cid,
+                    durability,
+                );
 
                 self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
                     .await
             }
-            KeyValueRequest::SubdocMultiLookup { key, paths } => {
-                let op = ep.sub_doc_multi_lookup(key.clone(), partition as u16, cid, paths);
+            KeyValueRequest::Replace {
+                key,
+                value,
+                flags,
+                expiry,
+                cas,
+                durability,
+            } => {
+                let (value, datatype) = ep.compress(value, self.compression_threshold);
+                let datatype = datatype | ep.json_datatype(flags);
+                let op = ep.replace(
+                    key.clone(),
+                    value,
+                    datatype,
+                    flags,
+                    expiry,
+                    cas,
+                    partition as u16,
+                    cid,
+                    durability,
+                );
 
                 self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
                     .await
             }
+            KeyValueRequest::Remove {
+                key,
+                cas,
+                durability,
+            } => {
+                let op = ep.remove(key.clone(), cas, partition as u16, cid, durability);
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::Increment {
+                key,
+                delta,
+                initial,
+                expiry,
+                durability,
+            } => {
+                let op = ep.counter(
+                    protocol::Opcode::Increment,
+                    key.clone(),
+                    delta,
+                    initial,
+                    expiry,
+                    partition as u16,
+                    cid,
+                    durability,
+                );
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::Decrement {
+                key,
+                delta,
+                initial,
+                expiry,
+                durability,
+            } => {
+                let op = ep.counter(
+                    protocol::Opcode::Decrement,
+                    key.clone(),
+                    delta,
+                    initial,
+                    expiry,
+                    partition as u16,
+                    cid,
+                    durability,
+                );
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::Append {
+                key,
+                value,
+                durability,
+            } => {
+                let op = ep.concat(
+                    protocol::Opcode::Append,
+                    key.clone(),
+                    value,
+                    partition as u16,
+                    cid,
+                    durability,
+                );
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::Prepend {
+                key,
+                value,
+                durability,
+            } => {
+                let op = ep.concat(
+                    protocol::Opcode::Prepend,
+                    key.clone(),
+                    value,
+                    partition as u16,
+                    cid,
+                    durability,
+                );
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::SubDocGet { key, path, xattr } => {
+                let op = ep.sub_doc_get(key.clone(), partition as u16, cid, path, xattr);
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::SubdocMultiLookup { key, specs } => {
+                let op = ep.sub_doc_multi_lookup(key.clone(), partition as u16, cid, specs);
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+            KeyValueRequest::SubdocMultiMutation {
+                key,
+                specs,
+                expiry,
+                durability,
+            } => {
+                let op = ep.sub_doc_multi_mutation(
+                    key.clone(),
+                    partition as u16,
+                    cid,
+                    specs,
+                    expiry,
+                    durability,
+                );
+
+                self.handle_op_future(key, op, deadline_sleep, ctrlc_fut)
+                    .await
+            }
+        };
+
+        let round_trip = sent.elapsed();
+        let result = self.handle_op_result(result).map(|mut r| {
+            r.node = node.clone();
+            r.replica = is_replica;
+            r.partition = partition as u16;
+            r.round_trip = round_trip;
+            if let Some(token) = r.mutation_token.as_mut() {
+                token.partition = partition as u16;
+            }
+            r
+        });
+
+        (node, result)
+    }
+
+    // get_any_replica fetches a document from the active and every replica node, returning the
+    // first successful response.
+    pub async fn get_any_replica(
+        &self,
+        key: String,
+        cid: u32,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Result<KvResponse, ClientError> {
+        let workers = self.replica_requests(key).into_iter().map(|request| {
+            self.request(request, cid, deadline, signals.clone())
+                .boxed_local()
+        });
+
+        select_ok(workers).await.map(|(response, _)| response)
+    }
+
+    // get_all_replicas fetches a document from the active and every replica node, returning all of
+    // the responses.
+    pub async fn get_all_replicas(
+        &self,
+        key: String,
+        cid: u32,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Vec<Result<KvResponse, ClientError>> {
+        let workers: FuturesUnordered<_> = self
+            .replica_requests(key)
+            .into_iter()
+            .map(|request| self.request(request, cid, deadline, signals.clone()))
+            .collect();
+
+        workers.collect().await
+    }
+
+    // get_with_xattrs fetches a document along with all of the extended attributes visible to
+    // the user and the virtual $document attribute. The content of the response is an object
+    // containing the document under "content" and the attributes under "xattrs".
+    pub async fn get_with_xattrs(
+        &self,
+        key: String,
+        cid: u32,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Result<KvResponse, ClientError> {
+        let request = KeyValueRequest::SubdocMultiLookup {
+            key: key.clone(),
+            specs: vec![
+                SubdocLookupSpec {
+                    path: "$document".to_string(),
+                    xattr: true,
+                },
+                SubdocLookupSpec {
+                    path: "$XTOC".to_string(),
+                    xattr: true,
+                },
+                SubdocLookupSpec {
+                    path: "".to_string(),
+                    xattr: false,
+                },
+            ],
+        };
+        let mut response = self
+            .request(request, cid, deadline, signals.clone())
+            .await?;
+
+        let mut values = match response.content() {
+            Some(serde_json::Value::Array(values)) if values.len() == 3 => values,
+            _ => {
+                return Err(ClientError::RequestFailed {
+                    reason: Some("Unexpected response to xattr lookup".to_string()),
+                    key: Some(key),
+                });
+            }
+        };
+        let content = values.pop().unwrap_or_default();
+        let xtoc = values.pop().unwrap_or_default();
+        let document = values.pop().unwrap_or_default();
+
+        let names: Vec<String> = match xtoc {
+            serde_json::Value::Array(names) => names
+                .into_iter()
+                .filter_map(|n| n.as_str().map(|n| n.to_string()))
+                .collect(),
+            _ => vec![],
+        };
+
+        let mut xattrs = serde_json::Map::new();
+        for chunk in names.chunks(MAX_SUBDOC_SPECS) {
+            let request = KeyValueRequest::SubdocMultiLookup {
+                key: key.clone(),
+                specs: chunk
+                    .iter()
+                    .map(|name| SubdocLookupSpec {
+                        path: name.clone(),
+                        xattr: true,
+                    })
+                    .collect(),
+            };
+            let mut res = self
+                .request(request, cid, deadline, signals.clone())
+                .await?;
+            if let Some(serde_json::Value::Array(values)) = res.content() {
+                for (name, value) in chunk.iter().zip(values) {
+                    xattrs.insert(name.clone(), value);
+                }
+            }
+        }
+        xattrs.insert("$document".to_string(), document);
+
+        response.content = Some(json!({ "content": content, "xattrs": xattrs }));
+        Ok(response)
+    }
+
+    // range_scan scans the documents of a collection, streaming the items back through the
+    // returned channel. Up to concurrency vbuckets are scanned at once and each request made to
+    // the server must complete within timeout.
+    pub fn range_scan(
+        self: Arc<Self>,
+        scan: RangeScan,
+        cid: u32,
+        concurrency: usize,
+        timeout: Duration,
+        signals: Signals,
+    ) -> mpsc::Receiver<Result<ScanItem, ClientError>> {
+        let (tx, rx) = mpsc::channel(RANGE_SCAN_BUFFER);
+        let handle = self.handle.clone();
+        handle.spawn(async move {
+            let num_partitions = self.config().vbucket_server_map.vbucket_map.len() as u16;
+            let returned = AtomicU64::new(0);
+            futures::stream::iter(0..num_partitions)
+                .for_each_concurrent(concurrency.max(1), |partition| {
+                    let client = self.clone();
+                    let (scan, tx, signals, returned) = (&scan, tx.clone(), &signals, &returned);
+                    async move {
+                        let result = client
+                            .scan_partition(partition, scan, cid, timeout, signals, &tx, returned)
+                            .await;
+                        if let Err(e) = result {
+                            let _ = tx.send(Err(e)).await;
+                        }
+                    }
+                })
+                .await;
+        });
+
+        rx
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn scan_partition(
+        &self,
+        partition: u16,
+        scan: &RangeScan,
+        cid: u32,
+        timeout: Duration,
+        signals: &Signals,
+        tx: &mpsc::Sender<Result<ScanItem, ClientError>>,
+        returned: &AtomicU64,
+    ) -> Result<(), ClientError> {
+        let body = scan.create_body(cid);
+        let deadline = Instant::now().add(timeout);
+
+        // The vbucket may move during a rebalance so keep trying until the deadline.
+        let (ep, scan_id) = loop {
+            if Instant::now() >= deadline {
+                return Err(ClientError::Timeout { key: None });
+            }
+
+            let (addr, port) = self.node_for_partition(partition as u32);
+            let node = format!("{}:{}", addr, port);
+            let ep = self
+                .endpoint(&node)
+                .ok_or_else(|| ClientError::RequestFailed {
+                    reason: Some(format!("Not connected to node {}", node)),
+                    key: None,
+                })?;
+
+            let result = timeout_at(deadline, ep.range_scan_create(partition, body.clone()))
+                .await
+                .map_err(|_| ClientError::Timeout { key: None })?;
+            let config = match result {
+                Ok(Some(scan_id)) => break (ep, scan_id),
+                // There is nothing in the range on this vbucket.
+                Ok(None) => return Ok(()),
+                Err(e) => match e.kind() {
+                    ClientError::NotMyVbucket { config, .. } => config.clone(),
+                    _ => return Err(e),
+                },
+            };
+
+            if !matches!(
+                self.refresh_config(config, addr, deadline, signals.clone())
+                    .await,
+                Ok(true)
+            ) {
+                sleep(NOT_MY_VBUCKET_RETRY_DELAY).await;
+            }
         };
 
-        self.handle_op_result(result)
+        loop {
+            if signals.interrupted() {
+                ep.range_scan_cancel(partition, scan_id).await;
+                return Err(ClientError::Cancelled { key: None });
+            }
+
+            // Ask the server to stop streaming well within the timeout, we then continue the scan.
+            let mut responses = ep
+                .range_scan_continue(partition, scan_id.clone(), timeout / 2)
+                .await?;
+            loop {
+                let mut resp = match tokio::time::timeout(timeout, responses.recv()).await {
+                    Ok(Some(resp)) => resp,
+                    Ok(None) => {
+                        return Err(ClientError::RequestFailed {
+                            reason: Some(format!(
+                                "connection to {} closed during range scan",
+                                ep.remote()
+                            )),
+                            key: None,
+                        });
+                    }
+                    Err(_) => {
+                        ep.range_scan_cancel(partition, scan_id).await;
+                        return Err(ClientError::Timeout { key: None });
+                    }
+                };
+
+                let status = resp.status();
+                let items = match status {
+                    protocol::Status::Success
+                    | protocol::Status::RangeScanMore
+                    | protocol::Status::RangeScanComplete => scan.parse_items(resp.body())?,
+                    _ => return Err(ep.range_scan_error(partition, &mut resp)),
+                };
+
+                for item in items {
+                    let limit_reached = match scan.limit() {
+                        Some(limit) => returned.fetch_add(1, Ordering::SeqCst) >= limit,
+                        None => false,
+                    };
+                    // Stop if we have enough items or nobody wants any more.
+                    if limit_reached || tx.send(Ok(item)).await.is_err() {
+                        if status != protocol::Status::RangeScanComplete {
+                            ep.range_scan_cancel(partition, scan_id).await;
+                        }
+                        return Ok(());
+                    }
+                }
+
+                match status {
+                    protocol::Status::RangeScanMore => break,
+                    protocol::Status::RangeScanComplete => return Ok(()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // changes streams the changes made to the documents of a collection through the returned
+    // channel, until the channel is dropped. Each node is streamed from over a new DCP connection
+    // and each request made to the server while opening the streams must complete within timeout.
+    pub fn changes(
+        self: Arc<Self>,
+        cid: u32,
+        start: DcpStart,
+        with_content: bool,
+        timeout: Duration,
+        signals: Signals,
+    ) -> mpsc::Receiver<Result<DcpEvent, ClientError>> {
+        let (tx, rx) = mpsc::channel(DCP_EVENT_BUFFER);
+        let handle = self.handle.clone();
+        handle.spawn(async move {
+            let num_partitions = self.config().vbucket_server_map.vbucket_map.len() as u32;
+            let mut partitions: HashMap<(String, u32), Vec<u16>> = HashMap::new();
+            for partition in 0..num_partitions {
+                partitions
+                    .entry(self.node_for_partition(partition))
+                    .or_default()
+                    .push(partition as u16);
+            }
+
+            let streams = partitions.into_iter().map(|(addr, partitions)| {
+                let client = self.clone();
+                let (tx, signals) = (tx.clone(), signals.clone());
+                async move {
+                    let result = client
+                        .stream_node_changes(
+                            addr,
+                            partitions,
+                            cid,
+                            start,
+                            with_content,
+                            timeout,
+                            &signals,
+                            &tx,
+                        )
+                        .await;
+                    if let Err(e) = result {
+                        let _ = tx.send(Err(e)).await;
+                    }
+                }
+            });
+            futures::future::join_all(streams).await;
+        });
+
+        rx
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn stream_node_changes(
+        &self,
+        addr: (String, u32),
+        partitions: Vec<u16>,
+        cid: u32,
+        start: DcpStart,
+        with_content: bool,
+        timeout: Duration,
+        signals: &Signals,
+        tx: &mpsc::Sender<Result<DcpEvent, ClientError>>,
+    ) -> Result<(), ClientError> {
+        let deadline = Instant::now().add(timeout);
+        let node = format!("{}:{}", addr.0, addr.1);
+
+        // DCP connections can't be used for anything else so each stream gets its own.
+        let ep = KvClient::connect_endpoints(
+            &self.handle,
+            vec![addr],
+            self.username.clone(),
+            self.password.clone(),
+            self.bucket.clone(),
+            self.tls_config.clone(),
+            self.sasl_mechanism,
+            deadline,
+            signals.clone(),
+            true,
+        )
+        .await?
+        .remove(&node)
+        .ok_or_else(|| ClientError::RequestFailed {
+            reason: Some(format!("Not connected to node {}", node)),
+            key: None,
+        })?;
+
+        let seqnos = match start {
+            DcpStart::Now => Some(
+                timeout_at(deadline, ep.all_vb_seqnos())
+                    .await
+                    .map_err(|_| ClientError::Timeout { key: None })??,
+            ),
+            DcpStart::Beginning => None,
+        };
+        let name = format!("cbsh-{}", Uuid::new_v4());
+        let mut reader = timeout_at(deadline, ep.open_dcp(name, with_content))
+            .await
+            .map_err(|_| ClientError::Timeout { key: None })??;
+
+        let filter = json!({ "collections": [format!("{:x}", cid)] }).to_string();
+        let requests = partitions.iter().map(|partition| {
+            let (ep, filter, seqnos) = (&ep, &filter, &seqnos);
+            async move {
+                // Streaming from a seqno other than zero requires the history that it belongs to.
+                let (start_seqno, vbucket_uuid) = match seqnos {
+                    Some(seqnos) => match seqnos.get(partition) {
+                        Some(seqno) if *seqno > 0 => {
+                            (*seqno, ep.dcp_failover_log(*partition).await?)
+                        }
+                        _ => (0, 0),
+                    },
+                    None => (0, 0),
+                };
+                ep.dcp_stream_request(
+                    *partition,
+                    start_seqno,
+                    vbucket_uuid,
+                    Some(filter.clone().into_bytes()),
+                )
+                .await
+            }
+        });
+        timeout_at(deadline, futures::future::try_join_all(requests))
+            .await
+            .map_err(|_| ClientError::Timeout { key: None })??;
+        debug!(
+            "Streaming changes to {} vbuckets from {}",
+            partitions.len(),
+            node
+        );
+
+        let mut open_streams = partitions.len();
+        let mut ticker = interval(DCP_CANCEL_CHECK_INTERVAL);
+        loop {
+            let message = select! {
+                message = reader.next() => message,
+                _ = ticker.tick() => {
+                    if tx.is_closed() || signals.interrupted() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+
+            let message = match message {
+                Some(message) => message?,
+                None => {
+                    return Err(ClientError::RequestFailed {
+                        reason: Some(format!("connection to {} closed during streaming", node)),
+                        key: None,
+                    });
+                }
+            };
+
+            if let protocol::Opcode::DcpStreamEnd = message.opcode {
+                let closed = ClientError::RequestFailed {
+                    reason: Some(format!(
+                        "the server closed the stream for vbucket {}",
+                        message.partition
+                    )),
+                    key: None,
+                };
+                if tx.send(Err(closed)).await.is_err() {
+                    return Ok(());
+                }
+                open_streams -= 1;
+                if open_streams == 0 {
+                    return Ok(());
+                }
+            } else if let Some(event) = DcpEvent::from_message(message) {
+                if tx.send(Ok(event)).await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            if let Some(bytes) = reader.take_acknowledgement() {
+                ep.dcp_buffer_ack(bytes).await?;
+            }
+        }
+    }
+
+    fn replica_requests(&self, key: String) -> Vec<KeyValueRequest> {
+        let mut requests = vec![KeyValueRequest::Get { key: key.clone() }];
+        for replica in 0..self.num_replicas() {
+            requests.push(KeyValueRequest::GetReplica {
+                key: key.clone(),
+                replica,
+            });
+        }
+
+        requests
     }
 
     fn handle_op_result(
@@ -297,16 +1583,73 @@ impl KvClient {
     ) -> Result<KvResponse, ClientError> {
         match result {
             Ok(mut r) => {
+                let mut format = DocumentFormat::Json;
+                let mut raw_content = None;
+                let mut path_errors = vec![];
                 let content = if let Some(body) = r.0.body() {
+                    // Documents may have been written as strings or binary by other SDKs.
+                    if matches!(
+                        r.0.opcode(),
+                        protocol::Opcode::Get
+                            | protocol::Opcode::GetReplica
+                            | protocol::Opcode::GetAndTouch
+                            | protocol::Opcode::GetAndLock
+                    ) {
+                        format = DocumentFormat::from_flags(r.0.flags(), r.0.datatype(), &body);
+                    }
+
                     match r.0.opcode() {
+                        _ if format != DocumentFormat::Json => {
+                            raw_content = Some(body.to_vec());
+                            None
+                        }
                         protocol::Opcode::SubdocMultiLookup => {
                             let mut results: Vec<serde_json::Value> = vec![];
                             let mut bytes = body.clone();
 
-                            while !bytes.is_empty() {
-                                //Drop leading empty bytes
-                                bytes.get_u32();
-                                let len: usize = bytes.get_u16().into();
+                            while bytes.remaining() >= 6 {
+                                let status = bytes.get_u16();
+                                let len = bytes.get_u32() as usize;
+                                if len > bytes.remaining() {
+                                    return Err(invalid_subdoc_response(r.1));
+                                }
+                                let temp = bytes.split_off(len);
+
+                                // Paths which failed to be looked up have no value
+                                let value = if status != 0 {
+                                    path_errors
+                                        .push(Some(protocol::Status::from(status).as_string()));
+                                    serde_json::Value::Null
+                                } else {
+                                    path_errors.push(None);
+                                    match serde_json::from_slice(bytes.as_ref()) {
+                                        Ok(v) => v,
+                                        Err(e) => {
+                                            return Err(ClientError::RequestFailed {
+                                                reason: Some(e.to_string()),
+                                                key: r.1,
+                                            });
+                                        }
+                                    }
+                                };
+
+                                results.push(value);
+                                bytes = temp;
+                            }
+                            Some(json!(results))
+                        }
+                        protocol::Opcode::SubdocMultiMutation => {
+                            // Only specs which return a value, such as counters, are present.
+                            let mut results: Vec<serde_json::Value> = vec![];
+                            let mut bytes = body.clone();
+
+                            while bytes.remaining() >= 7 {
+                                let _index = bytes.get_u8();
+                                let _status = bytes.get_u16();
+                                let len = bytes.get_u32() as usize;
+                                if len > bytes.remaining() {
+                                    return Err(invalid_subdoc_response(r.1));
+                                }
                                 let temp = bytes.split_off(len);
 
                                 let value = match serde_json::from_slice(bytes.as_ref()) {
@@ -324,6 +1667,12 @@ impl KvClient {
                             }
                             Some(json!(results))
                         }
+                        // Counters respond with the new value of the counter.
+                        protocol::Opcode::Increment | protocol::Opcode::Decrement
+                            if body.len() == 8 =>
+                        {
+                            Some(json!(body.clone().get_u64()))
+                        }
                         _ => match serde_json::from_slice(body.as_ref()) {
                             Ok(v) => Some(v),
                             Err(e) => {
@@ -339,9 +1688,26 @@ impl KvClient {
                 };
                 Ok(KvResponse {
                     content,
+                    raw_content,
+                    format,
                     cas: r.0.cas(),
                     key: r.1.unwrap_or_default(),
-                    extras: r.0.extras(),
+                    node: String::new(),
+                    replica: false,
+                    partition: 0,
+                    server_duration: r.0.server_duration(),
+                    round_trip: Duration::ZERO,
+                    retries: 0,
+                    // The partition is filled in once known.
+                    mutation_token: r.0.mutation_token().map(|(partition_uuid, sequence)| {
+                        MutationToken {
+                            bucket: self.bucket.clone(),
+                            partition: 0,
+                            partition_uuid,
+                            sequence,
+                        }
+                    }),
+                    path_errors,
                 })
             }
             Err(e) => Err(e),
@@ -375,44 +1741,100 @@ impl KvClient {
             collection
         };
 
-        let deadline_sleep = sleep(deadline.sub(Instant::now()));
-        tokio::pin!(deadline_sleep);
+        let cache_key = (scope_name.clone(), collection_name.clone());
+        if let Some(cid) = self.cids.read().unwrap().get(&cache_key) {
+            trace!("Using cached collection id {}", cid);
+            return Ok(*cid);
+        }
 
-        let ctrlc_fut = CtrlcFuture::new(signals.clone());
+        // Fetching the manifest resolves the ids of every collection at once.
+        let manifest = self.get_collections_manifest(deadline, signals).await?;
+        if let Some(cid) = self.cids.read().unwrap().get(&cache_key) {
+            return Ok(*cid);
+        }
+
+        if manifest.scopes.iter().any(|scope| scope.name == scope_name) {
+            Err(ClientError::CollectionNotFound {
+                scope_name,
+                name: collection_name,
+            })
+        } else {
+            Err(ClientError::ScopeNotFound { name: scope_name })
+        }
+    }
+
+    // get_collections_manifest fetches the collections manifest of the bucket, caching the ids of
+    // all of the collections in it.
+    pub async fn get_collections_manifest(
+        &self,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Result<CollectionsManifest, ClientError> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(ClientError::Timeout { key: None });
+        }
+
+        let ctrlc_fut = CtrlcFuture::new(signals);
         tokio::pin!(ctrlc_fut);
 
-        let (addr, port) = self.node_for_partition(0);
-        let ep = self
-            .endpoints
-            .get(format!("{}:{}", addr.clone(), port).as_str())
-            .unwrap();
+        let ep = self.any_endpoint()?;
+        let manifest = select! {
+            res = ep.get_collections_manifest() => res?,
+            () = sleep(deadline.sub(now)) => return Err(ClientError::Timeout{key: None}),
+            () = &mut ctrlc_fut => return Err(ClientError::Cancelled{key: None}),
+        };
+        trace!("Fetched collections manifest {:x}", manifest.uid);
 
-        let op = ep.get_cid(scope_name, collection_name);
+        let ids = manifest.collection_ids();
+        let mut names = self.cid_names.write().unwrap();
+        for (name, cid) in &ids {
+            names.insert(*cid, name.clone());
+        }
+        *self.cids.write().unwrap() = ids;
 
-        let resp = self
-            .handle_op_future(None, op, deadline_sleep, ctrlc_fut)
-            .await;
+        Ok(manifest)
+    }
 
-        let mut result = self.handle_op_result(resp)?;
-        match result.extras() {
-            Some(mut e) => {
-                if e.len() < 12 {
-                    return Err(ClientError::RequestFailed {
-                        reason: Some(
-                            "Response from get collection id not expected format".to_string(),
-                        ),
-                        key: None,
-                    });
-                }
-                // Skip over the manifest uid
-                e.advance(8);
-                Ok(e.get_u32())
+    // refresh_cid looks up the current id of the collection which an unknown id belonged to,
+    // returning nothing if the collection no longer exists.
+    async fn refresh_cid(&self, unknown: u32, deadline: Instant, signals: Signals) -> Option<u32> {
+        let name = self.cid_names.read().unwrap().get(&unknown).cloned()?;
+
+        // Another request may have already refreshed the manifest.
+        if let Some(cid) = self.cids.read().unwrap().get(&name) {
+            if *cid != unknown {
+                return Some(*cid);
             }
-            None => Err(ClientError::RequestFailed {
-                reason: Some("Response from get collection id not expected format".to_string()),
-                key: None,
-            }),
         }
+
+        if let Err(e) = self.get_collections_manifest(deadline, signals).await {
+            debug!("Failed to refresh collections manifest: {}", e);
+            self.cids.write().unwrap().retain(|_, id| *id != unknown);
+            return None;
+        }
+
+        self.cids.read().unwrap().get(&name).copied()
+    }
+
+    // any_endpoint returns a connection to any node, preferring the node hosting the first
+    // partition, for requests which aren't about a particular document.
+    fn any_endpoint(&self) -> Result<Arc<KvEndpoint>, ClientError> {
+        let (addr, port) = self.node_for_partition(0);
+        if let Some(ep) = self.endpoint(format!("{}:{}", addr, port).as_str()) {
+            return Ok(ep);
+        }
+
+        self.endpoints
+            .read()
+            .unwrap()
+            .values()
+            .next()
+            .cloned()
+            .ok_or_else(|| ClientError::RequestFailed {
+                reason: Some("Not connected to any node".to_string()),
+                key: None,
+            })
     }
 
     // handle_op_future resolves the future into a result containing (response, key) or an error.
@@ -436,7 +1858,7 @@ impl KvClient {
 
 #[derive(Deserialize, Debug)]
 struct BucketConfig {
-    // rev: u64,
+    rev: u64,
     #[serde(alias = "nodesExt")]
     nodes_ext: Vec<NodeExtConfig>,
     nodes: Vec<NodeConfig>,
@@ -551,43 +1973,107 @@ pub(crate) struct NodeConfig {
 
 #[derive(Deserialize, Debug)]
 struct VBucketServerMap {
-    // #[serde(alias = "numReplicas")]
-    // num_replicas: u32,
+    #[serde(alias = "numReplicas")]
+    num_replicas: u32,
     // #[serde(alias = "serverList")]
     // server_list: Vec<String>,
     #[serde(alias = "vBucketMap")]
     vbucket_map: Vec<Vec<i32>>,
 }
 
+#[derive(Clone)]
 pub enum KeyValueRequest {
     Get {
         key: String,
     },
+    GetReplica {
+        key: String,
+        replica: u32,
+    },
+    GetAndTouch {
+        key: String,
+        expiry: u32,
+    },
+    // The lock time is in seconds, the server caps it at 30.
+    GetAndLock {
+        key: String,
+        lock_time: u32,
+    },
+    Unlock {
+        key: String,
+        cas: u64,
+    },
+    Touch {
+        key: String,
+        expiry: u32,
+    },
     Set {
         key: String,
         value: Vec<u8>,
+        flags: u32,
         expiry: u32,
+        durability: Durability,
     },
     Insert {
         key: String,
         value: Vec<u8>,
+        flags: u32,
         expiry: u32,
+        durability: Durability,
     },
+    // A cas of 0 replaces or removes the document whatever its current cas.
     Replace {
         key: String,
         value: Vec<u8>,
+        flags: u32,
         expiry: u32,
+        cas: u64,
+        durability: Durability,
     },
     Remove {
         key: String,
+        cas: u64,
+        durability: Durability,
+    },
+    // Without an initial value the counter must already exist.
+    Increment {
+        key: String,
+        delta: u64,
+        initial: Option<u64>,
+        expiry: u32,
+        durability: Durability,
+    },
+    Decrement {
+        key: String,
+        delta: u64,
+        initial: Option<u64>,
+        expiry: u32,
+        durability: Durability,
+    },
+    Append {
+        key: String,
+        value: Vec<u8>,
+        durability: Durability,
+    },
+    Prepend {
+        key: String,
+        value: Vec<u8>,
+        durability: Durability,
     },
     SubDocGet {
         key: String,
         path: String,
+        xattr: bool,
     },
     SubdocMultiLookup {
         key: String,
-        paths: Vec<String>,
+        specs: Vec<SubdocLookupSpec>,
+    },
+    SubdocMultiMutation {
+        key: String,
+        specs: Vec<SubdocMutationSpec>,
+        expiry: u32,
+        durability: Durability,
     },
 }
 
@@ -595,12 +2081,76 @@ impl KeyValueRequest {
     pub fn key(&self) -> String {
         match self {
             KeyValueRequest::Get { key } => key.clone(),
+            KeyValueRequest::GetReplica { key, .. } => key.clone(),
+            KeyValueRequest::GetAndTouch { key, .. } => key.clone(),
+            KeyValueRequest::GetAndLock { key, .. } => key.clone(),
+            KeyValueRequest::Unlock { key, .. } => key.clone(),
+            KeyValueRequest::Touch { key, .. } => key.clone(),
             KeyValueRequest::Set { key, .. } => key.clone(),
             KeyValueRequest::Insert { key, .. } => key.clone(),
             KeyValueRequest::Replace { key, .. } => key.clone(),
-            KeyValueRequest::Remove { key } => key.clone(),
+            KeyValueRequest::Remove { key, .. } => key.clone(),
+            KeyValueRequest::Increment { key, .. } => key.clone(),
+            KeyValueRequest::Decrement { key, .. } => key.clone(),
+            KeyValueRequest::Append { key, .. } => key.clone(),
+            KeyValueRequest::Prepend { key, .. } => key.clone(),
             KeyValueRequest::SubDocGet { key, .. } => key.clone(),
             KeyValueRequest::SubdocMultiLookup { key, .. } => key.clone(),
+            KeyValueRequest::SubdocMultiMutation { key, .. } => key.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubdocMutationOp {
+    Insert,
+    Upsert,
+    Replace,
+    Remove,
+    ArrayAppend,
+    ArrayPrepend,
+    ArrayAddUnique,
+    Counter,
+}
+
+impl SubdocMutationOp {
+    pub(crate) fn opcode(&self) -> protocol::Opcode {
+        match self {
+            Self::Insert => protocol::Opcode::SubdocDictAdd,
+            Self::Upsert => protocol::Opcode::SubdocDictUpsert,
+            Self::Replace => protocol::Opcode::SubdocReplace,
+            Self::Remove => protocol::Opcode::SubdocDelete,
+            Self::ArrayAppend => protocol::Opcode::SubdocArrayPushLast,
+            Self::ArrayPrepend => protocol::Opcode::SubdocArrayPushFirst,
+            Self::ArrayAddUnique => protocol::Opcode::SubdocArrayAddUnique,
+            Self::Counter => protocol::Opcode::SubdocCounter,
         }
     }
+
+    pub fn requires_value(&self) -> bool {
+        !matches!(self, Self::Remove)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubdocLookupSpec {
+    pub path: String,
+    pub xattr: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubdocMutationSpec {
+    pub op: SubdocMutationOp,
+    pub path: String,
+    pub value: Option<Vec<u8>>,
+    pub create_path: bool,
+}
+
+// invalid_subdoc_response is the error for a multi lookup or mutation response whose values are
+// longer than the body, which would otherwise be read past the end.
+fn invalid_subdoc_response(key: Option<String>) -> ClientError {
+    ClientError::RequestFailed {
+        reason: Some("invalid sub-document response".to_string()),
+        key,
+    }
 }
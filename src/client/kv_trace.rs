@@ -0,0 +1,445 @@
+//! Captures every frame sent and received on data service connections to a file, either as text
+//! or as a pcap which can be opened with the memcached dissector of Wireshark.
+
+use crate::client::protocol::{Magic, Opcode, Status, HEADER_SIZE};
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+static TRACER: RwLock<Option<Arc<KvTracer>>> = RwLock::new(None);
+
+// The pcap link type for raw IPv4 and IPv6 packets, so no link layer header is needed.
+const LINKTYPE_RAW: u32 = 101;
+const PCAP_SNAPLEN: usize = 65535;
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const TCP_HEADER_SIZE: usize = 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KvTraceFormat {
+    Text,
+    Pcap,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone)]
+pub struct KvTraceSettings {
+    pub path: PathBuf,
+    pub format: KvTraceFormat,
+    pub redact: bool,
+}
+
+pub struct KvTracer {
+    settings: KvTraceSettings,
+    output: Mutex<TraceOutput>,
+}
+
+struct TraceOutput {
+    writer: BufWriter<File>,
+    // The next sequence number of each direction of each connection, pcap only.
+    sequences: HashMap<(String, bool), u32>,
+}
+
+// start_kv_trace starts writing every frame to the file at path, replacing any trace already
+// running.
+pub fn start_kv_trace(settings: KvTraceSettings) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(&settings.path)?);
+    if settings.format == KvTraceFormat::Pcap {
+        writer.write_all(&pcap_global_header())?;
+        writer.flush()?;
+    }
+
+    let tracer = KvTracer {
+        settings,
+        output: Mutex::new(TraceOutput {
+            writer,
+            sequences: HashMap::new(),
+        }),
+    };
+    *TRACER.write().unwrap() = Some(Arc::new(tracer));
+    Ok(())
+}
+
+// stop_kv_trace stops the running trace, returning its settings if there was one.
+pub fn stop_kv_trace() -> Option<KvTraceSettings> {
+    TRACER.write().unwrap().take().map(|t| t.settings.clone())
+}
+
+pub fn kv_trace_settings() -> Option<KvTraceSettings> {
+    TRACER.read().unwrap().as_ref().map(|t| t.settings.clone())
+}
+
+// trace_frame records a frame if a trace is running, failures to write are logged rather than
+// failing the request.
+pub(crate) fn trace_frame(
+    connection: &str,
+    local_addr: &str,
+    remote_addr: &str,
+    direction: Direction,
+    frame: &Bytes,
+) {
+    let tracer = match TRACER.read().unwrap().as_ref() {
+        Some(t) => Arc::clone(t),
+        None => return,
+    };
+    if frame.len() < HEADER_SIZE {
+        return;
+    }
+
+    if let Err(e) = tracer.write(connection, local_addr, remote_addr, direction, frame) {
+        log::warn!(
+            "Failed to write kv trace to {}: {}",
+            tracer.settings.path.display(),
+            e
+        );
+    }
+}
+
+impl KvTracer {
+    fn write(
+        &self,
+        connection: &str,
+        local_addr: &str,
+        remote_addr: &str,
+        direction: Direction,
+        frame: &Bytes,
+    ) -> std::io::Result<()> {
+        let now = Utc::now();
+        let (frame, redacted) = redact(frame, self.settings.redact);
+
+        let mut output = self.output.lock().unwrap();
+        match self.settings.format {
+            KvTraceFormat::Text => {
+                let line = text_line(now, connection, direction, &frame, redacted);
+                writeln!(output.writer, "{}", line)?;
+            }
+            KvTraceFormat::Pcap => {
+                let sent = direction == Direction::Sent;
+                let seq = *output
+                    .sequences
+                    .get(&(connection.to_string(), sent))
+                    .unwrap_or(&1);
+                let ack = *output
+                    .sequences
+                    .get(&(connection.to_string(), !sent))
+                    .unwrap_or(&1);
+                output.sequences.insert(
+                    (connection.to_string(), sent),
+                    seq.wrapping_add(frame.len() as u32),
+                );
+
+                let local = socket_addr(local_addr, None);
+                let remote = socket_addr(remote_addr, Some(local.ip()));
+                let (src, dst) = if sent {
+                    (local, remote)
+                } else {
+                    (remote, local)
+                };
+                let record = pcap_record(now, src, dst, seq, ack, &frame);
+                output.writer.write_all(&record)?;
+            }
+        }
+        output.writer.flush()
+    }
+}
+
+// redact removes the value from a frame, leaving the header, framing extras, extras and key. The
+// values of authentication requests are always removed as they contain the password.
+fn redact(frame: &Bytes, redact: bool) -> (Bytes, Option<usize>) {
+    let header = Header::from(frame);
+    let is_auth = matches!(
+        Opcode::try_from(header.opcode),
+        Ok(Opcode::Auth) | Ok(Opcode::SaslStep)
+    );
+    let value_len = header.value_len();
+    if !(redact || is_auth) || value_len == 0 {
+        return (frame.clone(), None);
+    }
+
+    let retained = header.body_len - value_len;
+    let mut redacted = BytesMut::with_capacity(HEADER_SIZE + retained);
+    redacted.put(&frame[0..8]);
+    redacted.put_u32(retained as u32);
+    redacted.put(&frame[12..HEADER_SIZE + retained]);
+    (redacted.freeze(), Some(value_len))
+}
+
+fn text_line(
+    now: DateTime<Utc>,
+    connection: &str,
+    direction: Direction,
+    frame: &Bytes,
+    redacted: Option<usize>,
+) -> String {
+    let header = Header::from(frame);
+    let opcode = match Opcode::try_from(header.opcode) {
+        Ok(op) => format!("{:?}", op),
+        Err(op) => format!("{:#04x}", op),
+    };
+
+    let mut line = format!(
+        "{} {} {} {} opaque={}",
+        now.format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+        connection,
+        match direction {
+            Direction::Sent => ">",
+            Direction::Received => "<",
+        },
+        opcode,
+        header.opaque,
+    );
+    if Magic::from(frame[0]).is_request() {
+        let _ = write!(line, " vbucket={}", header.vbucket_or_status);
+    } else {
+        let _ = write!(
+            line,
+            " status={}",
+            Status::from(header.vbucket_or_status).as_string()
+        );
+    }
+    let _ = write!(line, " cas={} body=", header.cas);
+    for b in &frame[HEADER_SIZE..] {
+        let _ = write!(line, "{:02x}", b);
+    }
+    if let Some(len) = redacted {
+        let _ = write!(line, " value=<redacted {} bytes>", len);
+    }
+
+    line
+}
+
+struct Header {
+    opcode: u8,
+    framing_extras_len: usize,
+    key_len: usize,
+    extras_len: usize,
+    vbucket_or_status: u16,
+    body_len: usize,
+    opaque: u32,
+    cas: u64,
+}
+
+impl Header {
+    fn value_len(&self) -> usize {
+        self.body_len
+            .saturating_sub(self.framing_extras_len + self.extras_len + self.key_len)
+    }
+}
+
+impl From<&Bytes> for Header {
+    fn from(frame: &Bytes) -> Self {
+        let (framing_extras_len, key_len) = if Magic::from(frame[0]).is_flexible() {
+            (frame[2] as usize, frame[3] as usize)
+        } else {
+            (0, u16::from_be_bytes([frame[2], frame[3]]) as usize)
+        };
+
+        Self {
+            opcode: frame[1],
+            framing_extras_len,
+            key_len,
+            extras_len: frame[4] as usize,
+            vbucket_or_status: u16::from_be_bytes([frame[6], frame[7]]),
+            body_len: u32::from_be_bytes([frame[8], frame[9], frame[10], frame[11]]) as usize,
+            opaque: u32::from_be_bytes([frame[12], frame[13], frame[14], frame[15]]),
+            cas: u64::from_be_bytes(frame[16..24].try_into().unwrap()),
+        }
+    }
+}
+
+// socket_addr parses an address for the pcap, hostnames cannot be resolved here so they are
+// recorded as the unspecified address of the same family as the other end of the connection.
+fn socket_addr(addr: &str, other: Option<IpAddr>) -> SocketAddr {
+    if let Ok(a) = addr.parse::<SocketAddr>() {
+        return a;
+    }
+
+    let port = addr
+        .rsplit_once(':')
+        .and_then(|(_, p)| p.parse::<u16>().ok())
+        .unwrap_or_default();
+    let ip = match other {
+        Some(IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        _ => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    SocketAddr::new(ip, port)
+}
+
+fn pcap_global_header() -> Bytes {
+    let mut header = BytesMut::with_capacity(24);
+    header.put_u32_le(0xa1b2c3d4);
+    header.put_u16_le(2);
+    header.put_u16_le(4);
+    header.put_i32_le(0);
+    header.put_u32_le(0);
+    header.put_u32_le(PCAP_SNAPLEN as u32);
+    header.put_u32_le(LINKTYPE_RAW);
+    header.freeze()
+}
+
+// pcap_record wraps a frame in a TCP segment and IP packet, truncating frames which do not fit
+// into a single packet.
+fn pcap_record(
+    now: DateTime<Utc>,
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    ack: u32,
+    frame: &[u8],
+) -> Bytes {
+    let ip_header_size = match (src, dst) {
+        (SocketAddr::V4(_), SocketAddr::V4(_)) => IPV4_HEADER_SIZE,
+        _ => IPV6_HEADER_SIZE,
+    };
+    let headers_size = ip_header_size + TCP_HEADER_SIZE;
+    let original_len = headers_size + frame.len();
+    let captured = &frame[..frame.len().min(PCAP_SNAPLEN - headers_size)];
+
+    let mut packet = BytesMut::with_capacity(headers_size + captured.len());
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            let mut ip = BytesMut::with_capacity(IPV4_HEADER_SIZE);
+            ip.put_u8(0x45);
+            ip.put_u8(0);
+            ip.put_u16((headers_size + captured.len()) as u16);
+            ip.put_u16(0);
+            // Don't fragment.
+            ip.put_u16(0x4000);
+            ip.put_u8(64);
+            ip.put_u8(6);
+            ip.put_u16(0);
+            ip.put_slice(&s.octets());
+            ip.put_slice(&d.octets());
+            let checksum = ipv4_checksum(&ip);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.put(ip);
+        }
+        (s, d) => {
+            packet.put_u32(0x60000000);
+            packet.put_u16((TCP_HEADER_SIZE + captured.len()) as u16);
+            packet.put_u8(6);
+            packet.put_u8(64);
+            packet.put_slice(&ipv6_octets(s));
+            packet.put_slice(&ipv6_octets(d));
+        }
+    }
+
+    packet.put_u16(src.port());
+    packet.put_u16(dst.port());
+    packet.put_u32(seq);
+    packet.put_u32(ack);
+    packet.put_u8((TCP_HEADER_SIZE as u8 / 4) << 4);
+    // PSH and ACK.
+    packet.put_u8(0x18);
+    packet.put_u16(0xffff);
+    packet.put_u16(0);
+    packet.put_u16(0);
+    packet.put_slice(captured);
+
+    let mut record = BytesMut::with_capacity(16 + packet.len());
+    record.put_u32_le(now.timestamp() as u32);
+    record.put_u32_le(now.timestamp_subsec_micros());
+    record.put_u32_le(packet.len() as u32);
+    record.put_u32_le(original_len as u32);
+    record.put(packet);
+    record.freeze()
+}
+
+fn ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::protocol::{request, KvRequest};
+
+    fn set_request() -> Bytes {
+        let req = KvRequest::new(
+            Opcode::Set,
+            0,
+            12,
+            0,
+            Some(Bytes::from("key")),
+            Some(Bytes::from(vec![0u8; 8])),
+            Some(Bytes::from(r#"{"secret":true}"#)),
+            0,
+        );
+        request(req, false).freeze()
+    }
+
+    #[test]
+    fn redacts_the_value() {
+        let frame = set_request();
+        let (redacted, value_len) = redact(&frame, true);
+
+        assert_eq!(Some(15), value_len);
+        assert_eq!(HEADER_SIZE + 8 + 3, redacted.len());
+        let header = Header::from(&redacted);
+        assert_eq!(11, header.body_len);
+        assert_eq!(0, header.value_len());
+        assert_eq!(&b"key"[..], &redacted[HEADER_SIZE + 8..]);
+    }
+
+    #[test]
+    fn keeps_the_value_without_redaction() {
+        let frame = set_request();
+        let (unredacted, value_len) = redact(&frame, false);
+
+        assert_eq!(None, value_len);
+        assert_eq!(frame, unredacted);
+    }
+
+    #[test]
+    fn text_line_has_frame_details() {
+        let frame = set_request();
+        let (redacted, value_len) = redact(&frame, true);
+        let line = text_line(Utc::now(), "conn", Direction::Sent, &redacted, value_len);
+
+        assert!(line.contains(" conn > Set opaque=0 vbucket=12 cas=0 body="));
+        assert!(line.ends_with("00000000000000006b6579 value=<redacted 15 bytes>"));
+    }
+
+    #[test]
+    fn pcap_record_wraps_frame_in_tcp() {
+        let frame = set_request();
+        let src: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let dst = socket_addr("localhost:11210", Some(src.ip()));
+        let record = pcap_record(Utc::now(), src, dst, 1, 1, &frame);
+
+        let packet_len = IPV4_HEADER_SIZE + TCP_HEADER_SIZE + frame.len();
+        assert_eq!(16 + packet_len, record.len());
+        assert_eq!(&(packet_len as u32).to_le_bytes(), &record[8..12]);
+        // The checksum of a header including its checksum is zero.
+        assert_eq!(0, ipv4_checksum(&record[16..16 + IPV4_HEADER_SIZE]));
+        let tcp = &record[16 + IPV4_HEADER_SIZE..];
+        assert_eq!(&11210u16.to_be_bytes(), &tcp[2..4]);
+        assert_eq!(&frame[..], &tcp[TCP_HEADER_SIZE..]);
+    }
+}
@@ -1,12 +1,26 @@
 pub use crate::client::cloud::CapellaClient;
 pub use crate::client::cloud::CAPELLA_SRV_SUFFIX;
 pub use crate::client::cloud::CLOUD_URL;
+pub use crate::client::collections_manifest::CollectionsManifest;
+pub use crate::client::dcp::{DcpEvent, DcpStart};
+pub use crate::client::document_format::DocumentFormat;
+pub use crate::client::durability::Durability;
 pub use crate::client::error::ClientError;
+pub use crate::client::error_map::ErrorAttribute;
 pub use crate::client::http_client::{
     AnalyticsQueryRequest, Endpoint, HTTPClient, ManagementRequest, QueryRequest,
     QueryTransactionRequest, TextSearchQueryRequest, VectorSearchQueryRequest,
 };
-pub use crate::client::kv_client::{KeyValueRequest, KvClient, KvResponse};
+pub use crate::client::kv_client::{
+    KeyValueRequest, KvClient, KvResponse, MutationToken, SubdocLookupSpec, SubdocMutationOp,
+    SubdocMutationSpec,
+};
+pub use crate::client::kv_trace::{
+    kv_trace_settings, start_kv_trace, stop_kv_trace, KvTraceFormat, KvTraceSettings,
+};
+pub use crate::client::range_scan::{RangeScan, ScanItem, ScanType};
+pub use crate::client::retry::{RetryPolicy, DEFAULT_KV_MAX_RETRIES, DEFAULT_KV_MAX_RETRY_BACKOFF};
+pub use crate::client::sasl::SaslMechanism;
 pub use crate::client::tls::RustTlsConfig;
 use log::debug;
 
@@ -21,16 +35,25 @@ mod capella_ca;
 pub(crate) mod cloud;
 pub mod cloud_json;
 mod codec;
+mod collections_manifest;
 mod crc;
+mod dcp;
+mod document_format;
+mod durability;
 mod error;
+mod error_map;
 mod gemini_client;
 pub(crate) mod http_client;
 pub(crate) mod http_handler;
 mod kv;
 mod kv_client;
+mod kv_trace;
 mod llm_client;
 mod openai_client;
 mod protocol;
+mod range_scan;
+mod retry;
+mod sasl;
 mod tls;
 
 pub use llm_client::LLMClients;
@@ -40,6 +63,7 @@ pub struct Client {
     username: String,
     password: String,
     tls_config: Option<RustTlsConfig>,
+    sasl_mechanism: Option<SaslMechanism>,
 }
 
 impl Client {
@@ -48,6 +72,7 @@ impl Client {
         username: String,
         password: String,
         tls_config: Option<RustTlsConfig>,
+        sasl_mechanism: Option<SaslMechanism>,
     ) -> Self {
         let seeds = if Client::might_be_srv(&seeds) {
             match utilities::try_lookup_srv(seeds[0].clone()) {
@@ -66,6 +91,7 @@ impl Client {
             username,
             password,
             tls_config,
+            sasl_mechanism,
         }
     }
 
@@ -89,6 +115,7 @@ impl Client {
             self.username.clone(),
             self.password.clone(),
             self.tls_config.clone(),
+            self.sasl_mechanism,
             bucket.clone(),
             deadline,
             signals,
@@ -1,12 +1,17 @@
 //! Utility functions and statics for interacting with the KV binary protocol
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::warn;
 use std::convert::TryFrom;
 use std::fmt::Write as _;
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
 
 pub static HEADER_SIZE: usize = 24;
-// pub static ERROR_MAP_VERSION: u16 = 1;
+
+// Datatype bits describing how a value is encoded.
+pub const DATATYPE_JSON: u8 = 0x01;
+pub const DATATYPE_SNAPPY: u8 = 0x02;
 
 #[derive(Debug)]
 pub struct KvRequest {
@@ -16,6 +21,7 @@ pub struct KvRequest {
     opaque: u32,
     cas: u64,
     key: Option<Bytes>,
+    framing_extras: Option<Bytes>,
     extras: Option<Bytes>,
     body: Option<Bytes>,
     collection_id: u32,
@@ -39,6 +45,7 @@ impl KvRequest {
             partition,
             cas,
             key,
+            framing_extras: None,
             extras,
             body,
             opaque: 0,
@@ -46,6 +53,10 @@ impl KvRequest {
         }
     }
 
+    pub fn set_framing_extras(&mut self, framing_extras: Option<Bytes>) {
+        self.framing_extras = framing_extras;
+    }
+
     pub fn set_opaque(&mut self, opaque: u32) {
         self.opaque = opaque;
     }
@@ -62,13 +73,14 @@ impl KvRequest {
 #[derive(Debug)]
 pub struct KvResponse {
     opcode: Opcode,
-    // datatype: u8,
+    datatype: u8,
     status: Status,
     opaque: u32,
     cas: u64,
-    // key: Option<Bytes>,
+    key: Option<Bytes>,
     extras: Option<Bytes>,
     body: Option<Bytes>,
+    server_duration: Option<Duration>,
 }
 
 impl From<&Bytes> for KvResponse {
@@ -100,7 +112,7 @@ impl From<&Bytes> for KvResponse {
         // 4
         let extras_len = slice.get_u8() as usize;
         // 5
-        let _datatype = slice.get_u8();
+        let datatype = slice.get_u8();
         // 6, 7
         let status = slice.get_u16();
 
@@ -112,6 +124,14 @@ impl From<&Bytes> for KvResponse {
         let cas = slice.get_u64();
         let body_len = total_body_len - key_len - extras_len - flexible_extras_len;
 
+        let server_duration = if flexible_extras_len > 0 {
+            server_duration_from_frames(
+                input.slice(HEADER_SIZE..(HEADER_SIZE + flexible_extras_len)),
+            )
+        } else {
+            None
+        };
+
         let extras = if extras_len > 0 {
             Some(input.slice(
                 (HEADER_SIZE + flexible_extras_len)
@@ -121,7 +141,7 @@ impl From<&Bytes> for KvResponse {
             None
         };
 
-        let _key = if key_len > 0 {
+        let key = if key_len > 0 {
             Some(input.slice(
                 (HEADER_SIZE + flexible_extras_len + extras_len)
                     ..(HEADER_SIZE + flexible_extras_len + extras_len + key_len),
@@ -130,25 +150,64 @@ impl From<&Bytes> for KvResponse {
             None
         };
 
-        let body = if body_len > 0 {
+        let mut body = if body_len > 0 {
             Some(input.slice((HEADER_SIZE + flexible_extras_len + extras_len + key_len)..))
         } else {
             None
         };
 
+        // Values are only compressed once snappy has been negotiated, we always hand out the
+        // uncompressed value.
+        if datatype & DATATYPE_SNAPPY != 0 {
+            if let Some(compressed) = body.as_ref() {
+                match snap::raw::Decoder::new().decompress_vec(compressed) {
+                    Ok(decompressed) => {
+                        body = Some(Bytes::from(decompressed));
+                    }
+                    Err(e) => {
+                        warn!("Failed to decompress snappy value: {}", e);
+                    }
+                }
+            }
+        }
+
         KvResponse {
             opaque,
             body,
             extras,
-            // key,
+            key,
             status: Status::from(status),
-            // datatype,
+            datatype,
             cas,
             opcode,
+            server_duration,
         }
     }
 }
 
+// server_duration_from_frames reads the server duration from the flexible framing extras of a
+// response, which the server sends once tracing has been negotiated.
+fn server_duration_from_frames(mut frames: Bytes) -> Option<Duration> {
+    while frames.has_remaining() {
+        // The frame id is in the upper nibble and the length in the lower
+        let header = frames.get_u8();
+        let id = header >> 4;
+        let len = (header & 0x0f) as usize;
+        if frames.remaining() < len {
+            return None;
+        }
+        if id == 0x00 && len == 2 {
+            // The duration is encoded as a u16 to fit into the frame, see the server docs for
+            // the encoding.
+            let encoded = frames.get_u16() as f64;
+            let micros = encoded.powf(1.74) / 2.0;
+            return Some(Duration::from_micros(micros.round() as u64));
+        }
+        frames.advance(len);
+    }
+    None
+}
+
 impl KvResponse {
     pub fn opaque(&self) -> u32 {
         self.opaque
@@ -163,25 +222,71 @@ impl KvResponse {
         self.status
     }
 
+    // flags reads the flags of the document from the extras of a get response.
+    pub fn flags(&self) -> u32 {
+        match &self.extras {
+            Some(extras) if extras.len() >= 4 => extras.slice(0..4).get_u32(),
+            _ => 0,
+        }
+    }
+
     pub fn cas(&self) -> u64 {
         self.cas
     }
 
+    pub fn datatype(&self) -> u8 {
+        self.datatype
+    }
+
+    pub fn key(&self) -> Option<Bytes> {
+        self.key.clone()
+    }
+
+    // ends_stream is whether a successful response is the last of a stream, the server ends the
+    // responses to a stat request with one which has no key.
+    pub fn ends_stream(&self) -> bool {
+        matches!(self.opcode, Opcode::Stat) && self.key.is_none()
+    }
+
     pub fn opcode(&self) -> Opcode {
         self.opcode
     }
 
-    // extras takes the extras from the response.
-    pub fn extras(&mut self) -> Option<Bytes> {
-        self.extras.take()
+    // server_duration is how long the server spent on the operation, when tracing is enabled.
+    pub fn server_duration(&self) -> Option<Duration> {
+        self.server_duration
+    }
+
+    // mutation_token reads the vbucket uuid and sequence number assigned to a mutation from the
+    // extras of its response, the server only sends these once mutation seqnos are negotiated.
+    pub fn mutation_token(&self) -> Option<(u64, u64)> {
+        match (self.opcode, &self.extras) {
+            (
+                Opcode::Set
+                | Opcode::Add
+                | Opcode::Replace
+                | Opcode::Remove
+                | Opcode::Append
+                | Opcode::Prepend
+                | Opcode::Increment
+                | Opcode::Decrement
+                | Opcode::SubdocMultiMutation,
+                Some(extras),
+            ) if extras.len() == 16 => {
+                let mut extras = extras.clone();
+                Some((extras.get_u64(), extras.get_u64()))
+            }
+            _ => None,
+        }
     }
 }
 
-/// Creates a regular, non-flex request with all fields necessary.
+/// Creates a request with all fields necessary, this is a flexible request if framing extras are
+/// present and a regular one otherwise.
 pub fn request(req: KvRequest, collections_enabled: bool) -> BytesMut {
     let key = match req.key {
         Some(k) => {
-            if collections_enabled {
+            if collections_enabled && req.opcode.has_collection_key() {
                 let cid = make_uleb128_32(k, req.collection_id);
                 Some(cid)
             } else {
@@ -191,6 +296,20 @@ pub fn request(req: KvRequest, collections_enabled: bool) -> BytesMut {
         None => None,
     };
 
+    if req.framing_extras.is_some() {
+        return flexible_request(
+            req.opcode,
+            req.datatype,
+            req.partition,
+            req.opaque,
+            req.cas,
+            key,
+            req.framing_extras,
+            req.extras,
+            req.body,
+        );
+    }
+
     let key_size = key.as_ref().map(|b| b.len()).unwrap_or_default();
     let extras_size = req.extras.as_ref().map(|b| b.len()).unwrap_or_default();
     let total_body_size =
@@ -224,7 +343,7 @@ pub fn request(req: KvRequest, collections_enabled: bool) -> BytesMut {
 
 // Creates a flexible request with optional framing extras
 #[allow(clippy::too_many_arguments)]
-pub fn _flexible_request(
+pub fn flexible_request(
     opcode: Opcode,
     datatype: u8,
     partition: u16,
@@ -274,6 +393,15 @@ pub fn _flexible_request(
     builder
 }
 
+/// Encodes a durability requirement as a flexible framing extra.
+pub fn durability_frame(level: u8) -> Bytes {
+    let mut frame = BytesMut::with_capacity(2);
+    // The frame id (0x01, durability requirement) is in the upper nibble and the length in the lower
+    frame.put_u8(0x11);
+    frame.put_u8(level);
+    frame.freeze()
+}
+
 /// Creates a regular, non-flex response with all fields necessary.
 #[allow(clippy::too_many_arguments)]
 pub fn _response(
@@ -402,11 +530,51 @@ pub enum Opcode {
     Hello,
     Noop,
     ErrorMap,
+    SaslListMechs,
     Auth,
+    SaslStep,
     SelectBucket,
+    GetReplica,
     GetCollectionID,
     SubdocGet,
+    SubdocDictAdd,
+    SubdocDictUpsert,
+    SubdocDelete,
+    SubdocReplace,
+    SubdocArrayPushLast,
+    SubdocArrayPushFirst,
+    SubdocArrayAddUnique,
+    SubdocCounter,
     SubdocMultiLookup,
+    SubdocMultiMutation,
+    RangeScanCreate,
+    RangeScanContinue,
+    RangeScanCancel,
+    GetAllVbSeqnos,
+    DcpOpen,
+    DcpStreamReq,
+    DcpGetFailoverLog,
+    DcpStreamEnd,
+    DcpSnapshotMarker,
+    DcpMutation,
+    DcpDeletion,
+    DcpExpiration,
+    DcpNoop,
+    DcpBufferAck,
+    DcpControl,
+    DcpSystemEvent,
+    DcpSeqnoAdvanced,
+    DcpOsoSnapshot,
+    GetCollectionsManifest,
+    Increment,
+    Decrement,
+    Append,
+    Prepend,
+    Touch,
+    GetAndTouch,
+    GetAndLock,
+    Unlock,
+    Stat,
 }
 
 impl Opcode {
@@ -419,14 +587,60 @@ impl Opcode {
             Self::Remove => 0x04,
             Self::Noop => 0x0A,
             Self::Hello => 0x1F,
+            Self::SaslListMechs => 0x20,
             Self::Auth => 0x21,
+            Self::SaslStep => 0x22,
+            Self::GetReplica => 0x83,
             Self::SelectBucket => 0x89,
             Self::ErrorMap => 0xFE,
             Self::GetCollectionID => 0xBB,
             Self::SubdocGet => 0xc5,
+            Self::SubdocDictAdd => 0xc7,
+            Self::SubdocDictUpsert => 0xc8,
+            Self::SubdocDelete => 0xc9,
+            Self::SubdocReplace => 0xca,
+            Self::SubdocArrayPushLast => 0xcc,
+            Self::SubdocArrayPushFirst => 0xcd,
+            Self::SubdocArrayAddUnique => 0xce,
+            Self::SubdocCounter => 0xcf,
             Self::SubdocMultiLookup => 0xd0,
+            Self::SubdocMultiMutation => 0xd1,
+            Self::RangeScanCreate => 0xda,
+            Self::RangeScanContinue => 0xdb,
+            Self::RangeScanCancel => 0xdc,
+            Self::GetAllVbSeqnos => 0x48,
+            Self::DcpOpen => 0x50,
+            Self::DcpStreamReq => 0x53,
+            Self::DcpGetFailoverLog => 0x54,
+            Self::DcpStreamEnd => 0x55,
+            Self::DcpSnapshotMarker => 0x56,
+            Self::DcpMutation => 0x57,
+            Self::DcpDeletion => 0x58,
+            Self::DcpExpiration => 0x59,
+            Self::DcpNoop => 0x5c,
+            Self::DcpBufferAck => 0x5d,
+            Self::DcpControl => 0x5e,
+            Self::DcpSystemEvent => 0x5f,
+            Self::DcpSeqnoAdvanced => 0x64,
+            Self::DcpOsoSnapshot => 0x65,
+            Self::GetCollectionsManifest => 0xba,
+            Self::Increment => 0x05,
+            Self::Decrement => 0x06,
+            Self::Append => 0x0e,
+            Self::Prepend => 0x0f,
+            Self::Touch => 0x1c,
+            Self::GetAndTouch => 0x1d,
+            Self::GetAndLock => 0x94,
+            Self::Unlock => 0x95,
+            Self::Stat => 0x10,
         }
     }
+
+    // has_collection_key is whether the key of a request is a document key, which is prefixed with
+    // the collection id once collections are enabled.
+    pub fn has_collection_key(&self) -> bool {
+        !matches!(self, Self::DcpOpen | Self::DcpControl | Self::Stat)
+    }
 }
 
 impl Display for Opcode {
@@ -447,12 +661,52 @@ impl TryFrom<u8> for Opcode {
             0x04 => Opcode::Remove,
             0x0A => Opcode::Noop,
             0x1F => Opcode::Hello,
+            0x20 => Opcode::SaslListMechs,
             0x21 => Opcode::Auth,
+            0x22 => Opcode::SaslStep,
+            0x83 => Opcode::GetReplica,
             0x89 => Opcode::SelectBucket,
             0xFE => Opcode::ErrorMap,
             0xBB => Opcode::GetCollectionID,
             0xc5 => Opcode::SubdocGet,
+            0xc7 => Opcode::SubdocDictAdd,
+            0xc8 => Opcode::SubdocDictUpsert,
+            0xc9 => Opcode::SubdocDelete,
+            0xca => Opcode::SubdocReplace,
+            0xcc => Opcode::SubdocArrayPushLast,
+            0xcd => Opcode::SubdocArrayPushFirst,
+            0xce => Opcode::SubdocArrayAddUnique,
+            0xcf => Opcode::SubdocCounter,
             0xd0 => Opcode::SubdocMultiLookup,
+            0xd1 => Opcode::SubdocMultiMutation,
+            0xda => Opcode::RangeScanCreate,
+            0xdb => Opcode::RangeScanContinue,
+            0xdc => Opcode::RangeScanCancel,
+            0x48 => Opcode::GetAllVbSeqnos,
+            0x50 => Opcode::DcpOpen,
+            0x53 => Opcode::DcpStreamReq,
+            0x54 => Opcode::DcpGetFailoverLog,
+            0x55 => Opcode::DcpStreamEnd,
+            0x56 => Opcode::DcpSnapshotMarker,
+            0x57 => Opcode::DcpMutation,
+            0x58 => Opcode::DcpDeletion,
+            0x59 => Opcode::DcpExpiration,
+            0x5c => Opcode::DcpNoop,
+            0x5d => Opcode::DcpBufferAck,
+            0x5e => Opcode::DcpControl,
+            0x5f => Opcode::DcpSystemEvent,
+            0x64 => Opcode::DcpSeqnoAdvanced,
+            0x65 => Opcode::DcpOsoSnapshot,
+            0xba => Opcode::GetCollectionsManifest,
+            0x05 => Opcode::Increment,
+            0x06 => Opcode::Decrement,
+            0x0e => Opcode::Append,
+            0x0f => Opcode::Prepend,
+            0x1c => Opcode::Touch,
+            0x1d => Opcode::GetAndTouch,
+            0x94 => Opcode::GetAndLock,
+            0x95 => Opcode::Unlock,
+            0x10 => Opcode::Stat,
             _ => return Err(input),
         })
     }
@@ -481,6 +735,11 @@ impl Magic {
     pub fn is_flexible(&self) -> bool {
         matches!(self, Self::FlexibleRequest | Self::FlexibleResponse)
     }
+
+    // is_request is whether the packet is a request, which the server sends on DCP connections.
+    pub fn is_request(&self) -> bool {
+        matches!(self, Self::Request | Self::FlexibleRequest)
+    }
 }
 
 impl From<u8> for Magic {
@@ -499,12 +758,46 @@ impl From<u8> for Magic {
 pub enum Status {
     Success,
     AuthError,
+    AuthContinue,
     AccessError,
     KeyNotFound,
     KeyExists,
+    NotMyVbucket,
     CollectionUnknown,
     ScopeUnknown,
+    DurabilityInvalidLevel,
+    DurabilityImpossible,
+    SyncWriteInProgress,
+    SyncWriteAmbiguous,
     PathNotFound,
+    PathMismatch,
+    PathInvalid,
+    PathTooBig,
+    DocTooDeep,
+    ValueCantInsert,
+    DocNotJson,
+    NumRange,
+    DeltaInvalid,
+    PathExists,
+    ValueTooDeep,
+    InvalidCombo,
+    SubdocMultiPathFailure,
+    RangeScanCancelled,
+    RangeScanMore,
+    RangeScanComplete,
+    RangeScanVbUuidNotEqual,
+    Rollback,
+    NotStored,
+    DeltaBadValue,
+    Locked,
+    NotLocked,
+    Busy,
+    TemporaryFailure,
+    SyncWriteReCommitInProgress,
+    RateLimitedNetworkIngress,
+    RateLimitedNetworkEgress,
+    RateLimitedMaxConnections,
+    RateLimitedMaxCommands,
     Unknown(u16),
 }
 
@@ -515,16 +808,98 @@ impl Display for Status {
 }
 
 impl Status {
+    pub fn encoded(&self) -> u16 {
+        match self {
+            Status::Success => 0x00,
+            Status::KeyNotFound => 0x01,
+            Status::KeyExists => 0x02,
+            Status::NotMyVbucket => 0x07,
+            Status::CollectionUnknown => 0x88,
+            Status::ScopeUnknown => 0x8c,
+            Status::AuthError => 0x20,
+            Status::AuthContinue => 0x21,
+            Status::AccessError => 0x24,
+            Status::DurabilityInvalidLevel => 0xa0,
+            Status::DurabilityImpossible => 0xa1,
+            Status::SyncWriteInProgress => 0xa2,
+            Status::SyncWriteAmbiguous => 0xa3,
+            Status::PathNotFound => 0xc0,
+            Status::PathMismatch => 0xc1,
+            Status::PathInvalid => 0xc2,
+            Status::PathTooBig => 0xc3,
+            Status::DocTooDeep => 0xc4,
+            Status::ValueCantInsert => 0xc5,
+            Status::DocNotJson => 0xc6,
+            Status::NumRange => 0xc7,
+            Status::DeltaInvalid => 0xc8,
+            Status::PathExists => 0xc9,
+            Status::ValueTooDeep => 0xca,
+            Status::InvalidCombo => 0xcb,
+            Status::SubdocMultiPathFailure => 0xcc,
+            Status::RangeScanCancelled => 0xa5,
+            Status::RangeScanMore => 0xa6,
+            Status::RangeScanComplete => 0xa7,
+            Status::RangeScanVbUuidNotEqual => 0xa8,
+            Status::Rollback => 0x23,
+            Status::NotStored => 0x05,
+            Status::DeltaBadValue => 0x06,
+            Status::Locked => 0x09,
+            Status::NotLocked => 0x0e,
+            Status::Busy => 0x85,
+            Status::TemporaryFailure => 0x86,
+            Status::SyncWriteReCommitInProgress => 0xa4,
+            Status::RateLimitedNetworkIngress => 0x30,
+            Status::RateLimitedNetworkEgress => 0x31,
+            Status::RateLimitedMaxConnections => 0x32,
+            Status::RateLimitedMaxCommands => 0x33,
+            Status::Unknown(status) => *status,
+        }
+    }
+
     pub fn as_string(&self) -> String {
         match self {
             Status::Success => "success".into(),
             Status::AuthError => "authentication error".into(),
+            Status::AuthContinue => "authentication continue".into(),
             Status::AccessError => "access error".into(),
             Status::KeyNotFound => "key not found".into(),
             Status::KeyExists => "key already exists".into(),
+            Status::NotMyVbucket => "not my vbucket".into(),
             Status::CollectionUnknown => "collection unknown".into(),
             Status::ScopeUnknown => "scope unknown".into(),
+            Status::DurabilityInvalidLevel => "durability level invalid".into(),
+            Status::DurabilityImpossible => "durability impossible".into(),
+            Status::SyncWriteInProgress => "sync write in progress".into(),
+            Status::SyncWriteAmbiguous => "sync write ambiguous".into(),
             Status::PathNotFound => "field not found".into(),
+            Status::PathMismatch => "path mismatch".into(),
+            Status::PathInvalid => "path invalid".into(),
+            Status::PathTooBig => "path too big".into(),
+            Status::DocTooDeep => "document too deep".into(),
+            Status::ValueCantInsert => "value cannot be inserted".into(),
+            Status::DocNotJson => "document is not JSON".into(),
+            Status::NumRange => "number out of range".into(),
+            Status::DeltaInvalid => "counter delta invalid".into(),
+            Status::PathExists => "field already exists".into(),
+            Status::ValueTooDeep => "value too deep".into(),
+            Status::InvalidCombo => "invalid combination of sub-document operations".into(),
+            Status::SubdocMultiPathFailure => "sub-document path failure".into(),
+            Status::RangeScanCancelled => "range scan cancelled".into(),
+            Status::RangeScanMore => "range scan has more items".into(),
+            Status::RangeScanComplete => "range scan complete".into(),
+            Status::RangeScanVbUuidNotEqual => "range scan vbucket uuid mismatch".into(),
+            Status::Rollback => "rollback required".into(),
+            Status::NotStored => "not stored".into(),
+            Status::DeltaBadValue => "value is not a number".into(),
+            Status::Locked => "document locked".into(),
+            Status::NotLocked => "document not locked".into(),
+            Status::Busy => "server busy".into(),
+            Status::TemporaryFailure => "temporary failure".into(),
+            Status::SyncWriteReCommitInProgress => "sync write re-commit in progress".into(),
+            Status::RateLimitedNetworkIngress => "rate limited: network ingress".into(),
+            Status::RateLimitedNetworkEgress => "rate limited: network egress".into(),
+            Status::RateLimitedMaxConnections => "rate limited: max connections".into(),
+            Status::RateLimitedMaxCommands => "rate limited: max commands".into(),
             Status::Unknown(status) => format!("{:#04x}", status),
         }
     }
@@ -536,11 +911,45 @@ impl From<u16> for Status {
             0x00 => Status::Success,
             0x01 => Status::KeyNotFound,
             0x02 => Status::KeyExists,
+            0x07 => Status::NotMyVbucket,
             0x88 => Status::CollectionUnknown,
             0x8c => Status::ScopeUnknown,
             0x20 => Status::AuthError,
+            0x21 => Status::AuthContinue,
             0x24 => Status::AccessError,
+            0xa0 => Status::DurabilityInvalidLevel,
+            0xa1 => Status::DurabilityImpossible,
+            0xa2 => Status::SyncWriteInProgress,
+            0xa3 => Status::SyncWriteAmbiguous,
             0xc0 => Status::PathNotFound,
+            0xc1 => Status::PathMismatch,
+            0xc2 => Status::PathInvalid,
+            0xc3 => Status::PathTooBig,
+            0xc4 => Status::DocTooDeep,
+            0xc5 => Status::ValueCantInsert,
+            0xc6 => Status::DocNotJson,
+            0xc7 => Status::NumRange,
+            0xc8 => Status::DeltaInvalid,
+            0xc9 => Status::PathExists,
+            0xca => Status::ValueTooDeep,
+            0xcb => Status::InvalidCombo,
+            0xcc => Status::SubdocMultiPathFailure,
+            0xa5 => Status::RangeScanCancelled,
+            0xa6 => Status::RangeScanMore,
+            0xa7 => Status::RangeScanComplete,
+            0xa8 => Status::RangeScanVbUuidNotEqual,
+            0x23 => Status::Rollback,
+            0x05 => Status::NotStored,
+            0x06 => Status::DeltaBadValue,
+            0x09 => Status::Locked,
+            0x0e => Status::NotLocked,
+            0x85 => Status::Busy,
+            0x86 => Status::TemporaryFailure,
+            0xa4 => Status::SyncWriteReCommitInProgress,
+            0x30 => Status::RateLimitedNetworkIngress,
+            0x31 => Status::RateLimitedNetworkEgress,
+            0x32 => Status::RateLimitedMaxConnections,
+            0x33 => Status::RateLimitedMaxCommands,
             _ => Status::Unknown(input),
         }
     }
@@ -567,3 +976,134 @@ fn make_uleb128_32(key: Bytes, collection_id: u32) -> Bytes {
 
     builder.freeze()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_without_framing_extras_is_not_flexible() {
+        let req = KvRequest::new(
+            Opcode::Get,
+            0,
+            0,
+            0,
+            Some(Bytes::from("key")),
+            None,
+            None,
+            0,
+        );
+
+        let packet = request(req, false);
+        assert_eq!(Magic::Request.encoded(), packet[0]);
+        assert_eq!(HEADER_SIZE + 3, packet.len());
+    }
+
+    #[test]
+    fn request_with_framing_extras_is_flexible() {
+        let mut req = KvRequest::new(
+            Opcode::Set,
+            0,
+            0,
+            0,
+            Some(Bytes::from("key")),
+            None,
+            Some(Bytes::from("{}")),
+            0,
+        );
+        req.set_framing_extras(Some(durability_frame(0x01)));
+
+        let packet = request(req, false);
+        assert_eq!(Magic::FlexibleRequest.encoded(), packet[0]);
+        // Framing extras length, key length and total body length.
+        assert_eq!(2, packet[2]);
+        assert_eq!(3, packet[3]);
+        assert_eq!(&[0, 0, 0, 7], &packet[8..12]);
+        assert_eq!(&[0x11, 0x01], &packet[HEADER_SIZE..HEADER_SIZE + 2]);
+    }
+
+    #[test]
+    fn snappy_response_is_decompressed() {
+        let value = br#"{"name":"snappy","description":"compressed compressed compressed"}"#;
+        let compressed = snap::raw::Encoder::new().compress_vec(value).unwrap();
+
+        let mut packet = BytesMut::new();
+        packet.put_u8(Magic::Response.encoded());
+        packet.put_u8(Opcode::Get.encoded());
+        packet.put_u16(0);
+        packet.put_u8(0);
+        packet.put_u8(DATATYPE_SNAPPY);
+        packet.put_u16(0);
+        packet.put_u32(compressed.len() as u32);
+        packet.put_u32(0);
+        packet.put_u64(0);
+        packet.put(compressed.as_slice());
+
+        let mut response = KvResponse::from(&packet.freeze());
+        assert_eq!(&value[..], response.body().unwrap().as_ref());
+    }
+
+    #[test]
+    fn server_duration_is_read_from_flexible_response() {
+        let mut packet = BytesMut::new();
+        packet.put_u8(Magic::FlexibleResponse.encoded());
+        packet.put_u8(Opcode::Get.encoded());
+        // Framing extras length and key length.
+        packet.put_u8(3);
+        packet.put_u8(0);
+        packet.put_u8(0);
+        packet.put_u8(DATATYPE_JSON);
+        packet.put_u16(0);
+        packet.put_u32(5);
+        packet.put_u32(0);
+        packet.put_u64(0);
+        packet.put_u8(0x02);
+        packet.put_u16(100);
+        packet.put(&b"{}"[..]);
+
+        let mut response = KvResponse::from(&packet.freeze());
+        // 100^1.74 / 2 is about 1510 microseconds.
+        assert_eq!(
+            Some(Duration::from_micros(1510)),
+            response.server_duration()
+        );
+        assert_eq!(&b"{}"[..], response.body().unwrap().as_ref());
+    }
+
+    #[test]
+    fn regular_response_has_no_server_duration() {
+        let packet = _response(Opcode::Get, 0, 0, 0, 0, None, None, None);
+        let response = KvResponse::from(&packet.freeze());
+        assert_eq!(None, response.server_duration());
+    }
+
+    #[test]
+    fn mutation_token_is_read_from_extras() {
+        let mut extras = BytesMut::new();
+        extras.put_u64(0xabcd);
+        extras.put_u64(42);
+        let packet = _response(Opcode::Set, 0, 0, 0, 1, None, Some(extras.freeze()), None);
+        let response = KvResponse::from(&packet.freeze());
+        assert_eq!(Some((0xabcd, 42)), response.mutation_token());
+
+        let mut flags = BytesMut::new();
+        flags.put_u32(0x02000006);
+        let packet = _response(Opcode::Get, 0, 0, 0, 1, None, Some(flags.freeze()), None);
+        let response = KvResponse::from(&packet.freeze());
+        assert_eq!(None, response.mutation_token());
+    }
+
+    #[test]
+    fn subdoc_statuses_round_trip() {
+        assert_eq!(Status::ValueCantInsert, Status::from(0xc5));
+        assert_eq!(Status::DocNotJson, Status::from(0xc6));
+        assert_eq!(Status::NumRange, Status::from(0xc7));
+        assert_eq!(Status::DeltaInvalid, Status::from(0xc8));
+
+        for code in 0xc0..=0xcc {
+            let status = Status::from(code);
+            assert!(!matches!(status, Status::Unknown(_)), "{:#04x}", code);
+            assert_eq!(code, status.encoded());
+        }
+    }
+}
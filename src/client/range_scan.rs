@@ -0,0 +1,274 @@
+//! Types for scanning the documents of a collection with the KV range scan protocol.
+
+use crate::client::protocol::DATATYPE_SNAPPY;
+use crate::client::ClientError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::{Buf, Bytes};
+use serde_json::json;
+
+// The smallest and largest possible keys, used when scanning everything or everything with a
+// prefix.
+const MIN_KEY: &str = "\u{0}";
+const MAX_KEY: &str = "\u{10ffff}";
+
+#[derive(Debug, Clone)]
+pub enum ScanType {
+    // Range scans every key between from and to, inclusive.
+    Range { from: String, to: String },
+    // Sampling scans a random selection of up to samples keys.
+    Sampling { samples: u64, seed: u64 },
+}
+
+impl ScanType {
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        Self::Range {
+            to: format!("{}{}", prefix, MAX_KEY),
+            from: prefix,
+        }
+    }
+
+    pub fn range(from: Option<String>, to: Option<String>) -> Self {
+        Self::Range {
+            from: from.unwrap_or_else(|| MIN_KEY.to_string()),
+            to: to.unwrap_or_else(|| MAX_KEY.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RangeScan {
+    pub scan_type: ScanType,
+    pub ids_only: bool,
+}
+
+impl RangeScan {
+    // limit is the maximum number of items that the whole scan should return, if any.
+    pub fn limit(&self) -> Option<u64> {
+        match self.scan_type {
+            ScanType::Sampling { samples, .. } => Some(samples),
+            ScanType::Range { .. } => None,
+        }
+    }
+
+    // create_body creates the body of the range scan create request for a collection.
+    pub(crate) fn create_body(&self, collection_id: u32) -> Vec<u8> {
+        let mut body = json!({
+            "collection": format!("{:x}", collection_id),
+        });
+        if self.ids_only {
+            body["key_only"] = json!(true);
+        }
+
+        match &self.scan_type {
+            ScanType::Range { from, to } => {
+                body["range"] = json!({
+                    "start": STANDARD.encode(from),
+                    "end": STANDARD.encode(to),
+                });
+            }
+            // Each vbucket is asked for the full number of samples, the scan as a whole stops
+            // once enough have been returned.
+            ScanType::Sampling { samples, seed } => {
+                body["sampling"] = json!({
+                    "samples": samples,
+                    "seed": seed,
+                });
+            }
+        }
+
+        body.to_string().into_bytes()
+    }
+
+    // parse_items parses the items in the body of a range scan continue response.
+    pub(crate) fn parse_items(&self, body: Option<Bytes>) -> Result<Vec<ScanItem>, ClientError> {
+        let mut body = match body {
+            Some(b) => b,
+            None => return Ok(vec![]),
+        };
+
+        let mut items = vec![];
+        while body.has_remaining() {
+            if self.ids_only {
+                items.push(ScanItem {
+                    key: read_string(&mut body)?,
+                    content: None,
+                    cas: 0,
+                });
+                continue;
+            }
+
+            if body.remaining() < 25 {
+                return Err(invalid_response());
+            }
+            let _flags = body.get_u32();
+            let _expiry = body.get_u32();
+            let _seqno = body.get_u64();
+            let cas = body.get_u64();
+            let datatype = body.get_u8();
+            let key = read_string(&mut body)?;
+            let value = read_bytes(&mut body)?;
+
+            let content = if datatype & DATATYPE_SNAPPY != 0 {
+                snap::raw::Decoder::new()
+                    .decompress_vec(&value)
+                    .map_err(|e| ClientError::RequestFailed {
+                        reason: Some(e.to_string()),
+                        key: Some(key.clone()),
+                    })?
+            } else {
+                value.to_vec()
+            };
+
+            items.push(ScanItem {
+                key,
+                content: Some(content),
+                cas,
+            });
+        }
+
+        Ok(items)
+    }
+}
+
+#[derive(Debug)]
+pub struct ScanItem {
+    pub key: String,
+    pub content: Option<Vec<u8>>,
+    pub cas: u64,
+}
+
+fn read_uleb128(body: &mut Bytes) -> Result<usize, ClientError> {
+    let mut result = 0usize;
+    let mut shift = 0;
+    loop {
+        if !body.has_remaining() || shift > 63 {
+            return Err(invalid_response());
+        }
+        let byte = body.get_u8();
+        result |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+fn read_bytes(body: &mut Bytes) -> Result<Bytes, ClientError> {
+    let len = read_uleb128(body)?;
+    if body.remaining() < len {
+        return Err(invalid_response());
+    }
+    Ok(body.split_to(len))
+}
+
+fn read_string(body: &mut Bytes) -> Result<String, ClientError> {
+    let bytes = read_bytes(body)?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+fn invalid_response() -> ClientError {
+    ClientError::RequestFailed {
+        reason: Some("invalid range scan response".to_string()),
+        key: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{BufMut, BytesMut};
+
+    #[test]
+    fn creates_prefix_scan_body() {
+        let scan = RangeScan {
+            scan_type: ScanType::prefix("airline_"),
+            ids_only: true,
+        };
+
+        let body: serde_json::Value = serde_json::from_slice(&scan.create_body(0x1f)).unwrap();
+        assert_eq!("1f", body["collection"]);
+        assert_eq!(true, body["key_only"]);
+        assert_eq!(STANDARD.encode("airline_"), body["range"]["start"]);
+        assert_eq!(STANDARD.encode("airline_\u{10ffff}"), body["range"]["end"]);
+    }
+
+    #[test]
+    fn creates_sampling_scan_body() {
+        let scan = RangeScan {
+            scan_type: ScanType::Sampling {
+                samples: 10,
+                seed: 42,
+            },
+            ids_only: false,
+        };
+
+        let body: serde_json::Value = serde_json::from_slice(&scan.create_body(8)).unwrap();
+        assert_eq!("8", body["collection"]);
+        assert!(body.get("key_only").is_none());
+        assert_eq!(10, body["sampling"]["samples"]);
+        assert_eq!(42, body["sampling"]["seed"]);
+        assert_eq!(Some(10), scan.limit());
+    }
+
+    #[test]
+    fn parses_keys() {
+        let scan = RangeScan {
+            scan_type: ScanType::range(None, None),
+            ids_only: true,
+        };
+
+        let mut body = BytesMut::new();
+        body.put_u8(3);
+        body.put_slice(b"one");
+        body.put_u8(3);
+        body.put_slice(b"two");
+
+        let items = scan.parse_items(Some(body.freeze())).unwrap();
+        let keys: Vec<String> = items.into_iter().map(|i| i.key).collect();
+        assert_eq!(vec!["one".to_string(), "two".to_string()], keys);
+    }
+
+    #[test]
+    fn parses_documents() {
+        let scan = RangeScan {
+            scan_type: ScanType::range(None, None),
+            ids_only: false,
+        };
+
+        let value = br#"{"name":"compressed compressed compressed"}"#;
+        let compressed = snap::raw::Encoder::new().compress_vec(value).unwrap();
+
+        let mut body = BytesMut::new();
+        body.put_u32(0x02000006);
+        body.put_u32(100);
+        body.put_u64(7);
+        body.put_u64(0x1234);
+        body.put_u8(DATATYPE_SNAPPY | 0x01);
+        body.put_u8(3);
+        body.put_slice(b"one");
+        body.put_u8(compressed.len() as u8);
+        body.put_slice(&compressed);
+
+        let items = scan.parse_items(Some(body.freeze())).unwrap();
+        assert_eq!(1, items.len());
+        assert_eq!("one", items[0].key);
+        assert_eq!(0x1234, items[0].cas);
+        assert_eq!(Some(value.to_vec()), items[0].content);
+    }
+
+    #[test]
+    fn rejects_truncated_items() {
+        let scan = RangeScan {
+            scan_type: ScanType::range(None, None),
+            ids_only: true,
+        };
+
+        let mut body = BytesMut::new();
+        body.put_u8(10);
+        body.put_slice(b"short");
+
+        assert!(scan.parse_items(Some(body.freeze())).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use rand::Rng;
+use std::time::Duration;
+
+pub const DEFAULT_KV_MAX_RETRIES: u32 = 10;
+pub const DEFAULT_KV_MAX_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(2);
+
+// RetryPolicy controls how requests which fail temporarily, such as when the server is short of
+// memory, are retried. Retries are always bounded by the deadline of the request as well.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, max_backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            max_backoff,
+        }
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        self.max_backoff
+    }
+
+    // backoff is how long to wait before the given retry, the backoff doubles with every retry up
+    // to the maximum and half of it is random so that retries of a batch of requests spread out.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let half = backoff / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_micros() as u64);
+        half + Duration::from_micros(jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_KV_MAX_RETRIES, DEFAULT_KV_MAX_RETRY_BACKOFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_with_jitter() {
+        let policy = RetryPolicy::default();
+        for retry in 0..5 {
+            let base = INITIAL_BACKOFF * 2u32.pow(retry);
+            let backoff = policy.backoff(retry);
+            assert!(backoff >= base / 2, "{:?} < {:?}", backoff, base / 2);
+            assert!(backoff <= base, "{:?} > {:?}", backoff, base);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy::new(100, Duration::from_millis(50));
+        for retry in [10, 31, 32, 100] {
+            assert!(policy.backoff(retry) <= Duration::from_millis(50));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! SASL mechanisms used to authenticate kv connections.
+
+use crate::client::ClientError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Mac, SimpleHmac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::digest::core_api::BlockSizeUser;
+use sha2::{Digest, Sha256, Sha512};
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA1")]
+    ScramSha1,
+    #[serde(rename = "SCRAM-SHA256")]
+    ScramSha256,
+    #[serde(rename = "SCRAM-SHA512")]
+    ScramSha512,
+}
+
+impl SaslMechanism {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Plain => "PLAIN",
+            Self::ScramSha1 => "SCRAM-SHA1",
+            Self::ScramSha256 => "SCRAM-SHA256",
+            Self::ScramSha512 => "SCRAM-SHA512",
+        }
+    }
+
+    // preferred returns the strongest SCRAM mechanism supported by the server, falling back to
+    // PLAIN if the server doesn't support any.
+    pub(crate) fn preferred(supported: &[String]) -> SaslMechanism {
+        for mechanism in [Self::ScramSha512, Self::ScramSha256, Self::ScramSha1] {
+            if supported.iter().any(|m| m == mechanism.name()) {
+                return mechanism;
+            }
+        }
+
+        Self::Plain
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::ScramSha1 => Sha1::digest(data).to_vec(),
+            Self::ScramSha256 => Sha256::digest(data).to_vec(),
+            Self::ScramSha512 => Sha512::digest(data).to_vec(),
+            Self::Plain => unreachable!("PLAIN is not a SCRAM mechanism"),
+        }
+    }
+
+    fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::ScramSha1 => hmac_with::<Sha1>(key, data),
+            Self::ScramSha256 => hmac_with::<Sha256>(key, data),
+            Self::ScramSha512 => hmac_with::<Sha512>(key, data),
+            Self::Plain => unreachable!("PLAIN is not a SCRAM mechanism"),
+        }
+    }
+
+    // salted_password is the Hi function from RFC 5802, which is PBKDF2 with the HMAC of the
+    // mechanism as the pseudorandom function.
+    fn salted_password(&self, password: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut block = salt.to_vec();
+        block.extend_from_slice(&1u32.to_be_bytes());
+
+        let mut u = self.hmac(password.as_bytes(), &block);
+        let mut result = u.clone();
+        for _ in 1..iterations {
+            u = self.hmac(password.as_bytes(), &u);
+            for (r, b) in result.iter_mut().zip(u.iter()) {
+                *r ^= b;
+            }
+        }
+
+        result
+    }
+}
+
+impl Display for SaslMechanism {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl TryFrom<&str> for SaslMechanism {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "PLAIN" => Ok(Self::Plain),
+            "SCRAM-SHA1" => Ok(Self::ScramSha1),
+            "SCRAM-SHA256" => Ok(Self::ScramSha256),
+            "SCRAM-SHA512" => Ok(Self::ScramSha512),
+            _ => Err(format!("unknown sasl mechanism {}", value)),
+        }
+    }
+}
+
+fn hmac_with<D: Digest + BlockSizeUser>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = SimpleHmac::<D>::new_from_slice(key).expect("hmac accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// plain_body creates the body of a PLAIN authentication request.
+pub(crate) fn plain_body(username: &str, password: &str) -> Vec<u8> {
+    let mut body = Vec::with_capacity(username.len() + password.len() + 2);
+    body.push(0);
+    body.extend_from_slice(username.as_bytes());
+    body.push(0);
+    body.extend_from_slice(password.as_bytes());
+    body
+}
+
+/// The client side of a SCRAM exchange as described in RFC 5802, without channel binding.
+pub(crate) struct ScramClient {
+    mechanism: SaslMechanism,
+    password: String,
+    client_nonce: String,
+    client_first_bare: String,
+    server_signature: Option<Vec<u8>>,
+}
+
+impl ScramClient {
+    pub fn new(mechanism: SaslMechanism, username: &str, password: &str) -> Self {
+        let nonce: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect();
+
+        Self::with_nonce(mechanism, username, password, nonce)
+    }
+
+    fn with_nonce(
+        mechanism: SaslMechanism,
+        username: &str,
+        password: &str,
+        client_nonce: String,
+    ) -> Self {
+        let username = username.replace('=', "=3D").replace(',', "=2C");
+        Self {
+            mechanism,
+            password: password.to_string(),
+            client_first_bare: format!("n={},r={}", username, client_nonce),
+            client_nonce,
+            server_signature: None,
+        }
+    }
+
+    pub fn client_first(&self) -> String {
+        format!("n,,{}", self.client_first_bare)
+    }
+
+    // client_final handles the server first message and creates the client final message
+    // containing the proof.
+    pub fn client_final(&mut self, server_first: &str) -> Result<String, ClientError> {
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attr in server_first.split(',') {
+            if let Some(v) = attr.strip_prefix("r=") {
+                nonce = Some(v);
+            } else if let Some(v) = attr.strip_prefix("s=") {
+                salt = Some(STANDARD.decode(v).map_err(|e| auth_error(e.to_string()))?);
+            } else if let Some(v) = attr.strip_prefix("i=") {
+                iterations = Some(v.parse::<u32>().map_err(|e| auth_error(e.to_string()))?);
+            }
+        }
+
+        let (nonce, salt, iterations) = match (nonce, salt, iterations) {
+            (Some(n), Some(s), Some(i)) => (n, s, i),
+            _ => return Err(auth_error("invalid server first message")),
+        };
+        if !nonce.starts_with(&self.client_nonce) || iterations == 0 {
+            return Err(auth_error("invalid server first message"));
+        }
+
+        let client_final_without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, server_first, client_final_without_proof
+        );
+
+        let m = self.mechanism;
+        let salted_password = m.salted_password(&self.password, &salt, iterations);
+        let client_key = m.hmac(&salted_password, b"Client Key");
+        let stored_key = m.hash(&client_key);
+        let client_signature = m.hmac(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature.iter())
+            .map(|(k, s)| k ^ s)
+            .collect();
+
+        let server_key = m.hmac(&salted_password, b"Server Key");
+        self.server_signature = Some(m.hmac(&server_key, auth_message.as_bytes()));
+
+        Ok(format!(
+            "{},p={}",
+            client_final_without_proof,
+            STANDARD.encode(proof)
+        ))
+    }
+
+    // verify_server_final checks that the server knows the password too.
+    pub fn verify_server_final(&self, server_final: &str) -> Result<(), ClientError> {
+        let expected = match &self.server_signature {
+            Some(s) => s,
+            None => return Err(auth_error("server final message received out of order")),
+        };
+
+        let signature = server_final
+            .split(',')
+            .find_map(|attr| attr.strip_prefix("v="))
+            .ok_or_else(|| auth_error("invalid server final message"))?;
+        let signature = STANDARD
+            .decode(signature)
+            .map_err(|e| auth_error(e.to_string()))?;
+
+        if &signature != expected {
+            return Err(auth_error("server signature does not match"));
+        }
+
+        Ok(())
+    }
+}
+
+fn auth_error(reason: impl Into<String>) -> ClientError {
+    ClientError::AuthError {
+        reason: Some(reason.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scram_sha1_exchange() {
+        // Test vector from RFC 5802.
+        let mut client = ScramClient::with_nonce(
+            SaslMechanism::ScramSha1,
+            "user",
+            "pencil",
+            "fyko+d2lbbFgONRv9qkxdawL".to_string(),
+        );
+
+        assert_eq!(
+            "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL",
+            client.client_first()
+        );
+
+        let client_final = client
+            .client_final("r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096")
+            .unwrap();
+        assert_eq!(
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts=",
+            client_final
+        );
+
+        client
+            .verify_server_final("v=rmF9pqV8S7suAoZWja4dJRkFsKQ=")
+            .unwrap();
+    }
+
+    #[test]
+    fn scram_sha256_exchange() {
+        // Test vector from RFC 7677.
+        let mut client = ScramClient::with_nonce(
+            SaslMechanism::ScramSha256,
+            "user",
+            "pencil",
+            "rOprNGfwEbeRWgbNEkqO".to_string(),
+        );
+
+        let client_final = client
+            .client_final(
+                "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            )
+            .unwrap();
+        assert_eq!(
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=",
+            client_final
+        );
+
+        client
+            .verify_server_final("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+            .unwrap();
+    }
+
+    #[test]
+    fn scram_rejects_bad_server_signature() {
+        let mut client = ScramClient::with_nonce(
+            SaslMechanism::ScramSha1,
+            "user",
+            "pencil",
+            "fyko+d2lbbFgONRv9qkxdawL".to_string(),
+        );
+        client
+            .client_final("r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096")
+            .unwrap();
+
+        assert!(client
+            .verify_server_final("v=AAAAAAAAAAAAAAAAAAAAAAAAAAA=")
+            .is_err());
+    }
+
+    #[test]
+    fn prefers_strongest_scram_mechanism() {
+        let supported = vec![
+            "SCRAM-SHA1".to_string(),
+            "SCRAM-SHA256".to_string(),
+            "PLAIN".to_string(),
+        ];
+        assert_eq!(
+            SaslMechanism::ScramSha256,
+            SaslMechanism::preferred(&supported)
+        );
+        assert_eq!(
+            SaslMechanism::Plain,
+            SaslMechanism::preferred(&["PLAIN".to_string()])
+        );
+    }
+}
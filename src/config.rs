@@ -1,3 +1,4 @@
+use crate::client::{SaslMechanism, DEFAULT_KV_MAX_RETRIES, DEFAULT_KV_MAX_RETRY_BACKOFF};
 use crate::remote_cluster::{ClusterTimeouts, RemoteCluster, RemoteClusterType};
 use crate::state::Provider;
 use log::debug;
@@ -382,6 +383,10 @@ impl ClusterConfigBuilder {
             timeouts: ClusterConfigTimeouts::default(),
             tls: self.tls.unwrap_or_default(),
             kv_batch_size: None,
+            sasl_mechanism: None,
+            kv_compression_threshold: None,
+            kv_max_retries: None,
+            kv_max_retry_backoff: None,
             capella_org: None,
             project: None,
             cluster_type: None,
@@ -413,6 +418,28 @@ pub struct ClusterConfig {
     #[serde(rename(deserialize = "kv-batch-size", serialize = "kv-batch-size"))]
     kv_batch_size: Option<u32>,
 
+    #[serde(rename(deserialize = "sasl-mechanism", serialize = "sasl-mechanism"))]
+    sasl_mechanism: Option<SaslMechanism>,
+
+    #[serde(rename(
+        deserialize = "kv-compression-threshold",
+        serialize = "kv-compression-threshold"
+    ))]
+    kv_compression_threshold: Option<u32>,
+
+    #[serde(rename(deserialize = "kv-max-retries", serialize = "kv-max-retries"))]
+    kv_max_retries: Option<u32>,
+
+    #[serde(default)]
+    #[serde(
+        rename(
+            deserialize = "kv-max-retry-backoff",
+            serialize = "kv-max-retry-backoff"
+        ),
+        with = "humantime_serde"
+    )]
+    kv_max_retry_backoff: Option<Duration>,
+
     #[serde(rename(
         deserialize = "capella-organization",
         serialize = "capella-organization"
@@ -480,6 +507,18 @@ impl ClusterConfig {
     pub fn kv_batch_size(&self) -> Option<u32> {
         self.kv_batch_size
     }
+    pub fn sasl_mechanism(&self) -> Option<SaslMechanism> {
+        self.sasl_mechanism
+    }
+    pub fn kv_compression_threshold(&self) -> Option<u32> {
+        self.kv_compression_threshold
+    }
+    pub fn kv_max_retries(&self) -> Option<u32> {
+        self.kv_max_retries
+    }
+    pub fn kv_max_retry_backoff(&self) -> Option<Duration> {
+        self.kv_max_retry_backoff
+    }
     pub fn display_name(&self) -> Option<String> {
         self.display_name.clone()
     }
@@ -561,6 +600,18 @@ impl From<(String, &RemoteCluster)> for ClusterConfig {
             Some(cluster.1.kv_batch_size())
         };
 
+        let retry_policy = cluster.1.kv_retry_policy();
+        let kv_max_retries = if retry_policy.max_retries() == DEFAULT_KV_MAX_RETRIES {
+            None
+        } else {
+            Some(retry_policy.max_retries())
+        };
+        let kv_max_retry_backoff = if retry_policy.max_backoff() == DEFAULT_KV_MAX_RETRY_BACKOFF {
+            None
+        } else {
+            Some(retry_policy.max_backoff())
+        };
+
         Self {
             identifier: cluster.0,
             conn_string: cluster.1.hostnames().join(","),
@@ -576,6 +627,10 @@ impl From<(String, &RemoteCluster)> for ClusterConfig {
             capella_org: cloud,
             project: cluster.1.project(),
             kv_batch_size,
+            sasl_mechanism: cluster.1.sasl_mechanism(),
+            kv_compression_threshold: cluster.1.kv_compression_threshold(),
+            kv_max_retries,
+            kv_max_retry_backoff,
             display_name: cluster.1.display_name(),
             // This is a config option for dev ony so we won't want to write to file
             cluster_type: None,
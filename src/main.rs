@@ -44,7 +44,10 @@ use nu_protocol::{
     PipelineData, PluginIdentity, RegisteredPlugin, Signals, Span, Value,
 };
 
-use crate::client::{RustTlsConfig, CLOUD_URL};
+use crate::client::{
+    RetryPolicy, RustTlsConfig, SaslMechanism, CLOUD_URL, DEFAULT_KV_MAX_RETRIES,
+    DEFAULT_KV_MAX_RETRY_BACKOFF,
+};
 use nu_path::canonicalize_with;
 use nu_plugin_engine::{GetPlugin, PluginDeclaration};
 use std::collections::HashMap;
@@ -352,9 +355,6 @@ fn remote_cluster_from_opts(opt: CliOptions, password: Option<String>) -> Remote
     };
 
     let tls_config = if opt.disable_tls {
-        warn!(
-                "Using PLAIN authentication for cluster default, credentials will sent in plaintext - configure tls to disable this warning"
-            );
         None
     } else {
         Some(RustTlsConfig::new(opt.tls_accept_all_certs, opt.tls_cert_path).unwrap())
@@ -377,6 +377,9 @@ fn remote_cluster_from_opts(opt: CliOptions, password: Option<String>) -> Remote
         None,
         DEFAULT_KV_BATCH_SIZE,
         cluster_type,
+        None,
+        None,
+        RetryPolicy::default(),
     )
 }
 
@@ -662,8 +665,15 @@ fn make_state(
                 v.project(),
                 kv_batch_size,
                 v.cluster_type().unwrap_or(cluster_type),
+                v.sasl_mechanism(),
+                v.kv_compression_threshold(),
+                RetryPolicy::new(
+                    v.kv_max_retries().unwrap_or(DEFAULT_KV_MAX_RETRIES),
+                    v.kv_max_retry_backoff()
+                        .unwrap_or(DEFAULT_KV_MAX_RETRY_BACKOFF),
+                ),
             );
-            if !v.tls().clone().enabled() {
+            if !v.tls().clone().enabled() && v.sasl_mechanism() == Some(SaslMechanism::Plain) {
                 warn!(
                     "Using PLAIN authentication for cluster {}, credentials will sent in plaintext - configure tls to disable this warning",
                     name.clone()
@@ -750,14 +760,17 @@ fn merge_couchbase_delta(context: &mut EngineState, state: Arc<Mutex<State>>) {
         working_set.add_decl(Box::new(CbEnvLLM::new(state.clone())));
         working_set.add_decl(Box::new(CBEnvManaged::new(state.clone())));
         working_set.add_decl(Box::new(CbEnvRegister::new(state.clone())));
+        working_set.add_decl(Box::new(CbEnvTraceKv));
         working_set.add_decl(Box::new(CbEnvUnregister::new(state.clone())));
         working_set.add_decl(Box::new(Clusters::new(state.clone())));
         working_set.add_decl(Box::new(ClustersCreate::new(state.clone())));
         working_set.add_decl(Box::new(ClustersDrop::new(state.clone())));
         working_set.add_decl(Box::new(ClustersGet::new(state.clone())));
         working_set.add_decl(Box::new(Collections::new(state.clone())));
+        working_set.add_decl(Box::new(CollectionsChanges::new(state.clone())));
         working_set.add_decl(Box::new(CollectionsCreate::new(state.clone())));
         working_set.add_decl(Box::new(CollectionsDrop::new(state.clone())));
+        working_set.add_decl(Box::new(CollectionsManifest::new(state.clone())));
         working_set.add_decl(Box::new(Columnar));
         working_set.add_decl(Box::new(ColumnarClusters::new(state.clone())));
         working_set.add_decl(Box::new(ColumnarClustersCreate::new(state.clone())));
@@ -767,13 +780,23 @@ fn merge_couchbase_delta(context: &mut EngineState, state: Arc<Mutex<State>>) {
         working_set.add_decl(Box::new(Credentials::new(state.clone())));
         working_set.add_decl(Box::new(CredentialsCreate::new(state.clone())));
         working_set.add_decl(Box::new(CredentialsDrop::new(state.clone())));
+        working_set.add_decl(Box::new(Data));
+        working_set.add_decl(Box::new(DataStats::new(state.clone())));
         working_set.add_decl(Box::new(Doc));
+        working_set.add_decl(Box::new(DocAppend::new(state.clone())));
+        working_set.add_decl(Box::new(DocDecrement::new(state.clone())));
         working_set.add_decl(Box::new(DocGet::new(state.clone())));
         working_set.add_decl(Box::new(DocImport::new(state.clone())));
+        working_set.add_decl(Box::new(DocIncrement::new(state.clone())));
         working_set.add_decl(Box::new(DocInsert::new(state.clone())));
+        working_set.add_decl(Box::new(DocPrepend::new(state.clone())));
         working_set.add_decl(Box::new(DocReplace::new(state.clone())));
         working_set.add_decl(Box::new(DocRemove::new(state.clone())));
+        working_set.add_decl(Box::new(DocScan::new(state.clone())));
+        working_set.add_decl(Box::new(DocTouch::new(state.clone())));
+        working_set.add_decl(Box::new(DocUnlock::new(state.clone())));
         working_set.add_decl(Box::new(DocUpsert::new(state.clone())));
+        working_set.add_decl(Box::new(DocWatch::new(state.clone())));
         working_set.add_decl(Box::new(HealthCheck::new(state.clone())));
         working_set.add_decl(Box::new(Help));
         working_set.add_decl(Box::new(FakeData::new(state.clone())));
@@ -791,7 +814,16 @@ fn merge_couchbase_delta(context: &mut EngineState, state: Arc<Mutex<State>>) {
         working_set.add_decl(Box::new(ScopesCreate::new(state.clone())));
         working_set.add_decl(Box::new(ScopesDrop::new(state.clone())));
         working_set.add_decl(Box::new(Search::new(state.clone())));
+        working_set.add_decl(Box::new(SubDoc));
+        working_set.add_decl(Box::new(SubDocArrayAddUnique::new(state.clone())));
+        working_set.add_decl(Box::new(SubDocArrayAppend::new(state.clone())));
+        working_set.add_decl(Box::new(SubDocArrayPrepend::new(state.clone())));
+        working_set.add_decl(Box::new(SubDocCounter::new(state.clone())));
         working_set.add_decl(Box::new(SubDocGet::new(state.clone())));
+        working_set.add_decl(Box::new(SubDocInsert::new(state.clone())));
+        working_set.add_decl(Box::new(SubDocRemove::new(state.clone())));
+        working_set.add_decl(Box::new(SubDocReplace::new(state.clone())));
+        working_set.add_decl(Box::new(SubDocUpsert::new(state.clone())));
         working_set.add_decl(Box::new(Transactions));
         working_set.add_decl(Box::new(TransactionsListAtrs::new(state.clone())));
         working_set.add_decl(Box::new(Tutorial::new(state.clone())));
@@ -1,12 +1,19 @@
-use crate::client::{Client, RustTlsConfig, CAPELLA_SRV_SUFFIX};
+use crate::client::{
+    Client, ClientError, KvClient, RetryPolicy, RustTlsConfig, SaslMechanism, CAPELLA_SRV_SUFFIX,
+};
 use crate::remote_cluster::RemoteClusterType::Provisioned;
 use crate::{
     DEFAULT_ANALYTICS_TIMEOUT, DEFAULT_DATA_TIMEOUT, DEFAULT_MANAGEMENT_TIMEOUT,
     DEFAULT_QUERY_TIMEOUT, DEFAULT_SEARCH_TIMEOUT, DEFAULT_TRANSACTION_TIMEOUT,
 };
+use log::debug;
+use nu_protocol::Signals;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::runtime::{Handle, Runtime};
+use tokio::time::Instant;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
 pub enum RemoteClusterType {
@@ -60,6 +67,8 @@ pub struct RemoteCluster {
     username: String,
     password: String,
     cluster: Mutex<Option<Arc<Client>>>,
+    kv_clients: Mutex<HashMap<String, Arc<KvClient>>>,
+    kv_runtime: Mutex<Option<KvRuntime>>,
     active_bucket: Mutex<Option<String>>,
     active_scope: Mutex<Option<String>>,
     active_collection: Mutex<Option<String>>,
@@ -70,6 +79,9 @@ pub struct RemoteCluster {
     kv_batch_size: u32,
     cluster_type: RemoteClusterType,
     display_name: Option<String>,
+    sasl_mechanism: Option<SaslMechanism>,
+    kv_compression_threshold: Option<u32>,
+    kv_retry_policy: RetryPolicy,
 }
 
 impl RemoteCluster {
@@ -82,9 +94,14 @@ impl RemoteCluster {
         project: Option<String>,
         kv_batch_size: u32,
         cluster_type: RemoteClusterType,
+        sasl_mechanism: Option<SaslMechanism>,
+        kv_compression_threshold: Option<u32>,
+        kv_retry_policy: RetryPolicy,
     ) -> Self {
         Self {
             cluster: Mutex::new(None),
+            kv_clients: Mutex::new(HashMap::new()),
+            kv_runtime: Mutex::new(None),
             hostnames: resources.hostnames,
             username: resources.username,
             password: resources.password,
@@ -98,6 +115,9 @@ impl RemoteCluster {
             kv_batch_size,
             cluster_type,
             display_name: resources.display_name,
+            sasl_mechanism,
+            kv_compression_threshold,
+            kv_retry_policy,
         }
     }
 
@@ -109,11 +129,65 @@ impl RemoteCluster {
                 self.username.clone(),
                 self.password.clone(),
                 self.tls_config.clone(),
+                self.sasl_mechanism,
             )));
         }
         c.as_ref().unwrap().clone()
     }
 
+    // key_value_client returns the cached kv client for the bucket, connecting a new one if there
+    // isn't one or the cached one is no longer healthy.
+    pub async fn key_value_client(
+        &self,
+        bucket: String,
+        deadline: Instant,
+        signals: Signals,
+    ) -> Result<Arc<KvClient>, ClientError> {
+        if let Some(client) = self.kv_clients.lock().unwrap().get(&bucket) {
+            if client.is_healthy() {
+                return Ok(client.clone());
+            }
+            debug!("Cached kv client for bucket {} is unhealthy", &bucket);
+        }
+
+        // The connections must be driven by a runtime which outlives the command that creates
+        // them, otherwise they would be closed when the command completes.
+        let cluster = self.cluster();
+        let b = bucket.clone();
+        let mut client = self
+            .kv_runtime_handle()
+            .spawn(async move { cluster.key_value_client(b, deadline, signals).await })
+            .await
+            .map_err(|e| ClientError::RequestFailed {
+                reason: Some(e.to_string()),
+                key: None,
+            })??;
+
+        client.set_compression_threshold(self.kv_compression_threshold);
+        client.set_retry_policy(self.kv_retry_policy);
+        let client = Arc::new(client);
+        self.kv_clients
+            .lock()
+            .unwrap()
+            .insert(bucket, client.clone());
+
+        Ok(client)
+    }
+
+    // invalidate_key_value_client drops the cached kv client for the bucket, if any, so that the
+    // next command creates a new one.
+    pub fn invalidate_key_value_client(&self, bucket: &str) {
+        self.kv_clients.lock().unwrap().remove(bucket);
+    }
+
+    fn kv_runtime_handle(&self) -> Handle {
+        let mut rt = self.kv_runtime.lock().unwrap();
+        if rt.is_none() {
+            *rt = Some(KvRuntime(Some(Runtime::new().unwrap())));
+        }
+        rt.as_ref().unwrap().0.as_ref().unwrap().handle().clone()
+    }
+
     pub fn active_bucket(&self) -> Option<String> {
         self.active_bucket.lock().unwrap().as_ref().cloned()
     }
@@ -146,6 +220,7 @@ impl RemoteCluster {
         if c.is_some() {
             *c = None;
         }
+        self.kv_clients.lock().unwrap().clear();
     }
 
     pub fn hostnames(&self) -> &Vec<String> {
@@ -197,6 +272,30 @@ impl RemoteCluster {
     pub fn display_name(&self) -> Option<String> {
         self.display_name.clone()
     }
+
+    pub fn sasl_mechanism(&self) -> Option<SaslMechanism> {
+        self.sasl_mechanism
+    }
+
+    pub fn kv_compression_threshold(&self) -> Option<u32> {
+        self.kv_compression_threshold
+    }
+
+    pub fn kv_retry_policy(&self) -> RetryPolicy {
+        self.kv_retry_policy
+    }
+}
+
+// KvRuntime drives the connections of cached kv clients. It is shut down in the background as it
+// may be dropped from within an async context.
+struct KvRuntime(Option<Runtime>);
+
+impl Drop for KvRuntime {
+    fn drop(&mut self) {
+        if let Some(rt) = self.0.take() {
+            rt.shutdown_background();
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
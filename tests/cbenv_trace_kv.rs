@@ -0,0 +1,19 @@
+mod common;
+
+use crate::common::playground::CBPlayground;
+use nu_test_support::pipeline;
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn trace_kv_redacts_values() {
+    CBPlayground::setup("trace_kv_redacts_values", None, None, |dirs, _sandbox| {
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"cb-env trace-kv --file kv.trace | ignore; doc upsert trace_kv_redacts_values {secret: hunter2} | ignore; cb-env trace-kv --off | ignore; open --raw kv.trace"#));
+
+        assert_eq!("", out.err);
+        assert!(out.out.contains("Set opaque="));
+        assert!(out.out.contains("value=<redacted"));
+        assert!(!out.out.contains("hunter2"));
+        // The trace writes bodies as hex.
+        assert!(!out.out.contains("68756e74657232"));
+    });
+}
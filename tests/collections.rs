@@ -39,3 +39,17 @@ fn collection_management() {
         assert_eq!("", out.err);
     });
 }
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn collections_manifest() {
+    CBPlayground::setup("collections_manifest", None, None, |dirs, sandbox| {
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"collections manifest --scope _default | where collection == "_default" | first | to json"#));
+        assert_eq!("", out.err);
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+        assert_eq!(json["scope_id"], 0);
+        assert_eq!(json["collection_id"], 0);
+        assert_ne!(json["manifest_uid"], "");
+    });
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use crate::common::playground::CBPlayground;
+use nu_test_support::pipeline;
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn default_stats_are_numeric() {
+    CBPlayground::setup("default_stats_are_numeric", None, None, |dirs, _sandbox| {
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"data stats | where stat == curr_items | first | get value | describe"#));
+
+        assert_eq!("", out.err);
+        assert_eq!("int", out.out);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn key_stats_for_a_document() {
+    CBPlayground::setup("key_stats_for_a_document", None, None, |dirs, sandbox| {
+        sandbox.create_document(
+            &dirs,
+            "key_stats_for_a_document",
+            r#"{"testkey": "testvalue"}"#,
+        );
+
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"data stats key --id key_stats_for_a_document | where stat == key_is_dirty | length"#));
+
+        assert_eq!("", out.err);
+        assert_eq!("1", out.out);
+    });
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use crate::common::{new_doc_id, playground::CBPlayground, support};
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn append_and_prepend_raw_values() {
+    CBPlayground::setup(
+        "append_and_prepend_raw_values",
+        None,
+        None,
+        |dirs, sandbox| {
+            let key = new_doc_id();
+            // The values keep the document valid JSON so that it can be read back with doc get.
+            sandbox.create_document(&dirs, &key, "12");
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc append {} 34 | first | to json", key)));
+            assert_eq!("", out.err);
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(1, json["success"]);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc prepend {} 9 | first | to json", key)));
+            assert_eq!("", out.err);
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(1, json["success"]);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc get {} | first | get content", key)));
+            assert_eq!("", out.err);
+            assert_eq!("91234", out.out);
+        },
+    );
+}
@@ -28,3 +28,86 @@ fn get_a_document_not_found() {
         assert!(out.out.contains("Key not found"));
     });
 }
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn get_a_document_any_replica() {
+    CBPlayground::setup("get_a_document_any_replica", None, None, |dirs, sandbox| {
+        sandbox.create_document(
+            &dirs,
+            "get_a_document_any_replica",
+            r#"{"testkey": "testvalue"}"#,
+        );
+
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"doc get "get_a_document_any_replica" --replica any | first | to json"#));
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+        assert_eq!("", out.err);
+        assert_eq!(r#"{"testkey":"testvalue"}"#, json["content"].to_string());
+        assert_ne!("", json["node"]);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn get_a_document_with_xattrs() {
+    CBPlayground::setup("get_a_document_with_xattrs", None, None, |dirs, sandbox| {
+        sandbox.create_document(
+            &dirs,
+            "get_a_document_with_xattrs",
+            r#"{"testkey": "testvalue"}"#,
+        );
+
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"doc get "get_a_document_with_xattrs" --xattrs | first | to json"#));
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+        assert_eq!("", out.err);
+        assert_eq!(r#"{"testkey":"testvalue"}"#, json["content"].to_string());
+        assert_eq!(json["cas"], json["xattrs"]["$document"]["CAS"]);
+        assert_eq!(24, json["xattrs"]["$document"]["value_bytes"]);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn get_and_lock_a_document() {
+    CBPlayground::setup("get_and_lock_a_document", None, None, |dirs, sandbox| {
+        sandbox.create_document(
+            &dirs,
+            "get_and_lock_a_document",
+            r#"{"testkey": "testvalue"}"#,
+        );
+
+        // Writes without the CAS of the lock fail until the document is unlocked.
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"let locked = (doc get get_and_lock_a_document --lock 10sec); doc upsert get_and_lock_a_document {testkey: other} | first | get failures | print; $locked | doc unlock | first | to json"#));
+        assert_eq!("", out.err);
+        assert!(out.out.contains("Document locked"));
+        assert!(out.out.contains(r#""success": 1"#));
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn get_a_document_with_timings() {
+    CBPlayground::setup(
+        "get_a_document_with_timings",
+        None,
+        None,
+        |dirs, sandbox| {
+            sandbox.create_document(
+                &dirs,
+                "get_a_document_with_timings",
+                r#"{"testkey": "testvalue"}"#,
+            );
+
+            let out = cbsh!(cwd: dirs.test(), pipeline(r#"doc get "get_a_document_with_timings" --with-timings | first | to json"#));
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+            assert_eq!("", out.err);
+            assert_eq!(r#"{"testkey":"testvalue"}"#, json["content"].to_string());
+            assert_ne!("", json["node"]);
+            assert!(json["vbucket"].is_number());
+            assert!(json["round_trip"].as_i64().unwrap() > 0);
+        },
+    );
+}
@@ -43,3 +43,153 @@ fn import_missing_id() {
         assert_eq!("Missing doc id", json["failures"]);
     });
 }
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn import_with_dead_letter_and_checkpoint() {
+    CBPlayground::setup(
+        "import_with_dead_letter_and_checkpoint",
+        None,
+        None,
+        |dirs, sandbox| {
+            let content = r#"[{id: dead1, foo: bar}, {foo: baz}]"#;
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("{} | save rows.json", content)));
+            assert_eq!("", out.err);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc import rows.json --checkpoint rows.checkpoint --dead-letter rows.dead | first | to json"));
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(1, json["success"]);
+            assert_eq!(1, json["failed"]);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("open rows.dead --raw | from json --objects | first | to json"));
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!("baz", json["foo"]);
+            assert_eq!("missing_id", json["_error"]["kind"]);
+            assert_eq!(1, json["_error"]["row"]);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("open rows.checkpoint | from json | get rows"));
+            assert_eq!("", out.err);
+            assert_eq!("2", out.out);
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn import_keeps_error_field() {
+    CBPlayground::setup("import_keeps_error_field", None, None, |dirs, sandbox| {
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#""{\"id\": \"error1\", \"_error\": \"disk full\"}\n" | save rows.ndjson"#));
+        assert_eq!("", out.err);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc import rows.ndjson | first | to json"));
+        assert_eq!("", out.err);
+
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(1, json["success"]);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc get error1 | first | get content._error"));
+        assert_eq!("", out.err);
+        assert_eq!("disk full", out.out);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn import_resumes_from_checkpoint() {
+    CBPlayground::setup(
+        "import_resumes_from_checkpoint",
+        None,
+        None,
+        |dirs, sandbox| {
+            let content =
+                r#"[{id: resume1, foo: bar}, {id: resume2, foo: baz}, {id: resume3, foo: qux}]"#;
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("{} | save rows.json", content)));
+            assert_eq!("", out.err);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"{file: rows.json, rows: 2} | to json | save rows.checkpoint"#));
+            assert_eq!("", out.err);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc import rows.json --checkpoint rows.checkpoint --resume | first | to json"));
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(1, json["processed"]);
+            assert_eq!(1, json["success"]);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("open rows.checkpoint | from json | get rows"));
+            assert_eq!("", out.err);
+            assert_eq!("3", out.out);
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn import_ndjson_with_format() {
+    CBPlayground::setup("import_ndjson_with_format", None, None, |dirs, sandbox| {
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#""{\"id\": \"ndjson1\", \"foo\": \"bar\"}\n\n{\"id\": \"ndjson2\", \"foo\": \"baz\"}\n" | save rows.txt"#));
+        assert_eq!("", out.err);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc import rows.txt --format ndjson | first | to json"));
+        assert_eq!("", out.err);
+
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(2, json["processed"]);
+        assert_eq!(2, json["success"]);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn import_csv_with_inferred_types() {
+    CBPlayground::setup(
+        "import_csv_with_inferred_types",
+        None,
+        None,
+        |dirs, sandbox| {
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#""id,age,zip\ncsv1,42,01234\n" | save rows.csv"#));
+            assert_eq!("", out.err);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc import rows.csv | first | to json"));
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(1, json["success"]);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc get csv1 | first | get content | to json"));
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(42, json["age"]);
+            assert_eq!("01234", json["zip"]);
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn import_with_id_template() {
+    CBPlayground::setup("import_with_id_template", None, None, |dirs, sandbox| {
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#""type,name\nairline,template1\nairline,\n" | save rows.csv"#));
+        assert_eq!("", out.err);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"doc import rows.csv --id-template "{type}::{name}" | first | to json"#));
+        assert_eq!("", out.err);
+
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(1, json["success"]);
+        assert_eq!(1, json["failed"]);
+        assert_eq!(
+            "Missing doc id, the row has no name field",
+            json["failures"]
+        );
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline("doc get airline::template1 | first | get content.name"));
+        assert_eq!("", out.err);
+        assert_eq!("template1", out.out);
+    });
+}
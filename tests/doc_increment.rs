@@ -0,0 +1,45 @@
+mod common;
+
+use crate::common::{new_doc_id, playground::CBPlayground, support};
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn increment_creates_and_increments_counter() {
+    CBPlayground::setup(
+        "increment_creates_and_increments_counter",
+        None,
+        None,
+        |dirs, sandbox| {
+            let key = new_doc_id();
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc increment {} --initial 10 | first | to json", key)));
+            assert_eq!("", out.err);
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(10, json["content"]);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc increment {} --delta 5 | first | to json", key)));
+            assert_eq!("", out.err);
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(15, json["content"]);
+            assert_eq!("", json["error"]);
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn increment_missing_counter_without_initial() {
+    CBPlayground::setup(
+        "increment_missing_counter_without_initial",
+        None,
+        None,
+        |dirs, sandbox| {
+            let key = new_doc_id();
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc increment {} | first | to json", key)));
+            assert_eq!("", out.err);
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert!(json["error"].to_string().contains("Key not found"));
+        },
+    );
+}
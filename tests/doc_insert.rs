@@ -55,3 +55,25 @@ fn insert_missing_id() {
         assert_eq!("Missing doc id", json["failures"]);
     });
 }
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn insert_twice_with_results() {
+    CBPlayground::setup("insert_twice_with_results", None, None, |dirs, sandbox| {
+        let key = new_doc_id();
+        sandbox.create_document(&dirs, key.clone(), r#"{"testkey": "testvalue"}"#);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!(r#"doc insert {} {{"test": "test"}} --with-results | first | to json"#, &key)));
+
+        assert_eq!("", out.err);
+
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+        assert_eq!(key, json["id"]);
+        assert_eq!("failed", json["status"]);
+        assert_eq!("key_already_exists", json["error_kind"]);
+        assert_eq!("Key already exists", json["error"]);
+        assert_eq!(0, json["cas"]);
+        assert!(json["mutation_token"].is_null());
+    });
+}
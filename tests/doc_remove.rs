@@ -42,3 +42,30 @@ fn error_on_remove_doc_not_found() {
         },
     );
 }
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn remove_documents_with_integer_ids() {
+    CBPlayground::setup(
+        "remove_documents_with_integer_ids",
+        None,
+        None,
+        |dirs, sandbox| {
+            let key = rand::random::<u32>().to_string();
+            sandbox.create_document(&dirs, &key, r#"{"testkey": "testvalue"}"#);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("[{{id: {}}}, {{name: noid}}] | doc remove | first | to json", &key)));
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+            assert_eq!(1, json["success"]);
+            assert_eq!(2, json["processed"]);
+            assert_eq!(1, json["failed"]);
+            assert_eq!(
+                "Missing doc id, the record has no id column",
+                json["failures"]
+            );
+        },
+    );
+}
@@ -68,3 +68,29 @@ fn replace_missing_doc_id() {
         assert_eq!("Missing doc id", json["failures"]);
     });
 }
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn replace_a_document_with_stale_cas() {
+    CBPlayground::setup(
+        "replace_a_document_with_stale_cas",
+        None,
+        None,
+        |dirs, sandbox| {
+            let key = new_doc_id();
+            sandbox.create_document(&dirs, &key, r#"{"foo": "bar"}"#);
+
+            // The first replace changes the CAS, so the second using the same read must fail.
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("let doc = (doc get {} | first); $doc | update content {{foo: fizz}} | doc replace | ignore; $doc | update content {{foo: buzz}} | doc replace | first | to json", key)));
+            assert_eq!("", out.err);
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(0, json["success"]);
+            assert_eq!(1, json["failed"]);
+            assert!(json["failures"].to_string().contains("CAS mismatch"));
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc get {} | first | to json", key)));
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!("{\"foo\":\"fizz\"}", json["content"].to_string());
+        },
+    );
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use crate::common::playground::CBPlayground;
+use nu_test_support::pipeline;
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn scan_documents_with_prefix() {
+    CBPlayground::setup("scan_documents_with_prefix", None, None, |dirs, sandbox| {
+        sandbox.create_document(&dirs, "scan_prefix_one", r#"{"testkey": "one"}"#);
+        sandbox.create_document(&dirs, "scan_prefix_two", r#"{"testkey": "two"}"#);
+
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"doc scan --prefix scan_prefix_ | sort-by id | get content.testkey | to json -r"#));
+
+        assert_eq!("", out.err);
+        assert_eq!(r#"["one","two"]"#, out.out);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn scan_document_ids() {
+    CBPlayground::setup("scan_document_ids", None, None, |dirs, sandbox| {
+        sandbox.create_document(&dirs, "scan_ids_one", r#"{"testkey": "one"}"#);
+
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"doc scan --prefix scan_ids_ --ids-only --concurrency 4 | first | to json"#));
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+        assert_eq!("", out.err);
+        assert_eq!("scan_ids_one", json["id"]);
+        assert!(json.get("content").is_none());
+    });
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use crate::common::playground::CBPlayground;
+use nu_test_support::pipeline;
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn touch_a_document() {
+    CBPlayground::setup("touch_a_document", None, None, |dirs, sandbox| {
+        sandbox.create_document(&dirs, "touch_a_document", r#"{"testkey": "testvalue"}"#);
+
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"doc touch touch_a_document --expiry 3600 | first | to json"#));
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+        assert_eq!("", out.err);
+        assert_eq!(1, json["success"]);
+
+        let out =
+            cbsh!(cwd: dirs.test(), pipeline(r#"doc get touch_a_document | first | to json"#));
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(r#"{"testkey":"testvalue"}"#, json["content"].to_string());
+    });
+}
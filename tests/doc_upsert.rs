@@ -16,6 +16,7 @@ fn upsert_a_document() {
         assert_eq!(1, json["processed"]);
         assert_eq!(0, json["failed"]);
         assert_eq!("", json["failures"]);
+        assert_eq!(0, json["retries"]);
     });
 }
 
@@ -34,3 +35,134 @@ fn upsert_missing_id() {
         assert_eq!("Missing doc id", json["failures"]);
     });
 }
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_invalid_durability() {
+    CBPlayground::setup("upsert_invalid_durability", None, None, |dirs, _sandbox| {
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"doc upsert test {"test": "test"} --durability all | first | to json"#));
+
+        assert!(out.err.contains("Failed to parse durability level all"));
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_a_string_document() {
+    CBPlayground::setup("upsert_a_string_document", None, None, |dirs, _sandbox| {
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"doc upsert string_doc hello --format string | ignore; doc get string_doc | first | get content"#));
+
+        assert_eq!("", out.err);
+        assert_eq!("hello", out.out);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_a_binary_document() {
+    CBPlayground::setup("upsert_a_binary_document", None, None, |dirs, _sandbox| {
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"0x[ff fe 00 01] | doc upsert binary_doc | ignore; doc get binary_doc | first | get content | describe"#));
+
+        assert_eq!("", out.err);
+        assert_eq!("binary", out.out);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_documents_with_timings() {
+    CBPlayground::setup(
+        "upsert_documents_with_timings",
+        None,
+        None,
+        |dirs, sandbox| {
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"[[id content]; [timings1 {a: 1}] [timings2 {a: 2}]] | doc upsert --with-timings | first | to json"#));
+
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+
+            assert_eq!(2, json["success"]);
+            assert!(json["round_trip_p50"].as_i64().unwrap() > 0);
+            assert!(
+                json["round_trip_max"].as_i64().unwrap()
+                    >= json["round_trip_p99"].as_i64().unwrap()
+            );
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_a_stream_of_documents() {
+    CBPlayground::setup(
+        "upsert_a_stream_of_documents",
+        None,
+        None,
+        |dirs, sandbox| {
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"1..500 | each {|i| {id: $"stream_($i)", content: {i: $i}}} | doc upsert --batch-size 7 | first | to json"#));
+
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(500, json["success"]);
+            assert_eq!(500, json["processed"]);
+            assert_eq!(0, json["failed"]);
+            assert!(json.get("interrupted").is_none());
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_documents_with_results() {
+    CBPlayground::setup(
+        "upsert_documents_with_results",
+        None,
+        None,
+        |dirs, sandbox| {
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"[[id content]; [results1 {a: 1}] ["" {a: 2}]] | doc upsert --with-results | sort-by status | to json"#));
+
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            let results = json.as_array().unwrap();
+            assert_eq!(2, results.len());
+
+            assert_eq!("", results[0]["id"]);
+            assert_eq!("failed", results[0]["status"]);
+            assert_eq!("missing_id", results[0]["error_kind"]);
+
+            assert_eq!("results1", results[1]["id"]);
+            assert_eq!("success", results[1]["status"]);
+            assert_eq!("", results[1]["error"]);
+            assert_ne!(0, results[1]["cas"]);
+            assert!(results[1]["mutation_token"]["seqno"].as_i64().unwrap() > 0);
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_documents_with_id_template() {
+    CBPlayground::setup(
+        "upsert_documents_with_id_template",
+        None,
+        None,
+        |dirs, sandbox| {
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(r#"[[content]; [{type: route, airline: AF, id: 1}] [{type: route, id: 2}]] | doc upsert --id-template "{type}::{airline}::{id}" --with-results | sort-by status | to json"#));
+
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            let results = json.as_array().unwrap();
+            assert_eq!(2, results.len());
+
+            assert_eq!("failed", results[0]["status"]);
+            assert_eq!("missing_id", results[0]["error_kind"]);
+
+            assert_eq!("route::AF::1", results[1]["id"]);
+            assert_eq!("success", results[1]["status"]);
+        },
+    );
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use crate::common::playground::CBPlayground;
+use nu_test_support::pipeline;
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn get_paths_when_one_is_missing() {
+    CBPlayground::setup(
+        "get_paths_when_one_is_missing",
+        None,
+        None,
+        |dirs, sandbox| {
+            sandbox.create_document(
+                &dirs,
+                "get_paths_when_one_is_missing",
+                r#"{"testkey": "testvalue"}"#,
+            );
+
+            let out = cbsh!(cwd: dirs.test(), pipeline(r#"subdoc get [testkey missing] "get_paths_when_one_is_missing" | first | to json"#));
+            assert_eq!("", out.err);
+
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!("testvalue", json["content"]["testkey"]);
+            assert_eq!(serde_json::Value::Null, json["content"]["missing"]);
+            assert_eq!("missing: field not found", json["error"]);
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn get_an_xattr_path() {
+    CBPlayground::setup("get_an_xattr_path", None, None, |dirs, sandbox| {
+        sandbox.create_document(&dirs, "get_an_xattr_path", r#"{"testkey": "testvalue"}"#);
+
+        let out = cbsh!(cwd: dirs.test(), pipeline(r#"subdoc get --xattr '$document' "get_an_xattr_path" | first | to json"#));
+        assert_eq!("", out.err);
+
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(json["cas"], json["content"]["CAS"]);
+        assert_eq!(24, json["content"]["value_bytes"]);
+    });
+}
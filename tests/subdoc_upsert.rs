@@ -0,0 +1,87 @@
+mod common;
+
+use crate::common::{new_doc_id, playground::CBPlayground, support};
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn upsert_a_path() {
+    CBPlayground::setup("subdoc_upsert_a_path", None, None, |dirs, sandbox| {
+        let key = new_doc_id();
+        sandbox.create_document(&dirs, &key, r#"{"foo": "bar"}"#);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("subdoc upsert fizz buzz {} | first | to json", key)));
+        assert_eq!("", out.err);
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(1, json["success"]);
+        assert_eq!(1, json["processed"]);
+        assert_eq!(0, json["failed"]);
+        assert_eq!("", json["failures"]);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("doc get {} | first | to json", key)));
+        assert_eq!("", out.err);
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(
+            "{\"foo\":\"bar\",\"fizz\":\"buzz\"}",
+            json["content"].to_string()
+        );
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn insert_existing_path() {
+    CBPlayground::setup(
+        "subdoc_insert_existing_path",
+        None,
+        None,
+        |dirs, sandbox| {
+            let key = new_doc_id();
+            sandbox.create_document(&dirs, &key, r#"{"foo": "bar"}"#);
+
+            let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("subdoc insert foo buzz {} | first | to json", key)));
+            assert_eq!("", out.err);
+            let json = sandbox.parse_out_to_json(out.out).unwrap();
+            assert_eq!(0, json["success"]);
+            assert_eq!(1, json["processed"]);
+            assert_eq!(1, json["failed"]);
+            assert_eq!("Path already exists", json["failures"]);
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn counter_a_path() {
+    CBPlayground::setup("subdoc_counter_a_path", None, None, |dirs, sandbox| {
+        let key = new_doc_id();
+        sandbox.create_document(&dirs, &key, r#"{"visits": 1}"#);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("subdoc counter visits 5 {} | first | to json", key)));
+        assert_eq!("", out.err);
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(1, json["success"]);
+        assert_eq!(0, json["failed"]);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("subdoc get visits {} | first | to json", key)));
+        assert_eq!("", out.err);
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(6, json["content"]);
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "key_value"), ignore)]
+fn remove_missing_path() {
+    CBPlayground::setup("subdoc_remove_missing_path", None, None, |dirs, sandbox| {
+        let key = new_doc_id();
+        sandbox.create_document(&dirs, &key, r#"{"foo": "bar"}"#);
+
+        let out = cbsh!(cwd: dirs.test(), support::cb_pipeline(format!("subdoc remove fizz {} | first | to json", key)));
+        assert_eq!("", out.err);
+        let json = sandbox.parse_out_to_json(out.out).unwrap();
+        assert_eq!(0, json["success"]);
+        assert_eq!(1, json["processed"]);
+        assert_eq!(1, json["failed"]);
+        assert_eq!("Path not found", json["failures"]);
+    });
+}
@@ -160,7 +160,7 @@ mod tests {
         let mut res = Vec::new();
 
         parser.push(json);
-        while let Some(next) = parser.next().unwrap() {
+        while let Some(next) = parser.parse_next().unwrap() {
             res.push(String::from_utf8(next).unwrap());
         }
 
@@ -189,7 +189,7 @@ mod tests {
         let mut res = Vec::new();
 
         parser.push(json);
-        while let Some(next) = parser.next().unwrap() {
+        while let Some(next) = parser.parse_next().unwrap() {
             res.push(String::from_utf8(next).unwrap());
         }
 
@@ -215,7 +215,7 @@ mod tests {
         let mut res = Vec::new();
 
         parser.push(json);
-        while let Some(next) = parser.next().unwrap() {
+        while let Some(next) = parser.parse_next().unwrap() {
             res.push(String::from_utf8(next).unwrap());
         }
 
@@ -241,7 +241,7 @@ mod tests {
         let mut res = Vec::new();
 
         parser.push(json);
-        while let Some(next) = parser.next().unwrap() {
+        while let Some(next) = parser.parse_next().unwrap() {
             res.push(String::from_utf8(next).unwrap());
         }
 
@@ -271,7 +271,7 @@ mod tests {
         let mut res = Vec::new();
 
         parser.push(json);
-        while let Some(next) = parser.next().unwrap() {
+        while let Some(next) = parser.parse_next().unwrap() {
             res.push(String::from_utf8(next).unwrap());
         }
 
@@ -303,7 +303,7 @@ mod tests {
         let mut res = Vec::new();
 
         parser.push(json);
-        while let Some(next) = parser.next().unwrap() {
+        while let Some(next) = parser.parse_next().unwrap() {
             res.push(String::from_utf8(next).unwrap());
         }
 